@@ -6,6 +6,26 @@ use std::{lazy::SyncLazy, path::PathBuf};
 pub struct Config {
     pub log_dir: PathBuf,
     pub dump_constraint: bool,
+    /// Check that functions never panic. Runtime assertions must be proven to hold and calls to
+    /// library functions that may panic, e.g., `Option::unwrap`, must be proven unreachable.
+    pub check_panics: bool,
+    /// Directory with `.lrspec` files giving specs to functions from other crates.
+    pub spec_dir: Option<PathBuf>,
+    /// Infer the signatures of private functions without a spec instead of checking them against
+    /// their default signature. All functions are then checked in a single fixpoint query.
+    pub infer_private_sigs: bool,
+    /// Report the type environments inferred at join points, e.g., loop headers, as notes.
+    pub show_invariants: bool,
+    /// The fixpoint executable.
+    pub fixpoint: PathBuf,
+    /// Extra arguments passed to fixpoint, separated by whitespace.
+    pub fixpoint_args: String,
+    /// Seconds after which a fixpoint query, or a z3 query for constraints without kvars, is killed.
+    pub fixpoint_timeout: Option<u64>,
+    /// Check constraints without kvars with z3 directly instead of going through fixpoint.
+    pub direct_smt: bool,
+    /// The z3 executable, used for constraints without kvars and to find counterexamples.
+    pub z3: PathBuf,
 }
 
 pub static CONFIG: SyncLazy<Config> = SyncLazy::new(|| {
@@ -13,6 +33,13 @@ pub static CONFIG: SyncLazy<Config> = SyncLazy::new(|| {
     // 1. Set defaults
     config.set_default("log_dir", "./log/").unwrap();
     config.set_default("dump_constraint", false).unwrap();
+    config.set_default("check_panics", false).unwrap();
+    config.set_default("infer_private_sigs", false).unwrap();
+    config.set_default("show_invariants", false).unwrap();
+    config.set_default("fixpoint", "fixpoint").unwrap();
+    config.set_default("fixpoint_args", "").unwrap();
+    config.set_default("direct_smt", true).unwrap();
+    config.set_default("z3", "z3").unwrap();
 
     // 2. Merge with env variables (prefixed with LR_)
     config
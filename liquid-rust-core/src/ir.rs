@@ -2,10 +2,10 @@ use std::fmt;
 
 use itertools::Itertools;
 use liquid_rust_common::index::{Idx, IndexVec};
-use rustc_data_structures::graph::dominators::Dominators;
+use rustc_data_structures::{fx::FxHashMap, graph::dominators::Dominators};
 use rustc_hir::def_id::DefId;
 pub use rustc_middle::mir::{
-    BasicBlock, Local, SourceInfo, SwitchTargets, UnOp, RETURN_PLACE, START_BLOCK,
+    BasicBlock, Field, Local, SourceInfo, SwitchTargets, UnOp, RETURN_PLACE, START_BLOCK,
 };
 use rustc_middle::{
     mir,
@@ -20,6 +20,32 @@ pub struct Body<'tcx> {
     pub arg_count: usize,
     pub nlocals: usize,
     pub mir: &'tcx mir::Body<'tcx>,
+    /// The closures created in the body.
+    pub closures: FxHashMap<DefId, Closure<'tcx>>,
+    /// The types of the fields of the structs constructed or unfolded in the body, in terms of the
+    /// generic parameters of each struct.
+    pub adt_fields: FxHashMap<DefId, Vec<Ty>>,
+}
+
+/// A closure together with a template of its signature. The refinements in the template are
+/// inferred every time the closure is called.
+#[derive(Debug)]
+pub struct Closure<'tcx> {
+    pub kind: ClosureKind,
+    pub args: Vec<Ty>,
+    pub ret: Ty,
+    pub body: Body<'tcx>,
+}
+
+/// The trait a closure implements. Closures mutating their captured variables (`FnMut`) are not
+/// supported: we copy the captured variables into the environment of the closure, so their
+/// updates would not be seen by the caller.
+#[derive(Clone, Copy, Debug)]
+pub enum ClosureKind {
+    /// The closure only reads its captured variables, which it accesses through a reference.
+    Fn,
+    /// The closure consumes its captured variables, so it can only be called once.
+    FnOnce,
 }
 
 #[derive(Debug)]
@@ -40,7 +66,45 @@ pub enum TerminatorKind {
         func: DefId,
         substs: Vec<Ty>,
         args: Vec<Operand>,
-        destination: Option<(Place, BasicBlock)>,
+        destination: (Place, BasicBlock),
+        /// Whether the callee panics when its preconditions don't hold, e.g., an implementation
+        /// of `Index` called with an index out of bounds. Violations of its preconditions are
+        /// then reported as panics.
+        panics: bool,
+    },
+    /// A call to a closure through one of the `Fn*` traits. `upvars` is the closure itself or a
+    /// reference to it.
+    CallClosure {
+        closure: DefId,
+        upvars: Operand,
+        args: Vec<Operand>,
+        destination: (Place, BasicBlock),
+    },
+    /// A call through a function pointer or through one of the `Fn*` traits on a value that
+    /// isn't a closure, e.g., a generic parameter. `callee` (or the value it references) must
+    /// have a function type.
+    CallIndirect {
+        callee: Operand,
+        args: Vec<Operand>,
+        destination: (Place, BasicBlock),
+    },
+    /// A call that must be unreachable. This is either a call to a function that never returns,
+    /// e.g., `panic!`, or, when checking for panics, a call to a library function that may panic,
+    /// e.g., `Option::unwrap`. The arguments are only lowered when the callee has a signature to
+    /// check them against, otherwise `substs` and `args` are empty.
+    Diverge {
+        func: DefId,
+        substs: Vec<Ty>,
+        args: Vec<Operand>,
+    },
+    /// `Unreachable`, `Abort` and `Resume` in MIR.
+    Unreachable,
+    /// A runtime check, e.g., for division by zero. When checking for panics `cond` must be
+    /// proven to be equal to `expected`, otherwise it is assumed to be.
+    Assert {
+        cond: Operand,
+        expected: bool,
+        target: BasicBlock,
     },
     SwitchInt {
         discr: Operand,
@@ -53,10 +117,17 @@ pub enum TerminatorKind {
         place: Place,
         target: BasicBlock,
     },
+    /// Drops the place and then assigns `value` to it.
+    DropAndReplace {
+        place: Place,
+        value: Operand,
+        target: BasicBlock,
+    },
 }
 
 pub struct Statement {
     pub kind: StatementKind,
+    pub source_info: SourceInfo,
 }
 
 #[derive(Debug)]
@@ -68,8 +139,29 @@ pub enum StatementKind {
 pub enum Rvalue {
     Use(Operand),
     MutRef(Place),
+    ShrRef(Place),
     BinaryOp(BinOp, Operand, Operand),
+    /// A binary operation returning a pair of the result and whether it overflowed.
+    CheckedBinaryOp(BinOp, Operand, Operand),
     UnaryOp(UnOp, Operand),
+    Aggregate(AggregateKind, Vec<Operand>),
+    /// The length of the array or slice at the place.
+    Len(Place),
+    /// An array `[op; len]`. The type is the type of the elements in the array.
+    Repeat(Operand, Ty, u64),
+    /// A function instantiated with the given type arguments, coerced to a function pointer of
+    /// the given type.
+    FnPointer(DefId, Vec<Ty>, Ty),
+    /// A closure without captured variables coerced to a function pointer.
+    ClosureFnPointer(DefId, Operand),
+}
+
+#[derive(Debug)]
+pub enum AggregateKind {
+    Tuple,
+    Adt(DefId, Vec<Ty>),
+    /// An array with elements of the given type.
+    Array(Ty),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -99,6 +191,18 @@ pub struct Place {
 #[derive(Debug)]
 pub enum PlaceElem {
     Deref,
+    /// A projection into the field of a tuple or struct. Besides the field we keep the kind of
+    /// the projected aggregate and the types of all its fields, which are needed to unfold it. The
+    /// types of the fields of a struct are in terms of its generic parameters.
+    Field(Field, AggregateKind, Vec<Ty>),
+    /// Indexing into an array or slice with the value stored in a local.
+    Index(Local),
+    /// Indexing into an array or slice with a constant offset, counting from the end if
+    /// `from_end` is true.
+    ConstantIndex {
+        offset: u64,
+        from_end: bool,
+    },
 }
 
 pub enum Constant {
@@ -163,26 +267,67 @@ impl fmt::Debug for Terminator {
                 substs: ty_subst,
                 args,
                 destination,
+                panics,
+            } => {
+                let (place, target) = destination;
+                write!(
+                    f,
+                    "{:?} = {}call {:?}<{:?}>({:?}) -> {:?}",
+                    place,
+                    if *panics { "panicking " } else { "" },
+                    func,
+                    ty_subst.iter().format(", "),
+                    args.iter().format(", "),
+                    target
+                )
+            }
+            TerminatorKind::CallClosure {
+                closure,
+                upvars,
+                args,
+                destination,
             } => {
-                if let Some((place, target)) = destination {
-                    write!(
-                        f,
-                        "{:?} = call {:?}({:?}) -> {:?}",
-                        place,
-                        func,
-                        args.iter().format(", "),
-                        target
-                    )
-                } else {
-                    write!(
-                        f,
-                        "call {:?}<{:?}>({:?})",
-                        func,
-                        ty_subst.iter().format(", "),
-                        args.iter().format(", ")
-                    )
-                }
+                let (place, target) = destination;
+                write!(
+                    f,
+                    "{:?} = call {:?}[{:?}]({:?}) -> {:?}",
+                    place,
+                    closure,
+                    upvars,
+                    args.iter().format(", "),
+                    target
+                )
             }
+            TerminatorKind::CallIndirect {
+                callee,
+                args,
+                destination,
+            } => {
+                let (place, target) = destination;
+                write!(
+                    f,
+                    "{:?} = call ({:?})({:?}) -> {:?}",
+                    place,
+                    callee,
+                    args.iter().format(", "),
+                    target
+                )
+            }
+            TerminatorKind::Diverge { func, substs, args } => {
+                write!(
+                    f,
+                    "call {:?}<{:?}>({:?}) -> !",
+                    func,
+                    substs.iter().format(", "),
+                    args.iter().format(", ")
+                )
+            }
+            TerminatorKind::Unreachable => write!(f, "unreachable"),
+            TerminatorKind::Assert {
+                cond,
+                expected,
+                target,
+            } => write!(f, "assert({:?} == {:?}) -> {:?}", cond, expected, target),
             TerminatorKind::SwitchInt { discr, .. } => {
                 write!(f, "switchInt({:?}) -> [todo]", discr,)
             }
@@ -192,18 +337,36 @@ impl fmt::Debug for Terminator {
             TerminatorKind::Drop { place, target } => {
                 write!(f, "drop({:?}) -> {:?}", place, target)
             }
+            TerminatorKind::DropAndReplace {
+                place,
+                value,
+                target,
+            } => {
+                write!(f, "replace({:?} <- {:?}) -> {:?}", place, value, target)
+            }
         }
     }
 }
 
 impl fmt::Debug for Place {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut p = format!("{:?}", self.local);
         for elem in &self.projection {
             match elem {
-                PlaceElem::Deref => write!(f, "*")?,
+                PlaceElem::Deref => p = format!("*{}", p),
+                PlaceElem::Field(field, ..) => p = format!("({}).{}", p, field.as_u32()),
+                PlaceElem::Index(idx) => p = format!("{}[{:?}]", p, idx),
+                PlaceElem::ConstantIndex {
+                    offset,
+                    from_end: false,
+                } => p = format!("{}[{:?}]", p, offset),
+                PlaceElem::ConstantIndex {
+                    offset,
+                    from_end: true,
+                } => p = format!("{}[-{:?}]", p, offset),
             }
         }
-        write!(f, "{:?}", self.local)
+        write!(f, "{}", p)
     }
 }
 
@@ -211,9 +374,28 @@ impl fmt::Debug for Rvalue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Use(op) => write!(f, "{:?}", op),
-            Self::MutRef(local) => write!(f, "&mut {:?}", local),
+            Self::MutRef(place) => write!(f, "&mut {:?}", place),
+            Self::ShrRef(place) => write!(f, "&{:?}", place),
             Self::BinaryOp(bin_op, op1, op2) => write!(f, "{:?}({:?}, {:?})", bin_op, op1, op2),
+            Self::CheckedBinaryOp(bin_op, op1, op2) => {
+                write!(f, "Checked{:?}({:?}, {:?})", bin_op, op1, op2)
+            }
             Self::UnaryOp(un_up, op) => write!(f, "{:?}({:?})", un_up, op),
+            Self::Aggregate(AggregateKind::Tuple, args) => {
+                write!(f, "({:?})", args.iter().format(", "))
+            }
+            Self::Aggregate(AggregateKind::Adt(def_id, _), args) => {
+                write!(f, "{:?} {{ {:?} }}", def_id, args.iter().format(", "))
+            }
+            Self::Aggregate(AggregateKind::Array(_), args) => {
+                write!(f, "[{:?}]", args.iter().format(", "))
+            }
+            Self::Len(place) => write!(f, "Len({:?})", place),
+            Self::Repeat(op, _, len) => write!(f, "[{:?}; {}]", op, len),
+            Self::FnPointer(def_id, substs, _) => {
+                write!(f, "{:?}<{:?}> as fn", def_id, substs.iter().format(", "))
+            }
+            Self::ClosureFnPointer(_, op) => write!(f, "{:?} as fn", op),
         }
     }
 }
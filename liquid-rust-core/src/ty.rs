@@ -1,5 +1,5 @@
 use liquid_rust_common::index::newtype_index;
-pub use liquid_rust_fixpoint::Sort;
+pub use liquid_rust_fixpoint::{KVid, Sort};
 pub use liquid_rust_syntax::ast::BinOp;
 use rustc_hir::def_id::DefId;
 pub use rustc_middle::ty::{IntTy, ParamTy, UintTy};
@@ -14,46 +14,67 @@ pub struct FnSig {
     pub ensures: Vec<(Name, Ty)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Ty {
     Refine(BaseTy, Expr),
     Exists(BaseTy, Pred),
+    Tuple(Vec<Ty>),
+    /// A shared reference.
+    Ref(Box<Ty>),
     MutRef(Name),
     Param(ParamTy),
+    Never,
+    /// A function type `fn(T1, ..., Tn) -> R`, which is the type of function pointers and of
+    /// values implementing one of the `Fn*` traits.
+    Fn(Vec<Ty>, Box<Ty>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Pred {
-    Infer,
+    /// A predicate to be inferred. The span is the one of the `_` written by the user, if any.
+    Infer(Option<Span>),
+    /// A kvar applied to the bound variable and the given expressions. Signatures inferred by
+    /// liquid-rust use kvars shared by the definition of a function and all its calls.
+    KVar(KVid, Vec<Expr>),
     Expr(Expr),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BaseTy {
     Int(IntTy),
     Uint(UintTy),
     Bool,
     Adt(DefId, Vec<Ty>),
+    /// An array `[T; N]` refined by its length.
+    Array(Box<Ty>),
+    /// A slice `[T]` refined by its length.
+    Slice(Box<Ty>),
+    /// A type parameter, refined by an integer index. The refinement is transferred to the type
+    /// the parameter is instantiated with.
+    Param(ParamTy),
 }
 
 #[derive(Debug)]
 pub struct Param {
     pub name: Ident,
     pub sort: Sort,
-    pub pred: Expr,
+    /// A predicate on the parameter, which is referred to by the bound variable in kvars.
+    pub pred: Pred,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Expr {
     pub kind: ExprKind,
     pub span: Option<Span>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ExprKind {
     Var(Var, Symbol, Span),
     Literal(Lit),
     BinaryOp(BinOp, Box<Expr>, Box<Expr>),
+    /// The application of an abstract refinement, i.e., a parameter of sort [`Sort::Pred`].
+    App(Var, Symbol, Box<[Expr]>),
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
@@ -80,13 +101,60 @@ newtype_index! {
     }
 }
 
+impl FnSig {
+    /// The spans of the holes `_` written by the user in the signature.
+    pub fn holes(&self) -> Vec<Span> {
+        let mut spans = vec![];
+        let tys = self
+            .requires
+            .iter()
+            .chain(&self.ensures)
+            .map(|(_, ty)| ty)
+            .chain(&self.args)
+            .chain([&self.ret]);
+        for ty in tys {
+            ty.collect_holes(&mut spans);
+        }
+        spans
+    }
+}
+
+impl Ty {
+    fn collect_holes(&self, spans: &mut Vec<Span>) {
+        match self {
+            Ty::Refine(bty, _) => bty.collect_holes(spans),
+            Ty::Exists(bty, pred) => {
+                bty.collect_holes(spans);
+                if let Pred::Infer(Some(span)) = pred {
+                    spans.push(*span);
+                }
+            }
+            Ty::Tuple(tys) => tys.iter().for_each(|ty| ty.collect_holes(spans)),
+            Ty::Ref(ty) => ty.collect_holes(spans),
+            Ty::Fn(args, ret) => {
+                args.iter().for_each(|ty| ty.collect_holes(spans));
+                ret.collect_holes(spans);
+            }
+            Ty::MutRef(_) | Ty::Param(_) | Ty::Never => {}
+        }
+    }
+}
+
 impl BaseTy {
+    fn collect_holes(&self, spans: &mut Vec<Span>) {
+        match self {
+            BaseTy::Adt(_, substs) => substs.iter().for_each(|ty| ty.collect_holes(spans)),
+            BaseTy::Array(ty) | BaseTy::Slice(ty) => ty.collect_holes(spans),
+            BaseTy::Int(_) | BaseTy::Uint(_) | BaseTy::Bool | BaseTy::Param(_) => {}
+        }
+    }
+
     pub fn sort(&self) -> Sort {
         match self {
             BaseTy::Int(_) => Sort::Int,
             BaseTy::Uint(_) => Sort::Int,
             BaseTy::Bool => Sort::Bool,
-            BaseTy::Adt(_, _) => Sort::Int,
+            BaseTy::Adt(_, _) | BaseTy::Array(_) | BaseTy::Slice(_) | BaseTy::Param(_) => Sort::Int,
         }
     }
 
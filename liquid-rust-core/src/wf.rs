@@ -21,7 +21,7 @@ impl Wf<'_> {
             .iter()
             .map(|param| {
                 env.insert(Var::Free(param.name.name), param.sort);
-                self.check_expr(&env, &param.pred, Sort::Bool)
+                self.check_pred(&env, &param.pred, Sort::Bool)
             })
             .try_collect_exhaust();
 
@@ -47,6 +47,23 @@ impl Wf<'_> {
         Ok(())
     }
 
+    /// Reports the holes `_` in a signature that is trusted or that of a trait method, as they
+    /// can only be inferred from the body of the function.
+    pub fn check_no_holes(&self, fn_sig: &FnSig) -> Result<(), ErrorReported> {
+        let holes = fn_sig.holes();
+        for span in &holes {
+            self.sess.span_err(
+                *span,
+                "`_` is not allowed in trusted signatures or signatures of trait methods",
+            );
+        }
+        if holes.is_empty() {
+            Ok(())
+        } else {
+            Err(ErrorReported)
+        }
+    }
+
     fn check_type(&self, env: &mut Env, ty: &Ty) -> Result<(), ErrorReported> {
         match ty {
             Ty::Refine(bty, e) => self.check_expr(env, e, bty.sort()),
@@ -54,20 +71,63 @@ impl Wf<'_> {
                 env.insert(Var::Bound, bty.sort());
                 self.check_pred(env, pred, Sort::Bool)
             }
+            Ty::Tuple(tys) => tys
+                .iter()
+                .try_for_each_exhaust(|ty| self.check_type(env, ty)),
+            Ty::Ref(ty) => self.check_type(env, ty),
+            Ty::Fn(args, ret) => {
+                let args = args
+                    .iter()
+                    .try_for_each_exhaust(|ty| self.check_type(env, ty));
+                let ret = self.check_type(env, ret);
+                args?;
+                ret
+            }
             Ty::MutRef(_) => {
                 // TODO: check identifier is actually a region
                 Ok(())
             }
-            Ty::Param(_) => Ok(()),
+            Ty::Param(_) | Ty::Never => Ok(()),
         }
     }
 
     fn check_pred(&self, env: &Env, pred: &Pred, sort: Sort) -> Result<(), ErrorReported> {
         match pred {
-            Pred::Infer => todo!(
-                "we should report this as an error as inference should not be allowed in the syntax"
-            ),
-            Pred::Expr(e) => self.check_expr(env, e, sort),
+            Pred::Infer(_) => Ok(()),
+            Pred::KVar(..) => unreachable!("kvars are never written by users"),
+            Pred::Expr(e) => self.check_conjuncts(env, e, sort),
+        }
+    }
+
+    /// Checks a predicate, whose conjuncts at the top level may be applications of abstract
+    /// refinements.
+    fn check_conjuncts(&self, env: &Env, e: &Expr, sort: Sort) -> Result<(), ErrorReported> {
+        match &e.kind {
+            ExprKind::BinaryOp(BinOp::And, e1, e2) => {
+                let r1 = self.check_conjuncts(env, e1, sort);
+                let r2 = self.check_conjuncts(env, e2, sort);
+                r1?;
+                r2
+            }
+            ExprKind::App(func, symbol, args) => {
+                let (span, symbol) = (e.span.unwrap(), *symbol);
+                if env[func] != Sort::Pred {
+                    self.sess
+                        .span_err(span, &format!("`{}` is not an abstract refinement", symbol));
+                    return Err(ErrorReported);
+                }
+                match &args[..] {
+                    [arg] => self.check_expr(env, arg, Sort::Int),
+                    _ => {
+                        self.sess.span_err(
+                            span,
+                            &format!("`{}` must be applied to a single argument", symbol),
+                        );
+                        Err(ErrorReported)
+                    }
+                }
+            }
+            _ => self.check_expr(env, e, sort),
         }
     }
 
@@ -82,9 +142,25 @@ impl Wf<'_> {
 
     fn synth_expr(&self, env: &Env, e: &Expr) -> Result<Sort, ErrorReported> {
         match &e.kind {
-            ExprKind::Var(var, ..) => Ok(env[var]),
+            ExprKind::Var(var, symbol, span) => {
+                if env[var] == Sort::Pred {
+                    self.sess.span_err(
+                        *span,
+                        &format!("abstract refinement `{}` must be applied to an argument", symbol),
+                    );
+                    return Err(ErrorReported);
+                }
+                Ok(env[var])
+            }
             ExprKind::Literal(lit) => Ok(lit.sort()),
             ExprKind::BinaryOp(op, e1, e2) => self.synth_binary_op(env, *op, e1, e2),
+            ExprKind::App(..) => {
+                self.sess.span_err(
+                    e.span.unwrap(),
+                    "abstract refinements can only be applied at the top level of a predicate",
+                );
+                Err(ErrorReported)
+            }
         }
     }
 
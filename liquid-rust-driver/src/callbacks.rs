@@ -1,16 +1,30 @@
-use liquid_rust_common::{errors::ErrorReported, iter::IterExt};
-use liquid_rust_core::wf::Wf;
+use liquid_rust_common::{config::CONFIG, errors::ErrorReported, iter::IterExt};
+use liquid_rust_core::{
+    ir::{Body, Rvalue, StatementKind, Terminator, TerminatorKind},
+    wf::Wf,
+};
 use liquid_rust_typeck::{
     self as typeck,
     global_env::{FnSpec, GlobalEnv},
 };
 use rustc_driver::{Callbacks, Compilation};
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
+use rustc_hir::{
+    def::DefKind,
+    def_id::{DefId, LocalDefId},
+};
 use rustc_interface::{interface::Compiler, Queries};
 use rustc_middle::ty::TyCtxt;
 use rustc_session::Session;
+use rustc_span::Span;
 
-use crate::{collector::SpecCollector, lowering::LoweringCtxt, resolve::Resolver};
+use crate::{
+    collector::SpecCollector,
+    lowering::{LoweringCtxt, UnsupportedErr},
+    metadata,
+    resolve::Resolver,
+    spec_files,
+};
 
 /// Compiler callbacks for Liquid Rust.
 #[derive(Default)]
@@ -23,22 +37,41 @@ impl Callbacks for LiquidCallbacks {
         queries: &'tcx Queries<'tcx>,
     ) -> Compilation {
         queries.global_ctxt().unwrap().peek_mut().enter(|tcx| {
-            let _ = check_crate(tcx, compiler.session());
-        });
-
-        Compilation::Stop
+            // When an output directory is given we are building a dependency of a crate that
+            // may be checked later, so we also export our signatures and produce the library.
+            match check_crate(tcx, compiler.session(), compiler.output_dir().is_some()) {
+                Ok(true) => Compilation::Continue,
+                _ => Compilation::Stop,
+            }
+        })
     }
 }
 
-fn check_crate(tcx: TyCtxt, sess: &Session) -> Result<(), ErrorReported> {
-    let annotations = SpecCollector::collect(tcx, sess)?;
+/// Checks the crate and exports its signatures if `export` is true. Returns whether the
+/// signatures were exported.
+fn check_crate(tcx: TyCtxt, sess: &Session, export: bool) -> Result<bool, ErrorReported> {
+    let specs = SpecCollector::collect(tcx, sess)?;
+    // Signatures exported by a dependency were checked against its code, so they take precedence
+    // over the ones in spec files.
+    let mut extern_fn_sigs = spec_files::load(tcx, sess)?;
+    extern_fn_sigs.extend(metadata::import(tcx).map_err(|err| {
+        sess.err(&format!(
+            "failed to load the signatures of a dependency: {}",
+            err
+        ));
+        ErrorReported
+    })?);
 
     let wf = Wf::new(sess);
-    let fn_sigs: FxHashMap<_, _> = annotations
+    let mut fn_sigs: FxHashMap<_, _> = specs
+        .fns
         .into_iter()
         .map(|(def_id, spec)| {
             let fn_sig = Resolver::resolve(tcx, def_id, spec.fn_sig)?;
             wf.check_fn_sig(&fn_sig)?;
+            if spec.assume || is_trait_method(tcx, def_id) {
+                wf.check_no_holes(&fn_sig)?;
+            }
             Ok((
                 def_id,
                 FnSpec {
@@ -49,16 +82,299 @@ fn check_crate(tcx: TyCtxt, sess: &Session) -> Result<(), ErrorReported> {
         })
         .try_collect_exhaust()?;
 
-    let global_env = GlobalEnv::new(tcx, fn_sigs);
-    global_env
-        .specs
+    let trait_methods: FxHashMap<_, _> = tcx
+        .hir()
+        .body_owners()
+        .filter_map(
+            |def_id| match LoweringCtxt::lower_trait_method_of(tcx, def_id.to_def_id()) {
+                Ok(Some((trait_method, substs))) => {
+                    let has_spec = trait_method
+                        .as_local()
+                        .map_or(false, |did| fn_sigs.contains_key(&did));
+                    has_spec.then(|| Ok((def_id, (trait_method, substs))))
+                }
+                Ok(None) => None,
+                Err(err) => Some(Err(report_unsupported(tcx, def_id, err))),
+            },
+        )
+        .try_collect_exhaust()?;
+
+    // Functions without a spec are checked against their default signature. With inference of
+    // private signatures, private functions get instead a signature to be inferred.
+    let unannotated: Vec<_> = tcx
+        .hir()
+        .body_owners()
+        .filter(|def_id| {
+            matches!(tcx.def_kind(*def_id), DefKind::Fn | DefKind::AssocFn)
+                && !fn_sigs.contains_key(def_id)
+                && !trait_methods.contains_key(def_id)
+        })
+        .collect();
+    // Signatures with holes `_` are inferred together with the ones of private functions.
+    let mut inferred: Vec<_> = fn_sigs
         .iter()
-        .map(|(def_id, spec)| {
-            if spec.assume {
-                return Ok(Default::default());
+        .filter(|(_, spec)| !spec.fn_sig.holes().is_empty())
+        .map(|(def_id, _)| *def_id)
+        .collect();
+    // Functions without a spec that cannot be lowered are not checked, they are reported with a
+    // warning instead.
+    let mut unannotated_sigs = FxHashSet::default();
+    for def_id in unannotated {
+        let infer = CONFIG.infer_private_sigs && is_private(tcx, def_id);
+        let fn_sig = if infer {
+            LoweringCtxt::inferred_fn_sig(tcx, def_id.to_def_id())
+        } else {
+            LoweringCtxt::default_fn_sig(tcx, def_id.to_def_id())
+        };
+        let fn_sig = match fn_sig {
+            Ok(fn_sig) => fn_sig,
+            Err(err) => {
+                warn_unchecked(tcx, def_id, &err.descr, err.span);
+                continue;
             }
-            let body = LoweringCtxt::lower(tcx, tcx.optimized_mir(*def_id))?;
-            typeck::check(&global_env, def_id.to_def_id(), &body)
-        })
-        .try_collect_exhaust()
+        };
+        if infer {
+            inferred.push(def_id);
+        }
+        unannotated_sigs.insert(def_id);
+        fn_sigs.insert(
+            def_id,
+            FnSpec {
+                fn_sig,
+                assume: false,
+            },
+        );
+    }
+
+    let fns_with_sig: FxHashSet<_> = fn_sigs
+        .keys()
+        .map(|def_id| def_id.to_def_id())
+        .chain(specs.extern_specs.keys().copied())
+        .chain(extern_fn_sigs.keys().copied())
+        .collect();
+    let to_check: Vec<_> = fn_sigs
+        .iter()
+        .filter(|(def_id, spec)| !spec.assume && has_body(tcx, **def_id))
+        .map(|(def_id, _)| *def_id)
+        .chain(trait_methods.keys().copied())
+        .collect();
+    // Functions that cannot be checked are reported without keeping the others from being checked.
+    let mut unsupported = Ok(());
+    let mut bodies = vec![];
+    for def_id in to_check {
+        match LoweringCtxt::lower(tcx, tcx.optimized_mir(def_id), &fns_with_sig) {
+            Ok(body) => bodies.push((def_id, body)),
+            Err(err) if unannotated_sigs.contains(&def_id) => {
+                warn_unchecked(tcx, def_id, &err.descr, err.span);
+                trust_default_sig(tcx, def_id, &mut fn_sigs, &mut inferred);
+            }
+            Err(err) => unsupported = Err(report_unsupported(tcx, def_id, err)),
+        }
+    }
+
+    // Callees without a signature, e.g., functions from other crates without a spec, are given
+    // their default signature.
+    let mut callees = FxHashSet::default();
+    for (_, body) in &bodies {
+        collect_callees(body, &mut callees);
+    }
+    let mut unsupported_callees = FxHashMap::default();
+    for callee in callees {
+        let has_sig = specs.extern_specs.contains_key(&callee)
+            || extern_fn_sigs.contains_key(&callee)
+            || callee
+                .as_local()
+                .map_or(false, |did| fn_sigs.contains_key(&did));
+        if has_sig {
+            continue;
+        }
+        let fn_sig = match LoweringCtxt::default_fn_sig(tcx, callee) {
+            Ok(fn_sig) => fn_sig,
+            Err(err) => {
+                unsupported_callees.insert(callee, err);
+                continue;
+            }
+        };
+        if let Some(local_id) = callee.as_local() {
+            fn_sigs.insert(
+                local_id,
+                FnSpec {
+                    fn_sig,
+                    assume: true,
+                },
+            );
+        } else {
+            extern_fn_sigs.insert(callee, fn_sig);
+        }
+    }
+
+    // Functions calling a callee whose signature cannot be lowered cannot be checked either.
+    if !unsupported_callees.is_empty() {
+        bodies.retain(|(def_id, body)| {
+            let mut callees = FxHashSet::default();
+            collect_callees(body, &mut callees);
+            let (callee, err) = match callees
+                .iter()
+                .find_map(|callee| unsupported_callees.get_key_value(callee))
+            {
+                Some(unsupported) => unsupported,
+                None => return true,
+            };
+            let descr = format!(
+                "the signature of `{}` is not supported: {}",
+                tcx.def_path_str(*callee),
+                err.descr
+            );
+            if unannotated_sigs.contains(def_id) {
+                warn_unchecked(tcx, *def_id, &descr, None);
+                trust_default_sig(tcx, *def_id, &mut fn_sigs, &mut inferred);
+            } else {
+                tcx.sess.span_err(tcx.def_span(*def_id), &descr);
+                unsupported = Err(ErrorReported);
+            }
+            false
+        });
+    }
+
+    let mut global_env = GlobalEnv::new(
+        tcx,
+        fn_sigs,
+        specs.extern_specs,
+        extern_fn_sigs,
+        trait_methods,
+    );
+
+    let impl_methods = global_env
+        .trait_methods
+        .keys()
+        .filter(|def_id| global_env.specs.contains_key(def_id))
+        .map(|def_id| typeck::check_impl_method(&global_env, def_id.to_def_id()))
+        .try_collect_exhaust();
+
+    // The signatures to be inferred are solved in a single query together with the bodies that
+    // depend on them, i.e., their own and the ones of their callers. Every other body is checked
+    // on its own.
+    let inferred_set: FxHashSet<_> = inferred.iter().map(|def_id| def_id.to_def_id()).collect();
+    let (joint, separate): (Vec<_>, Vec<_>) = bodies.into_iter().partition(|(def_id, body)| {
+        let mut callees = FxHashSet::default();
+        collect_callees(body, &mut callees);
+        inferred_set.contains(&def_id.to_def_id()) || !callees.is_disjoint(&inferred_set)
+    });
+    let joint = if inferred.is_empty() {
+        Ok(())
+    } else {
+        typeck::check_inferring_sigs(&mut global_env, &inferred, &joint)
+    };
+    let separate = separate
+        .iter()
+        .map(|(def_id, body)| typeck::check(&global_env, def_id.to_def_id(), body))
+        .try_collect_exhaust();
+
+    unsupported?;
+    impl_methods?;
+    joint?;
+    separate?;
+
+    if export {
+        // Inferred signatures, and signatures with holes, refer to kvars only meaningful in the
+        // query they were solved in. Default signatures say nothing, so they are not exported
+        // either and dependent crates use their own specs for these functions, if any.
+        for def_id in inferred.iter().chain(&unannotated_sigs) {
+            global_env.specs.remove(def_id);
+        }
+        metadata::export(tcx, global_env.exported_fn_sigs()).map_err(|err| {
+            sess.err(&format!("failed to export signatures: {}", err));
+            ErrorReported
+        })?;
+    }
+    Ok(export)
+}
+
+/// Reports a construct not supported in a function that has to be checked, at the function if
+/// the construct has no span.
+fn report_unsupported(tcx: TyCtxt, def_id: LocalDefId, err: UnsupportedErr) -> ErrorReported {
+    let span = err.span.unwrap_or_else(|| tcx.def_span(def_id));
+    tcx.sess.span_err(span, &err.descr);
+    ErrorReported
+}
+
+/// Warns that a function without a spec is not checked because it uses a construct not supported.
+fn warn_unchecked(tcx: TyCtxt, def_id: LocalDefId, descr: &str, span: Option<Span>) {
+    let mut diag = tcx.sess.struct_span_warn(
+        tcx.def_span(def_id),
+        &format!("`{}` is not checked", tcx.def_path_str(def_id.to_def_id())),
+    );
+    match span {
+        Some(span) => diag.span_note(span, descr),
+        None => diag.note(descr),
+    };
+    diag.emit();
+}
+
+/// Trusts the default signature of a function without a spec whose body cannot be checked,
+/// instead of inferring its signature.
+fn trust_default_sig(
+    tcx: TyCtxt,
+    def_id: LocalDefId,
+    fn_sigs: &mut FxHashMap<LocalDefId, FnSpec>,
+    inferred: &mut Vec<LocalDefId>,
+) {
+    inferred.retain(|did| *did != def_id);
+    match LoweringCtxt::default_fn_sig(tcx, def_id.to_def_id()) {
+        Ok(fn_sig) => {
+            fn_sigs.insert(
+                def_id,
+                FnSpec {
+                    fn_sig,
+                    assume: true,
+                },
+            );
+        }
+        Err(_) => {
+            fn_sigs.remove(&def_id);
+        }
+    }
+}
+
+/// Collects the functions called in `body` and in the closures it creates, including the ones
+/// coerced to function pointers.
+fn collect_callees(body: &Body, callees: &mut FxHashSet<DefId>) {
+    for data in &body.basic_blocks {
+        for stmt in &data.statements {
+            if let StatementKind::Assign(_, Rvalue::FnPointer(func, ..)) = &stmt.kind {
+                callees.insert(*func);
+            }
+        }
+        if let Some(Terminator {
+            kind: TerminatorKind::Call { func, .. },
+            ..
+        }) = &data.terminator
+        {
+            callees.insert(*func);
+        }
+    }
+    for closure in body.closures.values() {
+        collect_callees(&closure.body, callees);
+    }
+}
+
+/// Whether the function is private and not the implementation of a trait method.
+fn is_private(tcx: TyCtxt, def_id: LocalDefId) -> bool {
+    !tcx.visibility(def_id).is_public() && !is_trait_impl(tcx, def_id)
+}
+
+/// Whether the function is a method of a trait or of a trait impl.
+fn is_trait_method(tcx: TyCtxt, def_id: LocalDefId) -> bool {
+    tcx.trait_of_item(def_id.to_def_id()).is_some() || is_trait_impl(tcx, def_id)
+}
+
+fn is_trait_impl(tcx: TyCtxt, def_id: LocalDefId) -> bool {
+    tcx.impl_of_method(def_id.to_def_id())
+        .map_or(false, |impl_did| tcx.impl_trait_ref(impl_did).is_some())
+}
+
+/// Whether the function has a body, e.g., trait methods without a default implementation don't.
+fn has_body(tcx: TyCtxt, def_id: LocalDefId) -> bool {
+    let hir_id = tcx.hir().local_def_id_to_hir_id(def_id);
+    tcx.hir().maybe_body_owned_by(hir_id).is_some()
 }
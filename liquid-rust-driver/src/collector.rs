@@ -1,36 +1,49 @@
 use liquid_rust_common::errors::ErrorReported;
 use liquid_rust_syntax::{ast::FnSig, parse_fn_sig, ParseErrorKind};
-use rustc_ast::{tokenstream::TokenStream, AttrKind, Attribute, MacArgs};
+use rustc_ast::{
+    token::{BinOpToken, TokenKind},
+    tokenstream::{TokenStream, TokenTree},
+    AttrKind, Attribute, MacArgs,
+};
 use rustc_hash::FxHashMap;
 use rustc_hir::{
-    def_id::LocalDefId, itemlikevisit::ItemLikeVisitor, ForeignItem, ImplItem, ImplItemKind, Item,
-    ItemKind, TraitItem,
+    def_id::{DefId, LocalDefId},
+    itemlikevisit::ItemLikeVisitor,
+    ForeignItem, ForeignItemKind, ImplItem, ImplItemKind, Item, ItemKind, TraitItem, TraitItemKind,
 };
 use rustc_middle::ty::TyCtxt;
 use rustc_session::Session;
-use rustc_span::Span;
+use rustc_span::{Span, Symbol};
+
+use crate::def_path;
 
 pub(crate) struct SpecCollector<'tcx, 'a> {
     tcx: TyCtxt<'tcx>,
-    specs: FxHashMap<LocalDefId, FnSpec>,
+    specs: Specs,
     sess: &'a Session,
     error_reported: bool,
 }
 
+pub struct Specs {
+    pub fns: FxHashMap<LocalDefId, FnSpec>,
+    /// Functions from other crates mapped to the local stub declaring their spec.
+    pub extern_specs: FxHashMap<DefId, LocalDefId>,
+}
+
 pub struct FnSpec {
     pub fn_sig: FnSig,
     pub assume: bool,
 }
 
 impl<'tcx, 'a> SpecCollector<'tcx, 'a> {
-    pub(crate) fn collect(
-        tcx: TyCtxt<'tcx>,
-        sess: &'a Session,
-    ) -> Result<FxHashMap<LocalDefId, FnSpec>, ErrorReported> {
+    pub(crate) fn collect(tcx: TyCtxt<'tcx>, sess: &'a Session) -> Result<Specs, ErrorReported> {
         let mut collector = Self {
             tcx,
             sess,
-            specs: FxHashMap::default(),
+            specs: Specs {
+                fns: FxHashMap::default(),
+                extern_specs: FxHashMap::default(),
+            },
             error_reported: false,
         };
 
@@ -46,6 +59,7 @@ impl<'tcx, 'a> SpecCollector<'tcx, 'a> {
     fn parse_annotations(&mut self, def_id: LocalDefId, attributes: &[Attribute]) {
         let mut fn_sig = None;
         let mut assume = false;
+        let mut extern_fn = None;
         for attribute in attributes {
             if let AttrKind::Normal(attr_item, ..) = &attribute.kind {
                 // Be sure we are in a `liquid` attribute.
@@ -70,12 +84,62 @@ impl<'tcx, 'a> SpecCollector<'tcx, 'a> {
                     [second] if &*second.ident.as_str() == "assume" => {
                         assume = true;
                     }
+                    [second] if &*second.ident.as_str() == "extern_spec" => {
+                        if let MacArgs::Delimited(span, _, tokens) = &attr_item.args {
+                            extern_fn = self.parse_extern_path(tokens, span.entire());
+                            if extern_fn.is_none() {
+                                return;
+                            }
+                        } else {
+                            self.emit_error(
+                                "extern spec must name the function it specifies, e.g., `#[lr::extern_spec(std::vec::Vec::<T>::len)]`",
+                                attr_item.span(),
+                            );
+                            return;
+                        }
+                    }
                     _ => self.emit_error("invalid liquid annotation.", attr_item.span()),
                 }
             }
         }
+        if let Some(extern_fn) = extern_fn {
+            self.specs.extern_specs.insert(extern_fn, def_id);
+            // The stub only declares the spec, its body is never checked.
+            assume = true;
+        }
         if let Some(fn_sig) = fn_sig {
-            self.specs.insert(def_id, FnSpec { fn_sig, assume });
+            self.specs.fns.insert(def_id, FnSpec { fn_sig, assume });
+        }
+    }
+
+    /// Resolves the path in an extern spec attribute to a function defined in another crate, e.g.,
+    ///
+    /// ```ignore
+    /// #[lr::extern_spec(std::vec::Vec::<T>::len)]
+    /// #[lr::ty(fn(&Vec<T>) -> usize{v: v >= 0})]
+    /// fn len<T>(v: &Vec<T>) -> usize { v.len() }
+    /// ```
+    ///
+    /// Generic arguments in the path are ignored. The path must start with the name of a crate or
+    /// with a primitive type, e.g., `i32::abs`.
+    fn parse_extern_path(&mut self, tokens: &TokenStream, span: Span) -> Option<DefId> {
+        let segments = parse_path(tokens).unwrap_or_default();
+        let (root, segments) = match segments.split_first() {
+            Some(split) => split,
+            None => {
+                self.emit_error("expected the path of a function", span);
+                return None;
+            }
+        };
+        match def_path::lookup_fn(self.tcx, *root, segments) {
+            Ok(def_id) if !def_id.is_local() => Some(def_id),
+            _ => {
+                self.emit_error(
+                    "cannot find a function from another crate with this path",
+                    span,
+                );
+                None
+            }
         }
     }
 
@@ -101,6 +165,37 @@ impl<'tcx, 'a> SpecCollector<'tcx, 'a> {
     }
 }
 
+/// Parses a path like `std::vec::Vec::<T>::len` into its segments skipping generic arguments.
+fn parse_path(tokens: &TokenStream) -> Option<Vec<Symbol>> {
+    let mut segments = vec![];
+    let mut depth = 0;
+    let mut expect_ident = true;
+    for tree in tokens.trees() {
+        let kind = match tree {
+            TokenTree::Token(token) => token.kind,
+            TokenTree::Delimited(..) if depth > 0 => continue,
+            TokenTree::Delimited(..) => return None,
+        };
+        match kind {
+            TokenKind::Lt if depth > 0 || !segments.is_empty() => depth += 1,
+            TokenKind::Gt if depth > 0 => depth -= 1,
+            TokenKind::BinOp(BinOpToken::Shr) if depth > 1 => depth -= 2,
+            _ if depth > 0 => {}
+            TokenKind::Ident(name, _) if expect_ident => {
+                segments.push(name);
+                expect_ident = false;
+            }
+            TokenKind::ModSep if !expect_ident => expect_ident = true,
+            _ => return None,
+        }
+        // Generic arguments, either as `Vec<T>` or `Vec::<T>`, close the preceding segment.
+        if depth == 0 && matches!(kind, TokenKind::Gt | TokenKind::BinOp(BinOpToken::Shr)) {
+            expect_ident = false;
+        }
+    }
+    (depth == 0 && !expect_ident).then(|| segments)
+}
+
 impl<'hir> ItemLikeVisitor<'hir> for SpecCollector<'_, '_> {
     fn visit_item(&mut self, item: &'hir Item<'hir>) {
         if let ItemKind::Fn(..) = item.kind {
@@ -110,7 +205,13 @@ impl<'hir> ItemLikeVisitor<'hir> for SpecCollector<'_, '_> {
         }
     }
 
-    fn visit_trait_item(&mut self, _trait_item: &'hir TraitItem<'hir>) {}
+    fn visit_trait_item(&mut self, item: &'hir TraitItem<'hir>) {
+        if let TraitItemKind::Fn(..) = &item.kind {
+            let hir_id = item.hir_id();
+            let attrs = self.tcx.hir().attrs(hir_id);
+            self.parse_annotations(item.def_id, attrs);
+        }
+    }
     fn visit_impl_item(&mut self, item: &'hir ImplItem<'hir>) {
         if let ImplItemKind::Fn(..) = &item.kind {
             let hir_id = item.hir_id();
@@ -118,5 +219,15 @@ impl<'hir> ItemLikeVisitor<'hir> for SpecCollector<'_, '_> {
             self.parse_annotations(item.def_id, attrs);
         }
     }
-    fn visit_foreign_item(&mut self, _foreign_item: &'hir ForeignItem<'hir>) {}
+    fn visit_foreign_item(&mut self, item: &'hir ForeignItem<'hir>) {
+        if let ForeignItemKind::Fn(..) = &item.kind {
+            let hir_id = item.hir_id();
+            let attrs = self.tcx.hir().attrs(hir_id);
+            self.parse_annotations(item.def_id, attrs);
+            // Foreign functions don't have a body we can check so their specs are trusted.
+            if let Some(spec) = self.specs.fns.get_mut(&item.def_id) {
+                spec.assume = true;
+            }
+        }
+    }
 }
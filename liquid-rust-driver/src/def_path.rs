@@ -0,0 +1,96 @@
+//! Lookup of functions from other crates by their path, shared by extern specs and spec files.
+//!
+//! A path starts with the name of a crate or with a primitive type and may go through modules,
+//! inherent impls of types and traits, e.g., `std::vec::Vec::len`, `i32::abs` or
+//! `std::ops::Add::add`. Generic arguments are not part of the path.
+use rustc_hir::def::{DefKind, Res};
+use rustc_middle::ty::{AssocKind, TyCtxt};
+use rustc_span::{def_id::DefId, sym, Symbol};
+
+/// Why the path of a function could not be looked up.
+pub enum LookupError {
+    /// The first segment of the path is neither a dependency of the current crate nor a primitive
+    /// type.
+    UnknownRoot,
+    /// The segment at the given position (not counting the first one) is not found in the item
+    /// the path leads to before it.
+    NotFound(usize, DefId),
+    /// The path leads to an item that is not a function.
+    NotAFunction(DefId),
+}
+
+/// Looks up the function at the path made of `root` followed by `segments`.
+pub fn lookup_fn(tcx: TyCtxt, root: Symbol, segments: &[Symbol]) -> Result<DefId, LookupError> {
+    let mut def_id = match prim_ty_impl(tcx, root) {
+        Some(impl_id) => impl_id,
+        None => tcx
+            .crates(())
+            .iter()
+            .find(|cnum| tcx.crate_name(**cnum) == root)
+            .ok_or(LookupError::UnknownRoot)?
+            .as_def_id(),
+    };
+    for (i, segment) in segments.iter().enumerate() {
+        let is_last = i + 1 == segments.len();
+        def_id =
+            lookup_child(tcx, def_id, *segment, is_last).ok_or(LookupError::NotFound(i, def_id))?;
+    }
+    match tcx.def_kind(def_id) {
+        DefKind::Fn | DefKind::AssocFn => Ok(def_id),
+        _ => Err(LookupError::NotAFunction(def_id)),
+    }
+}
+
+/// Looks up an item named `name` inside a module, or an associated function inside an inherent
+/// impl of a type, a trait or the impl of a primitive type. Only functions are looked up for the
+/// last segment of a path.
+fn lookup_child(tcx: TyCtxt, parent: DefId, name: Symbol, is_last: bool) -> Option<DefId> {
+    match tcx.def_kind(parent) {
+        DefKind::Mod => tcx
+            .item_children(parent)
+            .iter()
+            .find_map(|export| match export.res {
+                Res::Def(def_kind, def_id)
+                    if export.ident.name == name && (def_kind == DefKind::Fn) == is_last =>
+                {
+                    Some(def_id)
+                }
+                _ => None,
+            }),
+        DefKind::Struct | DefKind::Enum | DefKind::Union if is_last => tcx
+            .inherent_impls(parent)
+            .iter()
+            .find_map(|impl_id| lookup_assoc_fn(tcx, *impl_id, name)),
+        DefKind::Trait | DefKind::Impl if is_last => lookup_assoc_fn(tcx, parent, name),
+        _ => None,
+    }
+}
+
+fn lookup_assoc_fn(tcx: TyCtxt, container: DefId, name: Symbol) -> Option<DefId> {
+    tcx.associated_items(container)
+        .filter_by_name_unhygienic(name)
+        .find(|item| item.kind == AssocKind::Fn)
+        .map(|item| item.def_id)
+}
+
+/// The inherent impl of the primitive type named `name`, if any.
+fn prim_ty_impl(tcx: TyCtxt, name: Symbol) -> Option<DefId> {
+    let lang_items = tcx.lang_items();
+    match name {
+        sym::i8 => lang_items.i8_impl(),
+        sym::i16 => lang_items.i16_impl(),
+        sym::i32 => lang_items.i32_impl(),
+        sym::i64 => lang_items.i64_impl(),
+        sym::i128 => lang_items.i128_impl(),
+        sym::isize => lang_items.isize_impl(),
+        sym::u8 => lang_items.u8_impl(),
+        sym::u16 => lang_items.u16_impl(),
+        sym::u32 => lang_items.u32_impl(),
+        sym::u64 => lang_items.u64_impl(),
+        sym::u128 => lang_items.u128_impl(),
+        sym::usize => lang_items.usize_impl(),
+        sym::bool => lang_items.bool_impl(),
+        sym::char => lang_items.char_impl(),
+        _ => None,
+    }
+}
@@ -3,6 +3,7 @@
 extern crate rustc_ast;
 extern crate rustc_ast_pretty;
 extern crate rustc_const_eval;
+extern crate rustc_data_structures;
 extern crate rustc_driver;
 extern crate rustc_errors;
 extern crate rustc_hash;
@@ -10,13 +11,18 @@ extern crate rustc_hir;
 extern crate rustc_interface;
 extern crate rustc_macros;
 extern crate rustc_middle;
+extern crate rustc_parse;
+extern crate rustc_serialize;
 extern crate rustc_session;
 extern crate rustc_span;
 
 mod callbacks;
 mod collector;
+mod def_path;
 mod lowering;
+mod metadata;
 mod resolve;
+mod spec_files;
 
 use callbacks::LiquidCallbacks;
 use rustc_driver::{catch_with_exit_code, RunCompiler};
@@ -34,10 +40,6 @@ pub fn run_compiler(mut args: Vec<String>) -> i32 {
     // Add the sysroot path to the arguments.
     args.push("--sysroot".into());
     args.push(sysroot().expect("Liquid Rust requires rustup to be built."));
-    // Add release mode to the arguments.
-    args.push("-O".into());
-    // We don't support unwinding.
-    args.push("-Cpanic=abort".into());
     // Run the rust compiler with the arguments.
     let mut callbacks = LiquidCallbacks::default();
     catch_with_exit_code(move || RunCompiler::new(&args, &mut callbacks).run())
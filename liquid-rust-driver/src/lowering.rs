@@ -1,29 +1,77 @@
+use std::cell::RefCell;
+
 use itertools::Itertools;
-use liquid_rust_common::errors::ErrorReported;
+use liquid_rust_common::{config::CONFIG, index::IndexGen};
 use liquid_rust_core::{
     self as core,
     ir::{
-        BasicBlockData, BinOp, Body, Constant, Operand, Place, PlaceElem, Rvalue, Statement,
-        StatementKind, Terminator, TerminatorKind,
+        AggregateKind, BasicBlockData, BinOp, Body, Closure, ClosureKind, Constant, Operand, Place,
+        PlaceElem, Rvalue, Statement, StatementKind, Terminator, TerminatorKind,
     },
 };
 use rustc_const_eval::interpret::ConstValue;
+use rustc_hash::{FxHashMap, FxHashSet};
+use rustc_hir::{def::DefKind, def_id::DefId};
 use rustc_middle::{
-    mir,
-    ty::{subst::GenericArgKind, ParamEnv, TyCtxt},
+    mir::{self, tcx::PlaceTy},
+    ty::{
+        adjustment::PointerCast,
+        subst::{GenericArgKind, InternalSubsts, SubstsRef},
+        AssocKind, Instance, ParamEnv, TyCtxt,
+    },
 };
+use rustc_span::{sym, symbol::kw, Span, Symbol};
 
-pub struct LoweringCtxt<'tcx> {
+pub struct LoweringCtxt<'a, 'tcx> {
     tcx: TyCtxt<'tcx>,
     body: &'tcx mir::Body<'tcx>,
+    adt_fields: RefCell<FxHashMap<DefId, Vec<core::ty::Ty>>>,
+    /// Functions with a signature. Only the arguments of diverging calls to them are lowered.
+    fns_with_sig: &'a FxHashSet<DefId>,
+    /// Library methods that panic on some of their inputs, e.g., `Option::unwrap`.
+    panicking_methods: FxHashSet<DefId>,
+}
+
+/// A construct not supported yet found while lowering a function or its signature. It is up to
+/// the caller to report it as an error or to skip the function.
+pub struct UnsupportedErr {
+    pub descr: String,
+    pub span: Option<Span>,
+}
+
+impl UnsupportedErr {
+    fn new(span: Option<Span>, descr: impl Into<String>) -> Self {
+        UnsupportedErr {
+            descr: descr.into(),
+            span,
+        }
+    }
 }
 
-impl<'tcx> LoweringCtxt<'tcx> {
+impl<'a, 'tcx> LoweringCtxt<'a, 'tcx> {
     pub fn lower(
         tcx: TyCtxt<'tcx>,
         body: &'tcx mir::Body<'tcx>,
-    ) -> Result<Body<'tcx>, ErrorReported> {
-        let lower = Self { tcx, body };
+        fns_with_sig: &'a FxHashSet<DefId>,
+    ) -> Result<Body<'tcx>, UnsupportedErr> {
+        let lower = Self {
+            tcx,
+            body,
+            adt_fields: RefCell::default(),
+            fns_with_sig,
+            panicking_methods: panicking_methods(tcx),
+        };
+
+        let closures = body
+            .local_decls
+            .iter()
+            .filter_map(|decl| match decl.ty.kind() {
+                rustc_middle::ty::TyKind::Closure(did, substs) => Some((*did, *substs)),
+                _ => None,
+            })
+            .unique_by(|(did, _)| *did)
+            .map(|(did, substs)| Ok((did, lower.lower_closure(did, substs)?)))
+            .try_collect()?;
 
         let basic_blocks = body
             .basic_blocks()
@@ -36,13 +84,187 @@ impl<'tcx> LoweringCtxt<'tcx> {
             nlocals: body.local_decls.len(),
             arg_count: body.arg_count,
             mir: body,
+            closures,
+            adt_fields: lower.adt_fields.into_inner(),
+        })
+    }
+
+    /// Returns the trait method implemented by `impl_method` together with the type arguments
+    /// instantiating it for the impl, if `impl_method` is the implementation of a trait method.
+    pub fn lower_trait_method_of(
+        tcx: TyCtxt<'tcx>,
+        impl_method: DefId,
+    ) -> Result<Option<(DefId, Vec<core::ty::Ty>)>, UnsupportedErr> {
+        if tcx.def_kind(impl_method) != DefKind::AssocFn {
+            return Ok(None);
+        }
+        let impl_did = if let Some(impl_did) = tcx.impl_of_method(impl_method) {
+            impl_did
+        } else {
+            return Ok(None);
+        };
+        let trait_ref = if let Some(trait_ref) = tcx.impl_trait_ref(impl_did) {
+            trait_ref
+        } else {
+            return Ok(None);
+        };
+        let item = tcx.associated_item(impl_method);
+        let trait_method = tcx
+            .associated_items(trait_ref.def_id)
+            .find_by_name_and_kind(tcx, item.ident, AssocKind::Fn, trait_ref.def_id)
+            .unwrap()
+            .def_id;
+
+        let substs = InternalSubsts::identity_for_item(tcx, impl_method).rebase_onto(
+            tcx,
+            impl_did,
+            trait_ref.substs,
+        );
+        let substs = substs
+            .types()
+            .map(|ty| lower_ty_with(tcx, ty, default_pred))
+            .try_collect()?;
+        Ok(Some((trait_method, substs)))
+    }
+
+    /// The signature of a function without a spec: every argument and the return value are
+    /// refined with `true` and mutable references are strong references to a location that holds
+    /// a value of the referenced type before and after the call.
+    pub fn default_fn_sig(
+        tcx: TyCtxt<'tcx>,
+        def_id: DefId,
+    ) -> Result<core::ty::FnSig, UnsupportedErr> {
+        Self::lower_unannotated_fn_sig(tcx, def_id, false)
+    }
+
+    /// The signature of a private function without a spec whose refinements are inferred. It is
+    /// like the default signature, except that integer and boolean arguments are refined by a
+    /// parameter constrained by a predicate to be inferred, and the value returned by one such
+    /// predicate if it is an integer or a boolean.
+    pub fn inferred_fn_sig(
+        tcx: TyCtxt<'tcx>,
+        def_id: DefId,
+    ) -> Result<core::ty::FnSig, UnsupportedErr> {
+        Self::lower_unannotated_fn_sig(tcx, def_id, true)
+    }
+
+    fn lower_unannotated_fn_sig(
+        tcx: TyCtxt<'tcx>,
+        def_id: DefId,
+        infer: bool,
+    ) -> Result<core::ty::FnSig, UnsupportedErr> {
+        let fn_sig = tcx.erase_late_bound_regions(tcx.fn_sig(def_id));
+        let arg_names = tcx.fn_arg_names(def_id);
+        let name_gen = IndexGen::new();
+        let mut params = vec![];
+        let mut requires = vec![];
+        let mut ensures = vec![];
+        let args = fn_sig
+            .inputs()
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| match ty.kind() {
+                rustc_middle::ty::TyKind::Ref(_, ty, rustc_hir::Mutability::Mut) => {
+                    let loc = name_gen.fresh();
+                    requires.push((loc, lower_ty_with(tcx, ty, default_pred)?));
+                    ensures.push((loc, lower_ty_with(tcx, ty, default_pred)?));
+                    Ok(core::ty::Ty::MutRef(loc))
+                }
+                _ => match lower_ty_with(tcx, ty, default_pred)? {
+                    core::ty::Ty::Exists(bty, _) if infer && is_scalar(&bty) => {
+                        let ident = arg_names[i];
+                        let symbol = if ident.name == kw::Empty || ident.name == kw::Underscore {
+                            Symbol::intern(&format!("a{}", i))
+                        } else {
+                            ident.name
+                        };
+                        let name = core::ty::Ident {
+                            name: name_gen.fresh(),
+                            source_info: (ident.span, symbol),
+                        };
+                        let var = core::ty::Expr {
+                            kind: core::ty::ExprKind::Var(
+                                core::ty::Var::Free(name.name),
+                                symbol,
+                                ident.span,
+                            ),
+                            span: Some(ident.span),
+                        };
+                        params.push(core::ty::Param {
+                            name,
+                            sort: bty.sort(),
+                            pred: core::ty::Pred::Infer(None),
+                        });
+                        Ok(core::ty::Ty::Refine(bty, var))
+                    }
+                    ty => Ok(ty),
+                },
+            })
+            .try_collect()?;
+        let ret = match lower_ty_with(tcx, fn_sig.output(), default_pred)? {
+            core::ty::Ty::Exists(bty, _) if infer && is_scalar(&bty) => {
+                core::ty::Ty::Exists(bty, core::ty::Pred::Infer(None))
+            }
+            ret => ret,
+        };
+        Ok(core::ty::FnSig {
+            params,
+            requires,
+            args,
+            ret,
+            ensures,
+        })
+    }
+
+    fn lower_closure(
+        &self,
+        did: DefId,
+        substs: SubstsRef<'tcx>,
+    ) -> Result<Closure<'tcx>, UnsupportedErr> {
+        let tcx = self.tcx;
+        let closure = substs.as_closure();
+        let kind = match closure.kind() {
+            rustc_middle::ty::ClosureKind::Fn => ClosureKind::Fn,
+            rustc_middle::ty::ClosureKind::FnOnce => ClosureKind::FnOnce,
+            rustc_middle::ty::ClosureKind::FnMut => {
+                return Err(UnsupportedErr::new(
+                    Some(tcx.def_span(did)),
+                    "closures mutating their captured variables are not supported yet",
+                ));
+            }
+        };
+        // The arguments of a closure are passed as a tuple.
+        let sig = tcx.erase_late_bound_regions(closure.sig());
+        let args = match sig.inputs()[0].kind() {
+            rustc_middle::ty::TyKind::Tuple(tys) => tys
+                .iter()
+                .map(|ty| lower_ty_with(self.tcx, ty.expect_ty(), infer_pred))
+                .try_collect()?,
+            _ => unreachable!("closure arguments are not tupled"),
+        };
+        Ok(Closure {
+            kind,
+            args,
+            ret: lower_ty_with(self.tcx, sig.output(), infer_pred)?,
+            body: LoweringCtxt::lower(tcx, tcx.optimized_mir(did), self.fns_with_sig)?,
         })
     }
 
     fn lower_basic_block_data(
         &self,
         data: &mir::BasicBlockData<'tcx>,
-    ) -> Result<BasicBlockData, ErrorReported> {
+    ) -> Result<BasicBlockData, UnsupportedErr> {
+        // Cleanup blocks only run when unwinding from a panic. We never follow unwind edges
+        // because panics are either proven unreachable or assumed not to happen.
+        if data.is_cleanup {
+            return Ok(BasicBlockData {
+                statements: vec![],
+                terminator: Some(Terminator {
+                    kind: TerminatorKind::Unreachable,
+                    source_info: data.terminator().source_info,
+                }),
+            });
+        }
         let data = BasicBlockData {
             statements: data
                 .statements
@@ -58,7 +280,7 @@ impl<'tcx> LoweringCtxt<'tcx> {
         Ok(data)
     }
 
-    fn lower_statement(&self, stmt: &mir::Statement<'tcx>) -> Result<Statement, ErrorReported> {
+    fn lower_statement(&self, stmt: &mir::Statement<'tcx>) -> Result<Statement, UnsupportedErr> {
         let kind = match &stmt.kind {
             mir::StatementKind::Assign(box (place, rvalue)) => StatementKind::Assign(
                 self.lower_place(place)?,
@@ -66,27 +288,30 @@ impl<'tcx> LoweringCtxt<'tcx> {
             ),
             mir::StatementKind::Nop
             | mir::StatementKind::StorageLive(_)
-            | mir::StatementKind::StorageDead(_) => StatementKind::Nop,
-            mir::StatementKind::FakeRead(_)
-            | mir::StatementKind::SetDiscriminant { .. }
-            | mir::StatementKind::LlvmInlineAsm(_)
+            | mir::StatementKind::StorageDead(_)
+            | mir::StatementKind::FakeRead(_)
             | mir::StatementKind::Retag(_, _)
             | mir::StatementKind::AscribeUserType(_, _)
-            | mir::StatementKind::Coverage(_)
+            | mir::StatementKind::Coverage(_) => StatementKind::Nop,
+            mir::StatementKind::SetDiscriminant { .. }
+            | mir::StatementKind::LlvmInlineAsm(_)
             | mir::StatementKind::CopyNonOverlapping(_) => {
-                self.tcx
-                    .sess
-                    .span_err(stmt.source_info.span, "unsupported statement kind");
-                return Err(ErrorReported);
+                return Err(UnsupportedErr::new(
+                    Some(stmt.source_info.span),
+                    "unsupported statement kind",
+                ));
             }
         };
-        Ok(Statement { kind })
+        Ok(Statement {
+            kind,
+            source_info: stmt.source_info,
+        })
     }
 
     fn lower_terminator(
         &self,
         terminator: &mir::Terminator<'tcx>,
-    ) -> Result<Terminator, ErrorReported> {
+    ) -> Result<Terminator, UnsupportedErr> {
         let kind = match &terminator.kind {
             mir::TerminatorKind::Return => TerminatorKind::Return,
             mir::TerminatorKind::Call {
@@ -94,62 +319,115 @@ impl<'tcx> LoweringCtxt<'tcx> {
                 args,
                 destination,
                 ..
-            } => {
-                let (func, substs) = match func.ty(self.body, self.tcx).kind() {
-                    rustc_middle::ty::TyKind::FnDef(fn_def, substs) => {
-                        let substs = substs
+            } => match (func.ty(self.body, self.tcx).kind(), destination) {
+                (rustc_middle::ty::TyKind::FnDef(fn_def, substs), Some(destination))
+                    if self.is_fn_trait_method(*fn_def) =>
+                {
+                    self.lower_fn_trait_call(substs, args, destination)?
+                }
+                (rustc_middle::ty::TyKind::FnPtr(_), Some((place, bb))) => {
+                    TerminatorKind::CallIndirect {
+                        callee: self.lower_operand(func)?,
+                        args: args
                             .iter()
-                            .map(|arg| self.lower_generic_arg(arg))
-                            .try_collect()?;
-
-                        (*fn_def, substs)
+                            .map(|arg| self.lower_operand(arg))
+                            .try_collect()?,
+                        destination: (self.lower_place(place)?, *bb),
                     }
-                    _ => {
-                        self.tcx
-                            .sess
-                            .span_err(terminator.source_info.span, "unsupported function call");
-                        return Err(ErrorReported);
+                }
+                (rustc_middle::ty::TyKind::FnDef(fn_def, substs), Some((place, bb)))
+                    if CONFIG.check_panics && self.is_index_method(*fn_def) =>
+                {
+                    self.lower_index_call(*fn_def, substs, args, place, *bb)?
+                }
+                (rustc_middle::ty::TyKind::FnDef(fn_def, substs), Some(_))
+                    if CONFIG.check_panics && self.panicking_methods.contains(fn_def) =>
+                {
+                    self.lower_diverge(*fn_def, substs, args)?
+                }
+                (rustc_middle::ty::TyKind::FnDef(fn_def, substs), Some((place, bb))) => {
+                    TerminatorKind::Call {
+                        func: *fn_def,
+                        substs: substs
+                            .iter()
+                            .map(|arg| self.lower_generic_arg(arg))
+                            .try_collect()?,
+                        destination: (self.lower_place(place)?, *bb),
+                        args: args
+                            .iter()
+                            .map(|arg| self.lower_operand(arg))
+                            .try_collect()?,
+                        panics: false,
                     }
-                };
-                let destination = match destination {
-                    Some((place, bb)) => Some((self.lower_place(place)?, *bb)),
-                    None => None,
-                };
-
-                TerminatorKind::Call {
-                    func,
-                    substs,
-                    destination,
-                    args: args
-                        .iter()
-                        .map(|arg| self.lower_operand(arg))
-                        .try_collect()?,
                 }
-            }
-            mir::TerminatorKind::SwitchInt { discr, targets, .. } => TerminatorKind::SwitchInt {
+                (rustc_middle::ty::TyKind::FnDef(fn_def, substs), None) => {
+                    self.lower_diverge(*fn_def, substs, args)?
+                }
+                _ => {
+                    return Err(UnsupportedErr::new(
+                        Some(terminator.source_info.span),
+                        "unsupported function call",
+                    ));
+                }
+            },
+            mir::TerminatorKind::SwitchInt {
+                discr,
+                switch_ty,
+                targets,
+            } => TerminatorKind::SwitchInt {
                 discr: self.lower_operand(discr)?,
-                targets: targets.clone(),
+                targets: self.lower_switch_targets(switch_ty, targets),
             },
-            mir::TerminatorKind::Goto { target } => TerminatorKind::Goto { target: *target },
+            mir::TerminatorKind::Goto { target }
+            | mir::TerminatorKind::FalseEdge {
+                real_target: target,
+                ..
+            }
+            | mir::TerminatorKind::FalseUnwind {
+                real_target: target,
+                ..
+            } => TerminatorKind::Goto { target: *target },
             mir::TerminatorKind::Drop { place, target, .. } => TerminatorKind::Drop {
                 place: self.lower_place(place)?,
                 target: *target,
             },
-            mir::TerminatorKind::Resume
+            mir::TerminatorKind::DropAndReplace {
+                place,
+                value,
+                target,
+                ..
+            } => TerminatorKind::DropAndReplace {
+                place: self.lower_place(place)?,
+                value: self.lower_operand(value)?,
+                target: *target,
+            },
+            // Bounds checks are redundant because every index projection is checked to be in
+            // bounds when typechecking the place.
+            mir::TerminatorKind::Assert {
+                msg: mir::AssertKind::BoundsCheck { .. },
+                target,
+                ..
+            } => TerminatorKind::Goto { target: *target },
+            mir::TerminatorKind::Assert {
+                cond,
+                expected,
+                target,
+                ..
+            } => TerminatorKind::Assert {
+                cond: self.lower_operand(cond)?,
+                expected: *expected,
+                target: *target,
+            },
+            mir::TerminatorKind::Unreachable
             | mir::TerminatorKind::Abort
-            | mir::TerminatorKind::Unreachable
-            | mir::TerminatorKind::DropAndReplace { .. }
-            | mir::TerminatorKind::Assert { .. }
-            | mir::TerminatorKind::Yield { .. }
+            | mir::TerminatorKind::Resume => TerminatorKind::Unreachable,
+            mir::TerminatorKind::Yield { .. }
             | mir::TerminatorKind::GeneratorDrop
-            | mir::TerminatorKind::FalseEdge { .. }
-            | mir::TerminatorKind::FalseUnwind { .. }
             | mir::TerminatorKind::InlineAsm { .. } => {
-                self.tcx.sess.span_err(
-                    terminator.source_info.span,
-                    &format!("unsupported terminator kind: {:?}", terminator.kind),
-                );
-                return Err(ErrorReported);
+                return Err(UnsupportedErr::new(
+                    Some(terminator.source_info.span),
+                    format!("unsupported terminator kind: {:?}", terminator.kind),
+                ));
             }
         };
         Ok(Terminator {
@@ -158,11 +436,73 @@ impl<'tcx> LoweringCtxt<'tcx> {
         })
     }
 
+    /// Lowers a call to a method of one of the `Fn*` traits. The arguments of the call are
+    /// passed as a tuple which we spread into one operand per argument.
+    fn lower_fn_trait_call(
+        &self,
+        substs: SubstsRef<'tcx>,
+        args: &[mir::Operand<'tcx>],
+        destination: &(mir::Place<'tcx>, mir::BasicBlock),
+    ) -> Result<TerminatorKind, UnsupportedErr> {
+        let (callee, tupled_args) = match args {
+            [callee, tupled_args] => (callee, tupled_args),
+            _ => unreachable!("unexpected number of arguments for a `Fn*` trait method"),
+        };
+        let tys = match tupled_args.ty(self.body, self.tcx).kind() {
+            rustc_middle::ty::TyKind::Tuple(tys) => tys,
+            _ => unreachable!("arguments are not tupled"),
+        };
+        let args = tys
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| {
+                let field = |place: &mir::Place<'tcx>| {
+                    self.tcx
+                        .mk_place_field(*place, mir::Field::from_usize(i), ty.expect_ty())
+                };
+                let arg = match tupled_args {
+                    mir::Operand::Copy(place) => mir::Operand::Copy(field(place)),
+                    mir::Operand::Move(place) => mir::Operand::Move(field(place)),
+                    mir::Operand::Constant(c) => {
+                        return Err(UnsupportedErr::new(
+                            Some(c.span),
+                            "unsupported constant arguments",
+                        ));
+                    }
+                };
+                self.lower_operand(&arg)
+            })
+            .try_collect()?;
+
+        let upvars_or_callee = self.lower_operand(callee)?;
+        let destination = (self.lower_place(&destination.0)?, destination.1);
+        match substs.type_at(0).kind() {
+            rustc_middle::ty::TyKind::Closure(did, _) => Ok(TerminatorKind::CallClosure {
+                closure: *did,
+                upvars: upvars_or_callee,
+                args,
+                destination,
+            }),
+            _ => Ok(TerminatorKind::CallIndirect {
+                callee: upvars_or_callee,
+                args,
+                destination,
+            }),
+        }
+    }
+
+    fn is_fn_trait_method(&self, def_id: DefId) -> bool {
+        self.tcx
+            .trait_of_item(def_id)
+            .and_then(|trait_id| self.tcx.fn_trait_kind_from_lang_item(trait_id))
+            .is_some()
+    }
+
     fn lower_rvalue(
         &self,
         rvalue: &mir::Rvalue<'tcx>,
         source_info: mir::SourceInfo,
-    ) -> Result<Rvalue, ErrorReported> {
+    ) -> Result<Rvalue, UnsupportedErr> {
         match rvalue {
             mir::Rvalue::Use(op) => Ok(Rvalue::Use(self.lower_operand(op)?)),
             mir::Rvalue::BinaryOp(bin_op, operands) => Ok(Rvalue::BinaryOp(
@@ -170,31 +510,96 @@ impl<'tcx> LoweringCtxt<'tcx> {
                 self.lower_operand(&operands.0)?,
                 self.lower_operand(&operands.1)?,
             )),
+            mir::Rvalue::CheckedBinaryOp(bin_op, operands) => Ok(Rvalue::CheckedBinaryOp(
+                self.lower_bin_op(*bin_op)?,
+                self.lower_operand(&operands.0)?,
+                self.lower_operand(&operands.1)?,
+            )),
             mir::Rvalue::Ref(_, mir::BorrowKind::Mut { .. }, p) => {
                 Ok(Rvalue::MutRef(self.lower_place(p)?))
             }
+            mir::Rvalue::Ref(_, mir::BorrowKind::Shared, p) => {
+                Ok(Rvalue::ShrRef(self.lower_place(p)?))
+            }
+            mir::Rvalue::Len(p) => Ok(Rvalue::Len(self.lower_place(p)?)),
+            mir::Rvalue::Repeat(op, len) => {
+                let ty = lower_ty_with(self.tcx, op.ty(self.body, self.tcx), infer_pred)?;
+                let len = len.eval_usize(self.tcx, ParamEnv::reveal_all());
+                Ok(Rvalue::Repeat(self.lower_operand(op)?, ty, len))
+            }
             mir::Rvalue::UnaryOp(un_op, op) => Ok(Rvalue::UnaryOp(*un_op, self.lower_operand(op)?)),
-            mir::Rvalue::Repeat(_, _)
-            | mir::Rvalue::Ref(_, _, _)
+            mir::Rvalue::Aggregate(aggregate_kind, args) => {
+                let aggregate_kind = self.lower_aggregate_kind(aggregate_kind, source_info)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.lower_operand(arg))
+                    .try_collect()?;
+                Ok(Rvalue::Aggregate(aggregate_kind, args))
+            }
+            mir::Rvalue::Cast(mir::CastKind::Pointer(cast), op, ty) => {
+                match (cast, op.ty(self.body, self.tcx).kind()) {
+                    (
+                        PointerCast::ReifyFnPointer,
+                        rustc_middle::ty::TyKind::FnDef(fn_def, substs),
+                    ) => Ok(Rvalue::FnPointer(
+                        *fn_def,
+                        substs
+                            .iter()
+                            .map(|arg| self.lower_generic_arg(arg))
+                            .try_collect()?,
+                        lower_ty_with(self.tcx, ty, infer_pred)?,
+                    )),
+                    (
+                        PointerCast::ClosureFnPointer(_),
+                        rustc_middle::ty::TyKind::Closure(did, _),
+                    ) => Ok(Rvalue::ClosureFnPointer(*did, self.lower_operand(op)?)),
+                    _ => Err(UnsupportedErr::new(
+                        Some(source_info.span),
+                        format!("unsupported rvalue: `{:?}`", rvalue),
+                    )),
+                }
+            }
+            mir::Rvalue::Ref(_, _, _)
             | mir::Rvalue::ThreadLocalRef(_)
             | mir::Rvalue::AddressOf(_, _)
-            | mir::Rvalue::Len(_)
             | mir::Rvalue::Cast(_, _, _)
-            | mir::Rvalue::CheckedBinaryOp(_, _)
             | mir::Rvalue::NullaryOp(_, _)
             | mir::Rvalue::Discriminant(_)
-            | mir::Rvalue::Aggregate(_, _)
-            | mir::Rvalue::ShallowInitBox(_, _) => {
-                self.tcx.sess.span_err(
-                    source_info.span,
-                    &format!("unsupported rvalue: `{:?}`", rvalue),
-                );
-                Err(ErrorReported)
+            | mir::Rvalue::ShallowInitBox(_, _) => Err(UnsupportedErr::new(
+                Some(source_info.span),
+                format!("unsupported rvalue: `{:?}`", rvalue),
+            )),
+        }
+    }
+
+    fn lower_aggregate_kind(
+        &self,
+        aggregate_kind: &mir::AggregateKind<'tcx>,
+        source_info: mir::SourceInfo,
+    ) -> Result<AggregateKind, UnsupportedErr> {
+        match aggregate_kind {
+            mir::AggregateKind::Tuple => Ok(AggregateKind::Tuple),
+            mir::AggregateKind::Adt(adt_def, _, substs, _, None) if adt_def.is_struct() => {
+                self.lower_adt_fields(adt_def)?;
+                let substs = substs
+                    .iter()
+                    .map(|arg| lower_generic_arg_with(self.tcx, arg, default_pred))
+                    .try_collect()?;
+                Ok(AggregateKind::Adt(adt_def.did, substs))
             }
+            mir::AggregateKind::Array(ty) => Ok(AggregateKind::Array(lower_ty_with(
+                self.tcx, ty, infer_pred,
+            )?)),
+            mir::AggregateKind::Adt(..)
+            | mir::AggregateKind::Closure(_, _)
+            | mir::AggregateKind::Generator(_, _, _) => Err(UnsupportedErr::new(
+                Some(source_info.span),
+                format!("unsupported aggregate kind: `{:?}`", aggregate_kind),
+            )),
         }
     }
 
-    fn lower_bin_op(&self, bin_op: mir::BinOp) -> Result<BinOp, ErrorReported> {
+    fn lower_bin_op(&self, bin_op: mir::BinOp) -> Result<BinOp, UnsupportedErr> {
         match bin_op {
             mir::BinOp::Add => Ok(BinOp::Add),
             mir::BinOp::Sub => Ok(BinOp::Sub),
@@ -212,16 +617,14 @@ impl<'tcx> LoweringCtxt<'tcx> {
             | mir::BinOp::Shl
             | mir::BinOp::Shr
             | mir::BinOp::Ge
-            | mir::BinOp::Offset => {
-                self.tcx
-                    .sess
-                    .err(&format!("unsupported binary operation: `{:?}`", bin_op));
-                Err(ErrorReported)
-            }
+            | mir::BinOp::Offset => Err(UnsupportedErr::new(
+                None,
+                format!("unsupported binary operation: `{:?}`", bin_op),
+            )),
         }
     }
 
-    fn lower_operand(&self, op: &mir::Operand<'tcx>) -> Result<Operand, ErrorReported> {
+    fn lower_operand(&self, op: &mir::Operand<'tcx>) -> Result<Operand, UnsupportedErr> {
         match op {
             mir::Operand::Copy(place) => Ok(Operand::Copy(self.lower_place(place)?)),
             mir::Operand::Move(place) => Ok(Operand::Move(self.lower_place(place)?)),
@@ -229,16 +632,97 @@ impl<'tcx> LoweringCtxt<'tcx> {
         }
     }
 
-    fn lower_place(&self, place: &mir::Place<'tcx>) -> Result<Place, ErrorReported> {
+    /// The values of a switch on a signed integer are sign-extended to 128 bits.
+    fn lower_switch_targets(
+        &self,
+        switch_ty: rustc_middle::ty::Ty<'tcx>,
+        targets: &mir::SwitchTargets,
+    ) -> mir::SwitchTargets {
+        if !switch_ty.is_signed() {
+            return targets.clone();
+        }
+        let size = self
+            .tcx
+            .layout_of(
+                ParamEnv::empty()
+                    .with_reveal_all_normalized(self.tcx)
+                    .and(switch_ty),
+            )
+            .unwrap()
+            .size;
+        mir::SwitchTargets::new(
+            targets
+                .iter()
+                .map(|(bits, bb)| (size.sign_extend(bits), bb)),
+            targets.otherwise(),
+        )
+    }
+
+    /// Whether `func` is `Index::index` or `IndexMut::index_mut`, or one of their implementations.
+    fn is_index_method(&self, func: DefId) -> bool {
+        let tcx = self.tcx;
+        // Calls through a trait refer to the method in the trait rather than in the impl.
+        let trait_id = tcx.trait_of_item(func).or_else(|| {
+            tcx.impl_of_method(func)
+                .and_then(|impl_id| tcx.trait_id_of_impl(impl_id))
+        });
+        let lang_items = tcx.lang_items();
+        trait_id.is_some()
+            && (trait_id == lang_items.index_trait() || trait_id == lang_items.index_mut_trait())
+    }
+
+    /// An indexing operation panics when the index is out of bounds. If the implementation called
+    /// has a signature, its preconditions are the panic condition. Otherwise we cannot prove that
+    /// the call doesn't panic, so it is required to be unreachable.
+    fn lower_index_call(
+        &self,
+        func: DefId,
+        substs: SubstsRef<'tcx>,
+        args: &[mir::Operand<'tcx>],
+        place: &mir::Place<'tcx>,
+        target: mir::BasicBlock,
+    ) -> Result<TerminatorKind, UnsupportedErr> {
+        let param_env = self.tcx.param_env(self.body.source.def_id());
+        match Instance::resolve(self.tcx, param_env, func, substs) {
+            Ok(Some(instance)) if self.fns_with_sig.contains(&instance.def_id()) => {
+                Ok(TerminatorKind::Call {
+                    func: instance.def_id(),
+                    substs: instance
+                        .substs
+                        .iter()
+                        .map(|arg| self.lower_generic_arg(arg))
+                        .try_collect()?,
+                    destination: (self.lower_place(place)?, target),
+                    args: args
+                        .iter()
+                        .map(|arg| self.lower_operand(arg))
+                        .try_collect()?,
+                    panics: true,
+                })
+            }
+            _ => self.lower_diverge(func, substs, args),
+        }
+    }
+
+    fn lower_place(&self, place: &mir::Place<'tcx>) -> Result<Place, UnsupportedErr> {
         let mut projection = vec![];
+        let mut place_ty = PlaceTy::from_ty(self.body.local_decls[place.local].ty);
         for elem in place.projection {
             match elem {
                 mir::PlaceElem::Deref => projection.push(PlaceElem::Deref),
+                mir::PlaceElem::Field(field, _) => {
+                    let (aggregate_kind, fields) = self.lower_aggregate_ty(place_ty.ty)?;
+                    projection.push(PlaceElem::Field(field, aggregate_kind, fields));
+                }
+                mir::PlaceElem::Index(local) => projection.push(PlaceElem::Index(local)),
+                mir::PlaceElem::ConstantIndex {
+                    offset, from_end, ..
+                } => projection.push(PlaceElem::ConstantIndex { offset, from_end }),
                 _ => {
-                    self.tcx.sess.err("place not supported");
-                    return Err(ErrorReported);
+                    return Err(UnsupportedErr::new(None, "place not supported"));
                 }
             }
+            place_ty = place_ty.projection_ty(self.tcx, elem);
         }
         Ok(Place {
             local: place.local,
@@ -246,7 +730,7 @@ impl<'tcx> LoweringCtxt<'tcx> {
         })
     }
 
-    fn lower_constant(&self, c: &mir::Constant<'tcx>) -> Result<Constant, ErrorReported> {
+    fn lower_constant(&self, c: &mir::Constant<'tcx>) -> Result<Constant, UnsupportedErr> {
         use rustc_middle::ty::{Const, ConstKind, TyKind};
         let tcx = self.tcx;
         match &c.literal {
@@ -257,77 +741,247 @@ impl<'tcx> LoweringCtxt<'tcx> {
                 (TyKind::Int(int_ty), Some(bits)) => Ok(Constant::Int(bits as i128, *int_ty)),
                 (TyKind::Uint(uint_ty), Some(bits)) => Ok(Constant::Uint(bits, *uint_ty)),
                 (TyKind::Bool, Some(bits)) => Ok(Constant::Bool(bits != 0)),
-                _ => {
-                    self.tcx.sess.span_err(
-                        c.span,
-                        &format!("constant not supported: `{:?}`", c.literal),
-                    );
-                    Err(ErrorReported)
-                }
+                _ => Err(UnsupportedErr::new(
+                    Some(c.span),
+                    format!("constant not supported: `{:?}`", c.literal),
+                )),
             },
-            _ => {
-                self.tcx.sess.span_err(
-                    c.span,
-                    &format!("constant not supported: `{:?}`", c.literal),
-                );
-                Err(ErrorReported)
-            }
+            _ => Err(UnsupportedErr::new(
+                Some(c.span),
+                format!("constant not supported: `{:?}`", c.literal),
+            )),
         }
     }
 
-    fn lower_generic_arg(
+    /// Returns the kind of an aggregate type (a tuple or a struct) together with the types of
+    /// its fields. Fields are not refined so they are given a trivial refinement, and the ones of
+    /// a struct are given in terms of its generic parameters. Closures are tuples of their
+    /// captured variables.
+    fn lower_aggregate_ty(
         &self,
-        arg: rustc_middle::ty::subst::GenericArg,
-    ) -> Result<core::ty::Ty, ErrorReported> {
-        match arg.unpack() {
-            GenericArgKind::Type(ty) => self.lower_ty(ty),
-            GenericArgKind::Const(_) | GenericArgKind::Lifetime(_) => {
-                self.tcx
-                    .sess
-                    .err(&format!("unsupported generic argument: `{:?}`", arg));
-                Err(ErrorReported)
-            }
-        }
-    }
-
-    fn lower_ty(&self, ty: rustc_middle::ty::Ty) -> Result<core::ty::Ty, ErrorReported> {
-        use liquid_rust_core::ty as core;
+        ty: rustc_middle::ty::Ty<'tcx>,
+    ) -> Result<(AggregateKind, Vec<core::ty::Ty>), UnsupportedErr> {
         match ty.kind() {
-            rustc_middle::ty::TyKind::Bool => {
-                Ok(core::Ty::Exists(core::BaseTy::Bool, core::Pred::Infer))
+            rustc_middle::ty::TyKind::Tuple(tys) => {
+                let fields = tys
+                    .iter()
+                    .map(|ty| lower_ty_with(self.tcx, ty.expect_ty(), default_pred))
+                    .try_collect()?;
+                Ok((AggregateKind::Tuple, fields))
             }
-            rustc_middle::ty::TyKind::Int(int_ty) => Ok(core::Ty::Exists(
-                core::BaseTy::Int(*int_ty),
-                core::Pred::Infer,
-            )),
-            rustc_middle::ty::TyKind::Uint(uint_ty) => Ok(core::Ty::Exists(
-                core::BaseTy::Uint(*uint_ty),
-                core::Pred::Infer,
-            )),
-            rustc_middle::ty::TyKind::Param(param) => Ok(core::Ty::Param(core::ParamTy {
-                index: param.index,
-                name: param.name,
-            })),
-            rustc_middle::ty::TyKind::Adt(adt_def, substs) => {
+            rustc_middle::ty::TyKind::Adt(adt_def, substs) if adt_def.is_struct() => {
+                let fields = self.lower_adt_fields(adt_def)?;
                 let substs = substs
                     .iter()
-                    .map(|arg| self.lower_generic_arg(arg))
+                    .map(|arg| lower_generic_arg_with(self.tcx, arg, default_pred))
                     .try_collect()?;
-                let adt = core::BaseTy::Adt(adt_def.did, substs);
-                Ok(core::Ty::Exists(adt, core::Pred::Infer))
+                Ok((AggregateKind::Adt(adt_def.did, substs), fields))
             }
-            _ => {
-                self.tcx.sess.err(&format!(
-                    "unsupported type `{:?}`, kind: `{:?}`",
-                    ty,
-                    ty.kind()
-                ));
-                Err(ErrorReported)
+            rustc_middle::ty::TyKind::Closure(_, substs) => {
+                let fields = substs
+                    .as_closure()
+                    .upvar_tys()
+                    .map(|ty| lower_ty_with(self.tcx, ty, default_pred))
+                    .try_collect()?;
+                Ok((AggregateKind::Tuple, fields))
             }
+            _ => Err(UnsupportedErr::new(
+                None,
+                format!("unsupported field projection on type `{:?}`", ty),
+            )),
+        }
+    }
+
+    /// Returns the types of the fields of a struct in terms of its generic parameters, recording
+    /// them in the fields of the structs used by the body.
+    fn lower_adt_fields(
+        &self,
+        adt_def: &rustc_middle::ty::AdtDef,
+    ) -> Result<Vec<core::ty::Ty>, UnsupportedErr> {
+        if let Some(fields) = self.adt_fields.borrow().get(&adt_def.did) {
+            return Ok(fields.clone());
         }
+        let fields: Vec<_> = adt_def
+            .all_fields()
+            .map(|field| lower_ty_with(self.tcx, self.tcx.type_of(field.did), default_pred))
+            .try_collect()?;
+        self.adt_fields
+            .borrow_mut()
+            .insert(adt_def.did, fields.clone());
+        Ok(fields)
     }
+
+    /// The arguments of calls to functions without a signature, e.g., the ones called by `panic!`,
+    /// are not lowered as they often have unsupported types.
+    fn lower_diverge(
+        &self,
+        func: DefId,
+        substs: SubstsRef<'tcx>,
+        args: &[mir::Operand<'tcx>],
+    ) -> Result<TerminatorKind, UnsupportedErr> {
+        if !self.fns_with_sig.contains(&func) {
+            return Ok(TerminatorKind::Diverge {
+                func,
+                substs: vec![],
+                args: vec![],
+            });
+        }
+        Ok(TerminatorKind::Diverge {
+            func,
+            substs: substs
+                .iter()
+                .map(|arg| self.lower_generic_arg(arg))
+                .try_collect()?,
+            args: args
+                .iter()
+                .map(|arg| self.lower_operand(arg))
+                .try_collect()?,
+        })
+    }
+
+    fn lower_generic_arg(
+        &self,
+        arg: rustc_middle::ty::subst::GenericArg<'tcx>,
+    ) -> Result<core::ty::Ty, UnsupportedErr> {
+        lower_generic_arg_with(self.tcx, arg, infer_pred)
+    }
+}
+
+/// The methods of `Option` and `Result` that panic on some of their inputs. We cannot prove that
+/// a call to one of them doesn't panic (e.g., we don't reason about enums), so when checking for
+/// panics we require it to be unreachable.
+fn panicking_methods(tcx: TyCtxt) -> FxHashSet<DefId> {
+    let names = [
+        sym::unwrap,
+        sym::expect,
+        Symbol::intern("unwrap_err"),
+        Symbol::intern("expect_err"),
+    ];
+    [sym::Option, sym::Result]
+        .iter()
+        .filter_map(|name| tcx.get_diagnostic_item(*name))
+        .flat_map(|adt_id| tcx.inherent_impls(adt_id))
+        .flat_map(|impl_id| tcx.associated_items(*impl_id).in_definition_order())
+        .filter(|item| item.kind == AssocKind::Fn && names.contains(&item.ident.name))
+        .map(|item| item.def_id)
+        .collect()
+}
+
+fn lower_generic_arg_with<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    arg: rustc_middle::ty::subst::GenericArg<'tcx>,
+    mk_pred: fn() -> core::ty::Pred,
+) -> Result<core::ty::Ty, UnsupportedErr> {
+    match arg.unpack() {
+        GenericArgKind::Type(ty) => lower_ty_with(tcx, ty, mk_pred),
+        GenericArgKind::Const(_) | GenericArgKind::Lifetime(_) => Err(UnsupportedErr::new(
+            None,
+            format!("unsupported generic argument: `{:?}`", arg),
+        )),
+    }
+}
+
+/// Lowers a type refining its base types with `mk_pred`.
+fn lower_ty_with<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    ty: rustc_middle::ty::Ty<'tcx>,
+    mk_pred: fn() -> core::ty::Pred,
+) -> Result<core::ty::Ty, UnsupportedErr> {
+    use liquid_rust_core::ty as core;
+    match ty.kind() {
+        rustc_middle::ty::TyKind::Bool => Ok(core::Ty::Exists(core::BaseTy::Bool, mk_pred())),
+        rustc_middle::ty::TyKind::Int(int_ty) => {
+            Ok(core::Ty::Exists(core::BaseTy::Int(*int_ty), mk_pred()))
+        }
+        rustc_middle::ty::TyKind::Uint(uint_ty) => {
+            Ok(core::Ty::Exists(core::BaseTy::Uint(*uint_ty), mk_pred()))
+        }
+        rustc_middle::ty::TyKind::Never => Ok(core::Ty::Never),
+        rustc_middle::ty::TyKind::Ref(_, ty, rustc_hir::Mutability::Not) => {
+            Ok(core::Ty::Ref(Box::new(lower_ty_with(tcx, ty, mk_pred)?)))
+        }
+        rustc_middle::ty::TyKind::FnPtr(fn_sig) => {
+            let fn_sig = tcx.erase_late_bound_regions(*fn_sig);
+            let args = fn_sig
+                .inputs()
+                .iter()
+                .map(|ty| lower_ty_with(tcx, ty, mk_pred))
+                .try_collect()?;
+            let ret = lower_ty_with(tcx, fn_sig.output(), mk_pred)?;
+            Ok(core::Ty::Fn(args, Box::new(ret)))
+        }
+        // A closure is seen as a function with the signature of the closure, e.g., when it
+        // instantiates a type parameter bounded by one of the `Fn*` traits.
+        rustc_middle::ty::TyKind::Closure(_, substs) => {
+            let sig = tcx.erase_late_bound_regions(substs.as_closure().sig());
+            let args = match sig.inputs()[0].kind() {
+                rustc_middle::ty::TyKind::Tuple(tys) => tys
+                    .iter()
+                    .map(|ty| lower_ty_with(tcx, ty.expect_ty(), mk_pred))
+                    .try_collect()?,
+                _ => unreachable!("closure arguments are not tupled"),
+            };
+            let ret = lower_ty_with(tcx, sig.output(), mk_pred)?;
+            Ok(core::Ty::Fn(args, Box::new(ret)))
+        }
+        rustc_middle::ty::TyKind::Param(param) => Ok(core::Ty::Param(core::ParamTy {
+            index: param.index,
+            name: param.name,
+        })),
+        rustc_middle::ty::TyKind::Adt(adt_def, substs) => {
+            let substs = substs
+                .iter()
+                .map(|arg| lower_generic_arg_with(tcx, arg, mk_pred))
+                .try_collect()?;
+            let adt = core::BaseTy::Adt(adt_def.did, substs);
+            Ok(core::Ty::Exists(adt, mk_pred()))
+        }
+        rustc_middle::ty::TyKind::Tuple(tys) => {
+            let tys = tys
+                .iter()
+                .map(|ty| lower_ty_with(tcx, ty.expect_ty(), mk_pred))
+                .try_collect()?;
+            Ok(core::Ty::Tuple(tys))
+        }
+        rustc_middle::ty::TyKind::Array(ty, len) => {
+            let ty = lower_ty_with(tcx, ty, mk_pred)?;
+            let len = len.eval_usize(tcx, ParamEnv::reveal_all());
+            let len = core::Expr {
+                kind: core::ExprKind::Literal(core::Lit::Int(len as i128)),
+                span: None,
+            };
+            Ok(core::Ty::Refine(core::BaseTy::Array(Box::new(ty)), len))
+        }
+        rustc_middle::ty::TyKind::Slice(ty) => {
+            let ty = lower_ty_with(tcx, ty, mk_pred)?;
+            Ok(core::Ty::Exists(
+                core::BaseTy::Slice(Box::new(ty)),
+                mk_pred(),
+            ))
+        }
+        _ => Err(UnsupportedErr::new(
+            None,
+            format!("unsupported type `{:?}`, kind: `{:?}`", ty, ty.kind()),
+        )),
+    }
+}
+
+fn is_scalar(bty: &core::ty::BaseTy) -> bool {
+    matches!(
+        bty,
+        core::ty::BaseTy::Int(_) | core::ty::BaseTy::Uint(_) | core::ty::BaseTy::Bool
+    )
+}
+
+fn infer_pred() -> core::ty::Pred {
+    core::ty::Pred::Infer(None)
+}
+
+fn default_pred() -> core::ty::Pred {
+    core::ty::Pred::TRUE
 }
 
+/// Returns the bits of `scalar`, sign-extended to 128 bits if `ty` is a signed integer.
 fn scalar_to_bits<'tcx>(
     tcx: TyCtxt<'tcx>,
     scalar: mir::interpret::Scalar,
@@ -337,5 +991,10 @@ fn scalar_to_bits<'tcx>(
         .layout_of(ParamEnv::empty().with_reveal_all_normalized(tcx).and(ty))
         .unwrap()
         .size;
-    scalar.to_bits(size).ok()
+    let bits = scalar.to_bits(size).ok()?;
+    Some(if ty.is_signed() {
+        size.sign_extend(bits)
+    } else {
+        bits
+    })
 }
@@ -0,0 +1,523 @@
+//! Export and import of refined signatures across crates.
+//!
+//! The resolved signatures of a crate are written to a `.lrmeta` file next to its metadata. When
+//! checking a downstream crate the signatures of its dependencies are loaded from these files.
+//! Definitions are identified by their [`DefPathHash`], which is stable across compilation
+//! sessions. A file also records the hash of the crate that wrote it, so a file left over from an
+//! older build of the crate is rejected instead of referring to definitions that no longer exist.
+//! Spans are not exported.
+use std::{fs, io, path::PathBuf};
+
+use liquid_rust_core::ty::{
+    BaseTy, BinOp, Expr, ExprKind, FnSig, Ident, IntTy, Lit, Name, Param, ParamTy, Pred, Sort, Ty,
+    UintTy, Var,
+};
+use rustc_data_structures::fingerprint::Fingerprint;
+use rustc_hash::{FxHashMap, FxHashSet};
+use rustc_hir::{
+    def_id::{CrateNum, DefId, LOCAL_CRATE},
+    definitions::DefPathHash,
+};
+use rustc_middle::ty::TyCtxt;
+use rustc_serialize::{
+    opaque::{self, EncodeResult},
+    Decoder as _, Encoder as _,
+};
+use rustc_span::{Symbol, DUMMY_SP};
+
+const MAGIC: &[u8] = b"LRMETA";
+const VERSION: u32 = 4;
+
+type DecodeResult<T> = Result<T, String>;
+
+/// Writes the signatures in `fn_sigs` next to the metadata of the local crate.
+pub fn export<'a>(
+    tcx: TyCtxt,
+    fn_sigs: impl ExactSizeIterator<Item = (DefId, &'a FnSig)>,
+) -> io::Result<()> {
+    let outputs = tcx.output_filenames(());
+    let crate_name = tcx.crate_name(LOCAL_CRATE);
+    let path =
+        rustc_session::output::filename_for_metadata(tcx.sess, &crate_name.as_str(), &outputs)
+            .with_extension("lrmeta");
+
+    let mut encoder = Encoder {
+        tcx,
+        opaque: opaque::Encoder::new(vec![]),
+    };
+    // The opaque encoder writes to memory, so its error type is uninhabited.
+    encoder
+        .encode_fn_sigs(fn_sigs)
+        .map_err(|err| -> io::Error { match err {} })?;
+    fs::write(path, encoder.opaque.into_inner())
+}
+
+/// Loads the signatures exported by the dependencies of the local crate. Dependencies that were
+/// not checked don't have a `.lrmeta` file and are skipped.
+pub fn import(tcx: TyCtxt) -> Result<FxHashMap<DefId, FnSig>, String> {
+    let mut fn_sigs = FxHashMap::default();
+    for &cnum in tcx.crates(()) {
+        let path = if let Some(path) = lrmeta_path(tcx.used_crate_source(cnum).paths().next()) {
+            path
+        } else {
+            continue;
+        };
+        let data = fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let mut decoder = Decoder::new(tcx, &data);
+        decoder
+            .decode_fn_sigs(cnum, &mut fn_sigs)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    Ok(fn_sigs)
+}
+
+fn lrmeta_path(crate_path: Option<&PathBuf>) -> Option<PathBuf> {
+    let path = crate_path?.with_extension("lrmeta");
+    path.exists().then(|| path)
+}
+
+struct Encoder<'tcx> {
+    tcx: TyCtxt<'tcx>,
+    opaque: opaque::Encoder,
+}
+
+struct Decoder<'a, 'tcx> {
+    tcx: TyCtxt<'tcx>,
+    opaque: opaque::Decoder<'a>,
+    /// The crates in the crate graph, used to reject hashes of definitions that are not loaded.
+    crates: FxHashSet<u64>,
+}
+
+impl Encoder<'_> {
+    fn encode_fn_sigs<'a>(
+        &mut self,
+        fn_sigs: impl ExactSizeIterator<Item = (DefId, &'a FnSig)>,
+    ) -> EncodeResult {
+        self.opaque.emit_raw_bytes(MAGIC)?;
+        self.opaque.emit_u32(VERSION)?;
+        self.opaque
+            .emit_u64(self.tcx.crate_hash(LOCAL_CRATE).as_u64())?;
+        self.opaque.emit_usize(fn_sigs.len())?;
+        for (def_id, fn_sig) in fn_sigs {
+            self.encode_def_id(def_id)?;
+            self.encode_fn_sig(fn_sig)?;
+        }
+        Ok(())
+    }
+
+    fn encode_fn_sig(&mut self, fn_sig: &FnSig) -> EncodeResult {
+        self.encode_seq(&fn_sig.params, Self::encode_param)?;
+        self.encode_seq(&fn_sig.requires, Self::encode_loc_ty)?;
+        self.encode_seq(&fn_sig.args, Self::encode_ty)?;
+        self.encode_ty(&fn_sig.ret)?;
+        self.encode_seq(&fn_sig.ensures, Self::encode_loc_ty)
+    }
+
+    fn encode_param(&mut self, param: &Param) -> EncodeResult {
+        self.encode_ident(&param.name)?;
+        self.encode_sort(param.sort)?;
+        self.encode_pred(&param.pred)
+    }
+
+    fn encode_pred(&mut self, pred: &Pred) -> EncodeResult {
+        match pred {
+            Pred::Infer(_) => self.opaque.emit_u8(0),
+            Pred::Expr(e) => {
+                self.opaque.emit_u8(1)?;
+                self.encode_expr(e)
+            }
+            Pred::KVar(..) => unreachable!("inferred signatures are not exported"),
+        }
+    }
+
+    fn encode_loc_ty(&mut self, (name, ty): &(Name, Ty)) -> EncodeResult {
+        self.encode_name(*name)?;
+        self.encode_ty(ty)
+    }
+
+    fn encode_ty(&mut self, ty: &Ty) -> EncodeResult {
+        match ty {
+            Ty::Refine(bty, e) => {
+                self.opaque.emit_u8(0)?;
+                self.encode_base_ty(bty)?;
+                self.encode_expr(e)
+            }
+            Ty::Exists(bty, pred) => {
+                self.opaque.emit_u8(1)?;
+                self.encode_base_ty(bty)?;
+                self.encode_pred(pred)
+            }
+            Ty::Tuple(tys) => {
+                self.opaque.emit_u8(2)?;
+                self.encode_seq(tys, Self::encode_ty)
+            }
+            Ty::Ref(ty) => {
+                self.opaque.emit_u8(3)?;
+                self.encode_ty(ty)
+            }
+            Ty::MutRef(name) => {
+                self.opaque.emit_u8(4)?;
+                self.encode_name(*name)
+            }
+            Ty::Param(param_ty) => {
+                self.opaque.emit_u8(5)?;
+                self.opaque.emit_u32(param_ty.index)?;
+                self.opaque.emit_str(&param_ty.name.as_str())
+            }
+            Ty::Never => self.opaque.emit_u8(6),
+            Ty::Fn(args, ret) => {
+                self.opaque.emit_u8(7)?;
+                self.encode_seq(args, Self::encode_ty)?;
+                self.encode_ty(ret)
+            }
+        }
+    }
+
+    fn encode_base_ty(&mut self, bty: &BaseTy) -> EncodeResult {
+        match bty {
+            BaseTy::Int(int_ty) => {
+                self.opaque.emit_u8(0)?;
+                self.opaque.emit_u8(*int_ty as u8)
+            }
+            BaseTy::Uint(uint_ty) => {
+                self.opaque.emit_u8(1)?;
+                self.opaque.emit_u8(*uint_ty as u8)
+            }
+            BaseTy::Bool => self.opaque.emit_u8(2),
+            BaseTy::Adt(def_id, substs) => {
+                self.opaque.emit_u8(3)?;
+                self.encode_def_id(*def_id)?;
+                self.encode_seq(substs, Self::encode_ty)
+            }
+            BaseTy::Array(ty) => {
+                self.opaque.emit_u8(4)?;
+                self.encode_ty(ty)
+            }
+            BaseTy::Slice(ty) => {
+                self.opaque.emit_u8(5)?;
+                self.encode_ty(ty)
+            }
+            BaseTy::Param(param_ty) => {
+                self.opaque.emit_u8(6)?;
+                self.opaque.emit_u32(param_ty.index)?;
+                self.opaque.emit_str(&param_ty.name.as_str())
+            }
+        }
+    }
+
+    fn encode_expr(&mut self, expr: &Expr) -> EncodeResult {
+        match &expr.kind {
+            ExprKind::Var(var, symbol, _) => {
+                self.opaque.emit_u8(0)?;
+                match var {
+                    Var::Bound => self.opaque.emit_u8(0)?,
+                    Var::Free(name) => {
+                        self.opaque.emit_u8(1)?;
+                        self.encode_name(*name)?;
+                    }
+                }
+                self.opaque.emit_str(&symbol.as_str())
+            }
+            ExprKind::Literal(Lit::Int(n)) => {
+                self.opaque.emit_u8(1)?;
+                self.opaque.emit_i128(*n)
+            }
+            ExprKind::Literal(Lit::Bool(b)) => {
+                self.opaque.emit_u8(2)?;
+                self.opaque.emit_bool(*b)
+            }
+            ExprKind::BinaryOp(op, e1, e2) => {
+                self.opaque.emit_u8(3)?;
+                self.opaque.emit_u8(*op as u8)?;
+                self.encode_expr(e1)?;
+                self.encode_expr(e2)
+            }
+            ExprKind::App(var, symbol, args) => {
+                self.opaque.emit_u8(4)?;
+                self.encode_expr(&Expr {
+                    kind: ExprKind::Var(*var, *symbol, DUMMY_SP),
+                    span: None,
+                })?;
+                self.encode_seq(args, Self::encode_expr)
+            }
+        }
+    }
+
+    fn encode_ident(&mut self, ident: &Ident) -> EncodeResult {
+        self.encode_name(ident.name)?;
+        self.opaque.emit_str(&ident.source_info.1.as_str())
+    }
+
+    fn encode_name(&mut self, name: Name) -> EncodeResult {
+        self.opaque.emit_u32(name.as_u32())
+    }
+
+    fn encode_sort(&mut self, sort: Sort) -> EncodeResult {
+        match sort {
+            Sort::Int => self.opaque.emit_u8(0),
+            Sort::Bool => self.opaque.emit_u8(1),
+            Sort::Pred => self.opaque.emit_u8(2),
+        }
+    }
+
+    fn encode_def_id(&mut self, def_id: DefId) -> EncodeResult {
+        let (hi, lo) = self.tcx.def_path_hash(def_id).0.as_value();
+        self.opaque.emit_u64(hi)?;
+        self.opaque.emit_u64(lo)
+    }
+
+    fn encode_seq<T>(
+        &mut self,
+        items: &[T],
+        mut f: impl FnMut(&mut Self, &T) -> EncodeResult,
+    ) -> EncodeResult {
+        self.opaque.emit_usize(items.len())?;
+        for item in items {
+            f(self, item)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'tcx> Decoder<'a, 'tcx> {
+    fn new(tcx: TyCtxt<'tcx>, data: &'a [u8]) -> Self {
+        let crates = tcx
+            .crates(())
+            .iter()
+            .map(|cnum| tcx.stable_crate_id(*cnum).to_u64())
+            .collect();
+        Decoder {
+            tcx,
+            opaque: opaque::Decoder::new(data, 0),
+            crates,
+        }
+    }
+
+    /// Decodes the signatures exported by the crate `cnum`.
+    fn decode_fn_sigs(
+        &mut self,
+        cnum: CrateNum,
+        fn_sigs: &mut FxHashMap<DefId, FnSig>,
+    ) -> DecodeResult<()> {
+        let mut magic = [0; MAGIC.len()];
+        self.opaque.read_raw_bytes_into(&mut magic)?;
+        if magic != MAGIC {
+            return Err("not a liquid-rust metadata file".to_string());
+        }
+        let version = self.opaque.read_u32()?;
+        if version != VERSION {
+            return Err(format!("unsupported metadata version {}", version));
+        }
+        // The definitions are looked up by their hash, which only succeeds if they still exist, so
+        // the file must come from the build of the crate we are linking against.
+        if self.opaque.read_u64()? != self.tcx.crate_hash(cnum).as_u64() {
+            return Err(format!(
+                "stale liquid metadata, rebuild `{}`",
+                self.tcx.crate_name(cnum)
+            ));
+        }
+        for _ in 0..self.opaque.read_usize()? {
+            let def_id = self.decode_def_id()?;
+            let fn_sig = self.decode_fn_sig()?;
+            fn_sigs.insert(def_id, fn_sig);
+        }
+        Ok(())
+    }
+
+    fn decode_fn_sig(&mut self) -> DecodeResult<FnSig> {
+        Ok(FnSig {
+            params: self.decode_seq(Self::decode_param)?,
+            requires: self.decode_seq(Self::decode_loc_ty)?,
+            args: self.decode_seq(Self::decode_ty)?,
+            ret: self.decode_ty()?,
+            ensures: self.decode_seq(Self::decode_loc_ty)?,
+        })
+    }
+
+    fn decode_param(&mut self) -> DecodeResult<Param> {
+        Ok(Param {
+            name: self.decode_ident()?,
+            sort: self.decode_sort()?,
+            pred: self.decode_pred()?,
+        })
+    }
+
+    fn decode_pred(&mut self) -> DecodeResult<Pred> {
+        match self.opaque.read_u8()? {
+            0 => Ok(Pred::Infer(None)),
+            1 => Ok(Pred::Expr(self.decode_expr()?)),
+            tag => Err(format!("invalid predicate tag {}", tag)),
+        }
+    }
+
+    fn decode_loc_ty(&mut self) -> DecodeResult<(Name, Ty)> {
+        Ok((self.decode_name()?, self.decode_ty()?))
+    }
+
+    fn decode_ty(&mut self) -> DecodeResult<Ty> {
+        let ty = match self.opaque.read_u8()? {
+            0 => Ty::Refine(self.decode_base_ty()?, self.decode_expr()?),
+            1 => {
+                let bty = self.decode_base_ty()?;
+                Ty::Exists(bty, self.decode_pred()?)
+            }
+            2 => Ty::Tuple(self.decode_seq(Self::decode_ty)?),
+            3 => Ty::Ref(Box::new(self.decode_ty()?)),
+            4 => Ty::MutRef(self.decode_name()?),
+            5 => {
+                let index = self.opaque.read_u32()?;
+                let name = self.decode_symbol()?;
+                Ty::Param(ParamTy { index, name })
+            }
+            6 => Ty::Never,
+            7 => {
+                let args = self.decode_seq(Self::decode_ty)?;
+                Ty::Fn(args, Box::new(self.decode_ty()?))
+            }
+            tag => return Err(format!("invalid type tag {}", tag)),
+        };
+        Ok(ty)
+    }
+
+    fn decode_base_ty(&mut self) -> DecodeResult<BaseTy> {
+        let bty = match self.opaque.read_u8()? {
+            0 => BaseTy::Int(decode_int_ty(self.opaque.read_u8()?)?),
+            1 => BaseTy::Uint(decode_uint_ty(self.opaque.read_u8()?)?),
+            2 => BaseTy::Bool,
+            3 => {
+                let def_id = self.decode_def_id()?;
+                BaseTy::Adt(def_id, self.decode_seq(Self::decode_ty)?)
+            }
+            4 => BaseTy::Array(Box::new(self.decode_ty()?)),
+            5 => BaseTy::Slice(Box::new(self.decode_ty()?)),
+            6 => {
+                let index = self.opaque.read_u32()?;
+                let name = self.decode_symbol()?;
+                BaseTy::Param(ParamTy { index, name })
+            }
+            tag => return Err(format!("invalid base type tag {}", tag)),
+        };
+        Ok(bty)
+    }
+
+    fn decode_expr(&mut self) -> DecodeResult<Expr> {
+        let kind = match self.opaque.read_u8()? {
+            0 => {
+                let var = match self.opaque.read_u8()? {
+                    0 => Var::Bound,
+                    1 => Var::Free(self.decode_name()?),
+                    tag => return Err(format!("invalid variable tag {}", tag)),
+                };
+                ExprKind::Var(var, self.decode_symbol()?, DUMMY_SP)
+            }
+            1 => ExprKind::Literal(Lit::Int(self.opaque.read_i128()?)),
+            2 => ExprKind::Literal(Lit::Bool(self.opaque.read_bool()?)),
+            3 => {
+                let op = decode_bin_op(self.opaque.read_u8()?)?;
+                let e1 = self.decode_expr()?;
+                let e2 = self.decode_expr()?;
+                ExprKind::BinaryOp(op, Box::new(e1), Box::new(e2))
+            }
+            4 => {
+                let (var, symbol) = match self.decode_expr()?.kind {
+                    ExprKind::Var(var, symbol, _) => (var, symbol),
+                    _ => return Err("invalid abstract refinement".to_string()),
+                };
+                let args = self.decode_seq(Self::decode_expr)?;
+                ExprKind::App(var, symbol, args.into())
+            }
+            tag => return Err(format!("invalid expression tag {}", tag)),
+        };
+        Ok(Expr { kind, span: None })
+    }
+
+    fn decode_ident(&mut self) -> DecodeResult<Ident> {
+        Ok(Ident {
+            name: self.decode_name()?,
+            source_info: (DUMMY_SP, self.decode_symbol()?),
+        })
+    }
+
+    fn decode_name(&mut self) -> DecodeResult<Name> {
+        Ok(Name::from_u32(self.opaque.read_u32()?))
+    }
+
+    fn decode_sort(&mut self) -> DecodeResult<Sort> {
+        match self.opaque.read_u8()? {
+            0 => Ok(Sort::Int),
+            1 => Ok(Sort::Bool),
+            2 => Ok(Sort::Pred),
+            tag => Err(format!("invalid sort tag {}", tag)),
+        }
+    }
+
+    fn decode_symbol(&mut self) -> DecodeResult<Symbol> {
+        Ok(Symbol::intern(&self.opaque.read_str()?))
+    }
+
+    /// Decodes a definition by its hash. Looking up the hash cannot fail: the crate hash checked
+    /// in `decode_fn_sigs` guarantees the definitions referred to by the file still exist.
+    fn decode_def_id(&mut self) -> DecodeResult<DefId> {
+        let hi = self.opaque.read_u64()?;
+        let lo = self.opaque.read_u64()?;
+        let hash = DefPathHash(Fingerprint::new(hi, lo));
+        if !self.crates.contains(&hash.stable_crate_id().to_u64()) {
+            return Err("reference to a crate that is not loaded".to_string());
+        }
+        Ok(self.tcx.def_path_hash_to_def_id(hash))
+    }
+
+    fn decode_seq<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> DecodeResult<T>,
+    ) -> DecodeResult<Vec<T>> {
+        (0..self.opaque.read_usize()?).map(|_| f(self)).collect()
+    }
+}
+
+fn decode_int_ty(tag: u8) -> DecodeResult<IntTy> {
+    [
+        IntTy::Isize,
+        IntTy::I8,
+        IntTy::I16,
+        IntTy::I32,
+        IntTy::I64,
+        IntTy::I128,
+    ]
+    .into_iter()
+    .find(|int_ty| *int_ty as u8 == tag)
+    .ok_or_else(|| format!("invalid integer type tag {}", tag))
+}
+
+fn decode_uint_ty(tag: u8) -> DecodeResult<UintTy> {
+    [
+        UintTy::Usize,
+        UintTy::U8,
+        UintTy::U16,
+        UintTy::U32,
+        UintTy::U64,
+        UintTy::U128,
+    ]
+    .into_iter()
+    .find(|uint_ty| *uint_ty as u8 == tag)
+    .ok_or_else(|| format!("invalid unsigned integer type tag {}", tag))
+}
+
+fn decode_bin_op(tag: u8) -> DecodeResult<BinOp> {
+    [
+        BinOp::Iff,
+        BinOp::Imp,
+        BinOp::Or,
+        BinOp::And,
+        BinOp::Eq,
+        BinOp::Lt,
+        BinOp::Le,
+        BinOp::Gt,
+        BinOp::Ge,
+        BinOp::Add,
+        BinOp::Sub,
+    ]
+    .into_iter()
+    .find(|op| *op as u8 == tag)
+    .ok_or_else(|| format!("invalid binary operator tag {}", tag))
+}
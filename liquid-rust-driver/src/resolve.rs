@@ -1,11 +1,11 @@
-use hir::{def_id::DefId, Impl, ItemId, ItemKind};
+use hir::{def_id::DefId, ItemId, ItemKind};
 use liquid_rust_common::{errors::ErrorReported, index::IndexGen, iter::IterExt};
 use liquid_rust_core::ty::{self, Name, ParamTy};
 use liquid_rust_syntax::ast;
 use quickscope::ScopeMap;
 use rustc_hash::FxHashMap;
 use rustc_hir::{self as hir, def_id::LocalDefId};
-use rustc_middle::ty::TyCtxt;
+use rustc_middle::ty::{subst::GenericArgKind, GenericParamDefKind, Ty, TyCtxt, TyKind};
 use rustc_session::{Session, SessionDiagnostic};
 use rustc_span::{sym, symbol::kw, Symbol};
 
@@ -15,8 +15,7 @@ pub struct Resolver<'tcx> {
     tcx: TyCtxt<'tcx>,
     diagnostics: Diagnostics<'tcx>,
     name_res_table: NameResTable,
-    def_id: LocalDefId,
-    parent: Option<&'tcx Impl<'tcx>>,
+    def_id: DefId,
 }
 
 enum ParamTyOrBaseTy {
@@ -42,28 +41,50 @@ impl<'tcx> Resolver<'tcx> {
         fn_sig: ast::FnSig,
     ) -> Result<ty::FnSig, ErrorReported> {
         let hir_id = tcx.hir().local_def_id_to_hir_id(def_id);
-        let hir_fn_sig = tcx.hir().fn_sig_by_hir_id(hir_id).unwrap();
+        let hir_fn_decl = tcx.hir().fn_decl_by_hir_id(hir_id).unwrap();
 
         let mut diagnostics = Diagnostics::new(tcx.sess);
 
         let mut name_res_table = FxHashMap::default();
-        let mut parent = None;
         if let Some(impl_did) = tcx.impl_of_method(def_id.to_def_id()) {
             let item_id = ItemId {
                 def_id: impl_did.expect_local(),
             };
             let item = tcx.hir().item(item_id);
             if let ItemKind::Impl(impl_parent) = &item.kind {
-                parent = Some(impl_parent);
                 collect_res_ty(&mut diagnostics, impl_parent.self_ty, &mut name_res_table)?;
             }
         }
-        collect_res(&mut diagnostics, hir_fn_sig, &mut name_res_table)?;
+        collect_res(&mut diagnostics, hir_fn_decl, &mut name_res_table)?;
 
         let mut resolver = Self {
             tcx,
             diagnostics,
-            parent,
+            name_res_table,
+            def_id: def_id.to_def_id(),
+        };
+        resolver.run(fn_sig)
+    }
+
+    /// Resolves the signature of a function defined in another crate. There is no HIR for such
+    /// functions, so names are resolved using the types in the rustc signature.
+    pub fn resolve_extern(
+        tcx: TyCtxt<'tcx>,
+        def_id: DefId,
+        fn_sig: ast::FnSig,
+    ) -> Result<ty::FnSig, ErrorReported> {
+        let mut name_res_table = FxHashMap::default();
+        for prim_ty in hir::PrimTy::ALL {
+            name_res_table.insert(prim_ty.name(), hir::def::Res::PrimTy(prim_ty));
+        }
+        let rustc_sig = tcx.fn_sig(def_id).skip_binder();
+        for ty in rustc_sig.inputs_and_output {
+            collect_res_rustc_ty(tcx, def_id, ty, &mut name_res_table);
+        }
+
+        let mut resolver = Self {
+            tcx,
+            diagnostics: Diagnostics::new(tcx.sess),
             name_res_table,
             def_id,
         };
@@ -75,18 +96,7 @@ impl<'tcx> Resolver<'tcx> {
 
         let name_gen = IndexGen::new();
 
-        if let Some(parent) = self.parent {
-            self.insert_generic_types(&parent.generics, &mut subst);
-            subst.push_type_layer();
-        }
-
-        let hir_generics = self
-            .tcx
-            .hir()
-            .get_generics(self.def_id.to_def_id())
-            .unwrap();
-
-        self.insert_generic_types(hir_generics, &mut subst);
+        self.insert_generic_types(self.def_id, &mut subst);
 
         let params = self.resolve_generics(fn_sig.generics, &name_gen, &mut subst);
 
@@ -136,15 +146,17 @@ impl<'tcx> Resolver<'tcx> {
         })
     }
 
-    fn insert_generic_types(&self, generics: &hir::Generics, subst: &mut Subst) {
-        for param in generics.params.iter() {
-            match param.kind {
-                hir::GenericParamKind::Type { .. } => {
-                    let def_id = self.tcx.hir().local_def_id(param.hir_id).to_def_id();
-                    let name = param.name.ident().name;
-                    subst.insert_type(def_id, name);
-                }
-                _ => {}
+    /// Inserts the type parameters of the item and its parents, outermost first. For traits,
+    /// `Self` is the first parameter.
+    fn insert_generic_types(&self, def_id: DefId, subst: &mut Subst) {
+        let generics = self.tcx.generics_of(def_id);
+        if let Some(parent) = generics.parent {
+            self.insert_generic_types(parent, subst);
+            subst.push_type_layer();
+        }
+        for param in &generics.params {
+            if let GenericParamDefKind::Type { .. } = param.kind {
+                subst.insert_type(param.def_id, param.name);
             }
         }
     }
@@ -173,8 +185,8 @@ impl<'tcx> Resolver<'tcx> {
                     };
                     let sort = self.resolve_sort(param.sort);
                     let pred = match param.pred {
-                        Some(expr) => self.resolve_expr(expr, subst),
-                        None => Ok(ty::Expr::TRUE),
+                        Some(expr) => self.resolve_expr(expr, subst).map(ty::Pred::Expr),
+                        None => Ok(ty::Pred::TRUE),
                     };
                     Ok(ty::Param {
                         name,
@@ -188,33 +200,43 @@ impl<'tcx> Resolver<'tcx> {
 
     fn resolve_ty(&mut self, ty: ast::Ty, subst: &mut Subst) -> Result<ty::Ty, ErrorReported> {
         match ty.kind {
-            ast::TyKind::BaseTy(path) => match self.resolve_path(path, subst)? {
+            ast::TyKind::BaseTy(bty) => match self.resolve_base_ty(bty, subst)? {
                 ParamTyOrBaseTy::BaseTy(bty) => Ok(ty::Ty::Exists(bty, ty::Pred::TRUE)),
                 ParamTyOrBaseTy::ParamTy(param_ty) => Ok(ty::Ty::Param(param_ty)),
             },
-            ast::TyKind::RefineTy { path, refine } => match self.resolve_path(path, subst)? {
-                ParamTyOrBaseTy::BaseTy(bty) => {
-                    let refine = self.resolve_expr(refine, subst);
-                    Ok(ty::Ty::Refine(bty, refine?))
-                }
-                ParamTyOrBaseTy::ParamTy(_) => self
-                    .diagnostics
-                    .emit_err(errors::RefinedTypeParam { span: ty.span })
-                    .raise(),
-            },
-            ast::TyKind::Exists { bind, path, pred } => match self.resolve_path(path, subst)? {
-                ParamTyOrBaseTy::BaseTy(bty) => {
-                    subst.push_expr_layer();
-                    subst.insert_expr(bind.name, ty::Var::Bound);
-                    let e = self.resolve_expr(pred, subst);
-                    subst.pop_expr_layer();
-                    Ok(ty::Ty::Exists(bty, ty::Pred::Expr(e?)))
-                }
-                ParamTyOrBaseTy::ParamTy(_) => self
-                    .diagnostics
-                    .emit_err(errors::RefinedTypeParam { span: ty.span })
-                    .raise(),
-            },
+            ast::TyKind::RefineTy { bty, refine } => {
+                let bty = self.resolve_refined_base_ty(bty, subst)?;
+                let refine = self.resolve_expr(refine, subst);
+                Ok(ty::Ty::Refine(bty, refine?))
+            }
+            ast::TyKind::Exists { bind, bty, pred } => {
+                let bty = self.resolve_refined_base_ty(bty, subst)?;
+                subst.push_expr_layer();
+                subst.insert_expr(bind.name, ty::Var::Bound);
+                let e = self.resolve_expr(pred, subst);
+                subst.pop_expr_layer();
+                Ok(ty::Ty::Exists(bty, ty::Pred::Expr(e?)))
+            }
+            ast::TyKind::Infer(bty) => {
+                let bty = self.resolve_refined_base_ty(bty, subst)?;
+                Ok(ty::Ty::Exists(bty, ty::Pred::Infer(Some(ty.span))))
+            }
+            ast::TyKind::Tuple(tys) => {
+                let tys = tys
+                    .into_iter()
+                    .map(|ty| self.resolve_ty(ty, subst))
+                    .try_collect_exhaust()?;
+                Ok(ty::Ty::Tuple(tys))
+            }
+            ast::TyKind::Array(ty, len) => {
+                let span = len.span;
+                let ty = self.resolve_ty(*ty, subst);
+                let len = ty::Expr {
+                    kind: ty::ExprKind::Literal(self.resolve_lit(len)?),
+                    span: Some(span),
+                };
+                Ok(ty::Ty::Refine(ty::BaseTy::Array(Box::new(ty?)), len))
+            }
             ast::TyKind::MutRef(region) => {
                 if let Some(name) = subst.get_region(region.name) {
                     Ok(ty::Ty::MutRef(name))
@@ -224,6 +246,42 @@ impl<'tcx> Resolver<'tcx> {
                         .raise()
                 }
             }
+            ast::TyKind::Ref(ty) => Ok(ty::Ty::Ref(Box::new(self.resolve_ty(*ty, subst)?))),
+            ast::TyKind::Never => Ok(ty::Ty::Never),
+            ast::TyKind::Fn(args, ret) => {
+                let args = args
+                    .into_iter()
+                    .map(|ty| self.resolve_ty(ty, subst))
+                    .try_collect_exhaust();
+                let ret = self.resolve_ty(*ret, subst);
+                Ok(ty::Ty::Fn(args?, Box::new(ret?)))
+            }
+        }
+    }
+
+    /// Resolves a base type that is explicitly refined, which type parameters can be.
+    fn resolve_refined_base_ty(
+        &mut self,
+        bty: ast::BaseTy,
+        subst: &mut Subst,
+    ) -> Result<ty::BaseTy, ErrorReported> {
+        match self.resolve_base_ty(bty, subst)? {
+            ParamTyOrBaseTy::BaseTy(bty) => Ok(bty),
+            ParamTyOrBaseTy::ParamTy(param_ty) => Ok(ty::BaseTy::Param(param_ty)),
+        }
+    }
+
+    fn resolve_base_ty(
+        &mut self,
+        bty: ast::BaseTy,
+        subst: &mut Subst,
+    ) -> Result<ParamTyOrBaseTy, ErrorReported> {
+        match bty {
+            ast::BaseTy::Path(path) => self.resolve_path(path, subst),
+            ast::BaseTy::Slice(ty) => {
+                let ty = self.resolve_ty(*ty, subst)?;
+                Ok(ParamTyOrBaseTy::BaseTy(ty::BaseTy::Slice(Box::new(ty))))
+            }
         }
     }
 
@@ -245,6 +303,9 @@ impl<'tcx> Resolver<'tcx> {
             hir::def::Res::Def(hir::def::DefKind::TyParam, did) => {
                 Ok(ParamTyOrBaseTy::ParamTy(subst.get_param_ty(did).unwrap()))
             }
+            hir::def::Res::SelfTy(Some(trait_did), None) => Ok(ParamTyOrBaseTy::ParamTy(
+                subst.get_param_ty(trait_did).unwrap(),
+            )),
             hir::def::Res::Def(hir::def::DefKind::Struct, did) => {
                 let args = path
                     .args
@@ -306,6 +367,14 @@ impl<'tcx> Resolver<'tcx> {
                 let e2 = self.resolve_expr(*e2, subst);
                 ty::ExprKind::BinaryOp(op, Box::new(e1?), Box::new(e2?))
             }
+            ast::ExprKind::App(func, args) => {
+                let var = self.resolve_var(func, subst);
+                let args = args
+                    .into_iter()
+                    .map(|arg| self.resolve_expr(arg, subst))
+                    .try_collect_exhaust();
+                ty::ExprKind::App(var?, func.name, args?)
+            }
         };
         Ok(ty::Expr {
             kind,
@@ -340,7 +409,27 @@ impl<'tcx> Resolver<'tcx> {
         }
     }
 
-    fn resolve_sort(&mut self, sort: ast::Ident) -> Result<ty::Sort, ErrorReported> {
+    fn resolve_sort(&mut self, sort: ast::Sort) -> Result<ty::Sort, ErrorReported> {
+        match sort {
+            ast::Sort::Base(sort) => self.resolve_base_sort(sort),
+            ast::Sort::Func { input, output } => {
+                let input_sort = self.resolve_base_sort(input);
+                let output_sort = self.resolve_base_sort(output);
+                match (input_sort?, output_sort?) {
+                    (ty::Sort::Int, ty::Sort::Bool) => Ok(ty::Sort::Pred),
+                    _ => self
+                        .diagnostics
+                        .emit_err(errors::UnsupportedSignature {
+                            span: input.span.to(output.span),
+                            msg: "only abstract refinements of sort `int -> bool` are supported",
+                        })
+                        .raise(),
+                }
+            }
+        }
+    }
+
+    fn resolve_base_sort(&mut self, sort: ast::Ident) -> Result<ty::Sort, ErrorReported> {
         if sort.name == SORTS.int {
             Ok(ty::Sort::Int)
         } else if sort.name == sym::bool {
@@ -442,16 +531,15 @@ impl Drop for Diagnostics<'_> {
 
 fn collect_res(
     diagnostics: &mut Diagnostics,
-    fn_sig: &hir::FnSig,
+    fn_decl: &hir::FnDecl,
     table: &mut NameResTable,
 ) -> Result<(), ErrorReported> {
-    fn_sig
-        .decl
+    fn_decl
         .inputs
         .iter()
         .try_for_each_exhaust(|ty| collect_res_ty(diagnostics, ty, table))?;
 
-    match fn_sig.decl.output {
+    match fn_decl.output {
         hir::FnRetTy::DefaultReturn(span) => {
             return diagnostics
                 .emit_err(errors::UnsupportedSignature {
@@ -514,8 +602,18 @@ fn collect_res_ty(
                 .flatten()
                 .try_for_each_exhaust(|arg| collect_res_generic_arg(diagnostics, arg, table))
         }
-        hir::TyKind::BareFn(_)
-        | hir::TyKind::Never
+        hir::TyKind::BareFn(bare_fn) => {
+            bare_fn
+                .decl
+                .inputs
+                .iter()
+                .try_for_each_exhaust(|ty| collect_res_ty(diagnostics, ty, table))?;
+            match bare_fn.decl.output {
+                hir::FnRetTy::DefaultReturn(_) => Ok(()),
+                hir::FnRetTy::Return(ty) => collect_res_ty(diagnostics, ty, table),
+            }
+        }
+        hir::TyKind::Never
         | hir::TyKind::OpaqueDef(_, _)
         | hir::TyKind::TraitObject(_, _, _)
         | hir::TyKind::Typeof(_)
@@ -524,6 +622,39 @@ fn collect_res_ty(
     }
 }
 
+fn collect_res_rustc_ty<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    def_id: DefId,
+    ty: Ty<'tcx>,
+    table: &mut NameResTable,
+) {
+    let tys = ty.walk(tcx).filter_map(|arg| match arg.unpack() {
+        GenericArgKind::Type(ty) => Some(ty),
+        _ => None,
+    });
+    for ty in tys {
+        match ty.kind() {
+            TyKind::Adt(adt_def, _) => {
+                let def_kind = tcx.def_kind(adt_def.did);
+                table.insert(
+                    tcx.item_name(adt_def.did),
+                    hir::def::Res::Def(def_kind, adt_def.did),
+                );
+            }
+            TyKind::Param(param) => {
+                let param_did = tcx.generics_of(def_id).type_param(param, tcx).def_id;
+                let res = if param.name == kw::SelfUpper {
+                    hir::def::Res::SelfTy(Some(param_did), None)
+                } else {
+                    hir::def::Res::Def(hir::def::DefKind::TyParam, param_did)
+                };
+                table.insert(param.name, res);
+            }
+            _ => {}
+        }
+    }
+}
+
 fn collect_res_generic_arg(
     diagnostics: &mut Diagnostics,
     arg: &hir::GenericArg,
@@ -623,14 +754,6 @@ mod errors {
         }
     }
 
-    #[derive(SessionDiagnostic)]
-    #[error = "LIQUID"]
-    pub struct RefinedTypeParam {
-        #[message = "type parameters cannot be refined"]
-        #[label = "refined type parameter"]
-        pub span: Span,
-    }
-
     #[derive(SessionDiagnostic)]
     #[error = "LIQUID"]
     pub struct UnresolvedSort {
@@ -0,0 +1,145 @@
+//! Loading of standalone spec files.
+//!
+//! Crates that are not checked with liquid-rust, e.g., third-party crates, can be given specs in
+//! `.lrspec` files placed in the directory set by the `LR_SPEC_DIR` environment variable. A spec
+//! file is a sequence of function signatures, each one prefixed by the path to the function:
+//!
+//! ```text
+//! fn std::vec::Vec<T>::len<n: int>(&Vec<T>@n) -> usize@n
+//! ```
+//!
+//! Paths are the same as in extern specs (see [`crate::def_path`]). Specs for crates that are not
+//! dependencies of the current crate are ignored. Spec files are trusted: there is no way to
+//! check them against the functions.
+use std::{fs, path::Path};
+
+use liquid_rust_common::{config::CONFIG, errors::ErrorReported};
+use liquid_rust_core::{ty::FnSig, wf::Wf};
+use liquid_rust_syntax::{ast, parse_extern_fns, ParseErrorKind};
+use rustc_hash::FxHashMap;
+use rustc_middle::ty::TyCtxt;
+use rustc_session::Session;
+use rustc_span::{def_id::DefId, Span};
+
+use crate::def_path::{self, LookupError};
+
+/// Loads the spec files in the configured spec directory, if any, and returns the signatures
+/// they give to functions from other crates.
+pub fn load(tcx: TyCtxt, sess: &Session) -> Result<FxHashMap<DefId, FnSig>, ErrorReported> {
+    let spec_dir = match &CONFIG.spec_dir {
+        Some(spec_dir) => spec_dir,
+        None => return Ok(FxHashMap::default()),
+    };
+
+    let mut paths: Vec<_> = fs::read_dir(spec_dir)
+        .and_then(|entries| entries.map(|entry| Ok(entry?.path())).collect())
+        .map_err(|err| {
+            sess.err(&format!(
+                "failed to read spec directory `{}`: {}",
+                spec_dir.display(),
+                err
+            ));
+            ErrorReported
+        })?;
+    paths.retain(|path| path.extension().map_or(false, |ext| ext == "lrspec"));
+    paths.sort();
+
+    let wf = Wf::new(sess);
+    let mut fn_sigs = FxHashMap::default();
+    for path in paths {
+        for extern_fn in parse_file(sess, &path)? {
+            if let Some((def_id, fn_sig)) = resolve(tcx, sess, extern_fn)? {
+                wf.check_fn_sig(&fn_sig)?;
+                wf.check_no_holes(&fn_sig)?;
+                fn_sigs.insert(def_id, fn_sig);
+            }
+        }
+    }
+    Ok(fn_sigs)
+}
+
+fn parse_file(sess: &Session, path: &Path) -> Result<Vec<ast::ExternFn>, ErrorReported> {
+    let source_file = sess.source_map().load_file(path).map_err(|err| {
+        sess.err(&format!(
+            "failed to read spec file `{}`: {}",
+            path.display(),
+            err
+        ));
+        ErrorReported
+    })?;
+    let span = Span::with_root_ctxt(source_file.start_pos, source_file.end_pos);
+    let (tokens, mut unmatched) =
+        rustc_parse::source_file_to_stream(&sess.parse_sess, source_file, None);
+    if !unmatched.is_empty() {
+        rustc_parse::parser::emit_unclosed_delims(&mut unmatched, &sess.parse_sess);
+        return Err(ErrorReported);
+    }
+
+    parse_extern_fns(tokens, span).map_err(|err| {
+        let msg = match err.kind {
+            ParseErrorKind::UnexpectedEOF => "spec file ended unexpectedly",
+            ParseErrorKind::UnexpectedToken => "unexpected token",
+            ParseErrorKind::IntTooLarge => "integer literal is too large",
+        };
+        sess.span_err(err.span, msg);
+        ErrorReported
+    })
+}
+
+/// Resolves the path of the function and its signature. Returns `None` if the path starts with
+/// a crate that is not a dependency of the current crate.
+fn resolve(
+    tcx: TyCtxt,
+    sess: &Session,
+    extern_fn: ast::ExternFn,
+) -> Result<Option<(DefId, FnSig)>, ErrorReported> {
+    let (root, segments) = match extern_fn.path.split_first() {
+        Some(split) => split,
+        None => {
+            sess.span_err(extern_fn.fn_sig.span, "expected the path of a function");
+            return Err(ErrorReported);
+        }
+    };
+    let names: Vec<_> = segments.iter().map(|segment| segment.ident.name).collect();
+    let def_id = match def_path::lookup_fn(tcx, root.ident.name, &names) {
+        Ok(def_id) => def_id,
+        Err(LookupError::UnknownRoot) => return Ok(None),
+        Err(LookupError::NotFound(i, parent)) => {
+            sess.span_err(
+                segments[i].span,
+                &format!(
+                    "cannot find `{}` in `{}`",
+                    segments[i].ident,
+                    tcx.def_path_str(parent)
+                ),
+            );
+            return Err(ErrorReported);
+        }
+        Err(LookupError::NotAFunction(def_id)) => {
+            let span = segments.last().unwrap_or(root).span;
+            sess.span_err(
+                span,
+                &format!("`{}` is not a function", tcx.def_path_str(def_id)),
+            );
+            return Err(ErrorReported);
+        }
+    };
+
+    let expected = tcx.fn_sig(def_id).skip_binder().inputs().len();
+    let found = extern_fn.fn_sig.args.len();
+    if expected != found {
+        sess.span_err(
+            extern_fn.fn_sig.span,
+            &format!(
+                "the spec has {} arguments but `{}` takes {}",
+                found,
+                tcx.def_path_str(def_id),
+                expected
+            ),
+        );
+        return Err(ErrorReported);
+    }
+
+    let fn_sig = crate::resolve::Resolver::resolve_extern(tcx, def_id, extern_fn.fn_sig)?;
+    Ok(Some((def_id, fn_sig)))
+}
@@ -63,6 +63,10 @@
 // }
 
 pub fn test_file_exec(file: &str, expected: bool) {
+    test_file_exec_with_env(file, expected, &[])
+}
+
+pub fn test_file_exec_with_env(file: &str, expected: bool, env: &[(&str, &str)]) {
     let root = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
 
     let mut lr = root.clone();
@@ -73,14 +77,274 @@ pub fn test_file_exec(file: &str, expected: bool) {
 
     let res = std::process::Command::new(lr)
         .arg("--crate-type=lib")
-        .arg(test_path)
-        .status()
+        .args(compile_flags(&test_path))
+        .arg(&test_path)
+        .envs(env.iter().copied())
+        .output()
         .expect("failed to execute process");
-    assert_eq!(res.success(), expected); 
+    check_output(&test_path, expected, res);
+}
+
+/// The extra flags passed to the compiler when checking `test_path`, given in `// compile-flags:`
+/// lines of the file.
+fn compile_flags(test_path: &std::path::Path) -> Vec<String> {
+    let src = std::fs::read_to_string(test_path).unwrap();
+    src.lines()
+        .filter_map(|line| line.trim_start().strip_prefix("// compile-flags:"))
+        .flat_map(|flags| flags.split_whitespace().map(str::to_string))
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Error,
+    Note,
+}
+
+/// An error or a note reported in the test file: the line of its primary span and the whole text
+/// of the diagnostic, i.e., its message, labels and notes.
+struct Diagnostic {
+    level: Level,
+    line: usize,
+    text: String,
+}
+
+/// An expected error, written `//~ ERROR <text>` at the end of the line of its primary span, or
+/// `//~^ ERROR <text>` (with one `^` per line) below it, or an expected note, written the same
+/// with `NOTE`. `text` must appear in the diagnostic.
+struct Annotation {
+    level: Level,
+    line: usize,
+    text: String,
+}
+
+/// Safe files must be accepted. Unsafe files must be rejected and every error reported in them
+/// must match exactly one annotation, so each failing case in a file is checked separately.
+/// Notes, e.g., inferred invariants, must be reported where annotated but need not all be.
+fn check_output(test_path: &std::path::Path, expected: bool, output: std::process::Output) {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if expected {
+        assert!(
+            output.status.success(),
+            "expected no errors, got:\n{}",
+            stderr
+        );
+    } else {
+        assert!(!output.status.success(), "expected errors, got none");
+    }
+
+    let src = std::fs::read_to_string(test_path).unwrap();
+    let annotations = parse_annotations(&src);
+    assert!(
+        expected || annotations.iter().any(|annot| annot.level == Level::Error),
+        "no `//~ ERROR` annotations in `{}`",
+        test_path.display()
+    );
+
+    let (mut diagnostics, unlocated) = parse_diagnostics(&stderr, &test_path.display().to_string());
+    let mut unmatched = vec![];
+    for annot in annotations {
+        match diagnostics.iter().position(|diag| {
+            diag.level == annot.level && diag.line == annot.line && diag.text.contains(&annot.text)
+        }) {
+            Some(i) => {
+                diagnostics.remove(i);
+            }
+            None => unmatched.push(format!("{}: {}", annot.line, annot.text)),
+        }
+    }
+    let unexpected: Vec<_> = diagnostics
+        .iter()
+        .filter(|diag| diag.level == Level::Error)
+        .map(|diag| format!("{}: {}", diag.line, diag.text.lines().next().unwrap()))
+        .chain(unlocated)
+        .collect();
+    assert!(
+        unmatched.is_empty() && unexpected.is_empty(),
+        "expected errors not found: {:#?}\nunexpected errors: {:#?}\nfull output:\n{}",
+        unmatched,
+        unexpected,
+        stderr
+    );
+}
+
+fn parse_annotations(src: &str) -> Vec<Annotation> {
+    let mut annotations = vec![];
+    for (i, line) in src.lines().enumerate() {
+        if let Some(pos) = line.find("//~") {
+            let rest = &line[pos + 3..];
+            let carets = rest.chars().take_while(|c| *c == '^').count();
+            let rest = rest[carets..].trim_start();
+            let (level, text) = if let Some(text) = rest.strip_prefix("ERROR") {
+                (Level::Error, text)
+            } else if let Some(text) = rest.strip_prefix("NOTE") {
+                (Level::Note, text)
+            } else {
+                panic!("malformed annotation at line {}", i + 1)
+            };
+            annotations.push(Annotation {
+                level,
+                line: i + 1 - carets,
+                text: text.trim().to_string(),
+            });
+        }
+    }
+    annotations
+}
+
+/// Returns the errors and notes with a primary span in `file` and the headers of the other errors.
+fn parse_diagnostics(stderr: &str, file: &str) -> (Vec<Diagnostic>, Vec<String>) {
+    let mut blocks: Vec<Vec<&str>> = vec![];
+    for line in stderr.lines() {
+        if line.starts_with("error") || line.starts_with("warning") || line.starts_with("note") {
+            blocks.push(vec![]);
+        }
+        if let Some(block) = blocks.last_mut() {
+            block.push(line);
+        }
+    }
+
+    let mut diagnostics = vec![];
+    let mut unlocated = vec![];
+    for block in blocks {
+        let header = block[0];
+        let level = if header.starts_with("note") {
+            Level::Note
+        } else if header.starts_with("error") && !header.starts_with("error: aborting due to") {
+            Level::Error
+        } else {
+            continue;
+        };
+        let line = block.iter().find_map(|line| {
+            let loc = line.trim_start().strip_prefix("--> ")?;
+            let mut parts = loc.rsplitn(3, ':');
+            let _col = parts.next()?;
+            let line = parts.next()?.parse().ok()?;
+            (parts.next()? == file).then(|| line)
+        });
+        match line {
+            Some(line) => {
+                // Skip the lines quoting the source, which contain the annotations themselves.
+                let text = block
+                    .iter()
+                    .filter(|line| {
+                        let line = line.trim_start();
+                        line.starts_with("error")
+                            || line.starts_with("note")
+                            || line.starts_with('|')
+                            || line.starts_with('=')
+                    })
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                diagnostics.push(Diagnostic { level, line, text })
+            }
+            None if level == Level::Error => unlocated.push(header.to_string()),
+            None => {}
+        }
+    }
+    (diagnostics, unlocated)
+}
+
+/// Checks `file` against the libraries in `deps`, which are checked first and must be safe. The
+/// variables in `env` are only set when checking `file`.
+pub fn test_file_exec_with_deps(file: &str, expected: bool, deps: &[&str], env: &[(&str, &str)]) {
+    let root = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+
+    let mut lr = root.clone();
+    lr.push("../target/debug/liquid-rust");
+
+    let mut test_path = root.clone();
+    test_path.push(file);
+
+    // Tests with the same file name in different directories get different output directories.
+    let mut out_dir = root.clone();
+    out_dir.push("../target/lr-deps");
+    out_dir.push(format!(
+        "{}-{}",
+        test_path
+            .parent()
+            .unwrap()
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        test_path.file_stem().unwrap().to_str().unwrap()
+    ));
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    let mut externs = vec![];
+    for dep in deps {
+        let mut dep_path = root.clone();
+        dep_path.push(dep);
+        let name = dep_path.file_stem().unwrap().to_str().unwrap().to_string();
+
+        let res = std::process::Command::new(&lr)
+            .arg("--crate-type=lib")
+            .arg("--out-dir")
+            .arg(&out_dir)
+            .arg(&dep_path)
+            .output()
+            .expect("failed to execute process");
+        assert!(
+            res.status.success(),
+            "dependency `{}` is unsafe:\n{}",
+            dep,
+            String::from_utf8_lossy(&res.stderr)
+        );
+        externs.push(format!("{}={}/lib{}.rlib", name, out_dir.display(), name));
+    }
+
+    let mut cmd = std::process::Command::new(&lr);
+    cmd.arg("--crate-type=lib")
+        .args(compile_flags(&test_path))
+        .arg(&test_path)
+        .envs(env.iter().copied());
+    for ext in externs {
+        cmd.arg("--extern").arg(ext);
+    }
+    let res = cmd.output().expect("failed to execute process");
+    check_output(&test_path, expected, res);
 }
 
 #[macro_export]
 macro_rules! tests {
+    (env: $env:expr; $($name:ident: $file:literal => Safe),* $(,)?) => {$(
+        #[test]
+        fn $name() {
+            $crate::common::test_file_exec_with_env($file, true, $env)
+        }
+    )*};
+    (env: $env:expr; $($name:ident: $file:literal => Unsafe),* $(,)?) => {$(
+        #[test]
+        fn $name() {
+            $crate::common::test_file_exec_with_env($file, false, $env)
+        }
+    )*};
+    (deps: $deps:expr; env: $env:expr; $($name:ident: $file:literal => Safe),* $(,)?) => {$(
+        #[test]
+        fn $name() {
+            $crate::common::test_file_exec_with_deps($file, true, $deps, $env)
+        }
+    )*};
+    (deps: $deps:expr; env: $env:expr; $($name:ident: $file:literal => Unsafe),* $(,)?) => {$(
+        #[test]
+        fn $name() {
+            $crate::common::test_file_exec_with_deps($file, false, $deps, $env)
+        }
+    )*};
+    (deps: $deps:expr; $($name:ident: $file:literal => Safe),* $(,)?) => {$(
+        #[test]
+        fn $name() {
+            $crate::common::test_file_exec_with_deps($file, true, $deps, &[])
+        }
+    )*};
+    (deps: $deps:expr; $($name:ident: $file:literal => Unsafe),* $(,)?) => {$(
+        #[test]
+        fn $name() {
+            $crate::common::test_file_exec_with_deps($file, false, $deps, &[])
+        }
+    )*};
     ($($name:ident: $file:literal => Safe),* $(,)?) => {$(
         #[test]
         fn $name() {
@@ -5,4 +5,62 @@ tests! {
     test01: "../tests/neg/test01.rs" => Unsafe,
     test02: "../tests/neg/test02.rs" => Unsafe,
     test03: "../tests/neg/test03.rs" => Unsafe,
+    constants: "../tests/neg/constants.rs" => Unsafe,
+    tuples: "../tests/neg/tuples.rs" => Unsafe,
+    structs: "../tests/neg/structs.rs" => Unsafe,
+    arrays: "../tests/neg/arrays.rs" => Unsafe,
+    unchecked_arith: "../tests/neg/unchecked_arith.rs" => Unsafe,
+    never: "../tests/neg/never.rs" => Unsafe,
+    closures: "../tests/neg/closures.rs" => Unsafe,
+    closures_fn_mut: "../tests/neg/closures_fn_mut.rs" => Unsafe,
+    higher_order: "../tests/neg/higher_order.rs" => Unsafe,
+    traits: "../tests/neg/traits.rs" => Unsafe,
+    ffi: "../tests/neg/ffi.rs" => Unsafe,
+    extern_specs: "../tests/neg/extern_specs.rs" => Unsafe,
+    default_sigs: "../tests/neg/default_sigs.rs" => Unsafe,
+    holes: "../tests/neg/holes.rs" => Unsafe,
+    abstract_refinements: "../tests/neg/abstract_refinements.rs" => Unsafe,
+    abstract_refinements_wf: "../tests/neg/abstract_refinements_wf.rs" => Unsafe,
+    param_inference: "../tests/neg/param_inference.rs" => Unsafe,
+    ownership: "../tests/neg/ownership.rs" => Unsafe,
+    unsupported: "../tests/neg/unsupported.rs" => Unsafe,
+    unsupported_lowering: "../tests/neg/unsupported_lowering.rs" => Unsafe,
+    unsupported_refs: "../tests/neg/unsupported_refs.rs" => Unsafe,
+    obligations: "../tests/neg/obligations.rs" => Unsafe,
+}
+
+tests! {
+    env: &[("LR_CHECK_PANICS", "true")];
+    panics: "../tests/neg/panics.rs" => Unsafe,
+}
+
+tests! {
+    env: &[("LR_INFER_PRIVATE_SIGS", "true")];
+    infer_sigs: "../tests/neg/infer_sigs.rs" => Unsafe,
+}
+
+tests! {
+    deps: &["../tests/deps/nat.rs"];
+    cross_crate: "../tests/neg/cross_crate.rs" => Unsafe,
+}
+
+tests! {
+    deps: &["../tests/deps/plain.rs"];
+    env: &[("LR_SPEC_DIR", "../tests/lrspec")];
+    spec_files: "../tests/neg/spec_files.rs" => Unsafe,
+}
+
+tests! {
+    env: &[("LR_FIXPOINT", "../tests/no-such-fixpoint")];
+    fixpoint_not_found: "../tests/neg/fixpoint_not_found.rs" => Unsafe,
+}
+
+tests! {
+    env: &[("LR_FIXPOINT", "../tests/stubs/slow-fixpoint"), ("LR_FIXPOINT_TIMEOUT", "1")];
+    fixpoint_timeout: "../tests/neg/fixpoint_timeout.rs" => Unsafe,
+}
+
+tests! {
+    env: &[("LR_Z3", "../tests/no-such-z3")];
+    z3_not_found: "../tests/neg/z3_not_found.rs" => Unsafe,
 }
@@ -6,4 +6,46 @@ tests! {
     test02: "../tests/pos/test02.rs" => Safe,
     test03: "../tests/pos/test03.rs" => Safe,
     heapsort: "../tests/pos/heapsort.rs" => Safe,
+    constants: "../tests/pos/constants.rs" => Safe,
+    optimized: "../tests/pos/optimized.rs" => Safe,
+    tuples: "../tests/pos/tuples.rs" => Safe,
+    structs: "../tests/pos/structs.rs" => Safe,
+    arrays: "../tests/pos/arrays.rs" => Safe,
+    never: "../tests/pos/never.rs" => Safe,
+    closures: "../tests/pos/closures.rs" => Safe,
+    higher_order: "../tests/pos/higher_order.rs" => Safe,
+    traits: "../tests/pos/traits.rs" => Safe,
+    ffi: "../tests/pos/ffi.rs" => Safe,
+    extern_specs: "../tests/pos/extern_specs.rs" => Safe,
+    default_sigs: "../tests/pos/default_sigs.rs" => Safe,
+    holes: "../tests/pos/holes.rs" => Safe,
+    abstract_refinements: "../tests/pos/abstract_refinements.rs" => Safe,
+    param_inference: "../tests/pos/param_inference.rs" => Safe,
+    ownership: "../tests/pos/ownership.rs" => Safe,
+}
+
+tests! {
+    env: &[("LR_CHECK_PANICS", "true")];
+    panics: "../tests/pos/panics.rs" => Safe,
+}
+
+tests! {
+    env: &[("LR_INFER_PRIVATE_SIGS", "true")];
+    infer_sigs: "../tests/pos/infer_sigs.rs" => Safe,
+}
+
+tests! {
+    env: &[("LR_SHOW_INVARIANTS", "true")];
+    invariants: "../tests/pos/invariants.rs" => Safe,
+}
+
+tests! {
+    deps: &["../tests/deps/nat.rs"];
+    cross_crate: "../tests/pos/cross_crate.rs" => Safe,
+}
+
+tests! {
+    deps: &["../tests/deps/plain.rs"];
+    env: &[("LR_SPEC_DIR", "../tests/lrspec")];
+    spec_files: "../tests/pos/spec_files.rs" => Safe,
 }
@@ -4,7 +4,7 @@ use std::fmt::{self, Write};
 use liquid_rust_common::{format::PadAdapter, index::newtype_index};
 
 pub enum Constraint {
-    Pred(Pred),
+    Pred(Pred, Option<TagIdx>),
     Conj(Vec<Self>),
     Guard(Expr, Box<Self>),
     ForAll(Name, Sort, Pred, Box<Self>),
@@ -14,6 +14,8 @@ pub enum Constraint {
 pub enum Sort {
     Int,
     Bool,
+    /// The sort `int -> bool` of abstract refinements, i.e., of predicates on integers.
+    Pred,
 }
 
 pub enum Pred {
@@ -27,6 +29,8 @@ pub enum Expr {
     Constant(Constant),
     BinaryOp(BinOp, Box<Self>, Box<Self>),
     UnaryOp(UnOp, Box<Self>),
+    /// The application of an uninterpreted function.
+    App(Name, Vec<Self>),
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -82,6 +86,13 @@ newtype_index! {
     }
 }
 
+newtype_index! {
+    /// Identifies a head so that it can be traced back when fixpoint reports it as failing.
+    pub struct TagIdx {
+        DEBUG_FORMAT = "t{}",
+    }
+}
+
 newtype_index! {
     pub struct Name {
         DEBUG_FORMAT = "a{}",
@@ -89,7 +100,7 @@ newtype_index! {
 }
 
 impl Constraint {
-    pub const TRUE: Self = Self::Pred(Pred::Expr(Expr::Constant(Constant::Bool(true))));
+    pub const TRUE: Self = Self::Pred(Pred::Expr(Expr::Constant(Constant::Bool(true))), None);
 }
 
 impl BinOp {
@@ -117,7 +128,8 @@ impl Precedence {
 impl fmt::Display for Constraint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Constraint::Pred(pred) => write!(f, "({})", pred),
+            Constraint::Pred(pred, None) => write!(f, "({})", pred),
+            Constraint::Pred(pred, Some(tag)) => write!(f, "(tag {} \"{}\")", pred, tag.index()),
             Constraint::Conj(preds) => {
                 write!(f, "(and")?;
                 let mut w = PadAdapter::wrap_fmt(f);
@@ -145,13 +157,17 @@ impl fmt::Display for Sort {
         match self {
             Sort::Int => write!(f, "int"),
             Sort::Bool => write!(f, "bool"),
+            Sort::Pred => write!(f, "(func 0 (int) bool)"),
         }
     }
 }
 
 impl fmt::Debug for Sort {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Display::fmt(self, f)
+        match self {
+            Sort::Pred => write!(f, "int -> bool"),
+            _ => fmt::Display::fmt(self, f),
+        }
     }
 }
 
@@ -210,6 +226,13 @@ impl fmt::Display for Expr {
                     write!(f, "{}({})", op, e)
                 }
             }
+            Expr::App(func, args) => {
+                write!(f, "({:?}", func)?;
+                for arg in args {
+                    write!(f, " ({})", arg)?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
@@ -267,7 +290,7 @@ impl From<u128> for Constant {
 impl From<i128> for Constant {
     fn from(c: i128) -> Self {
         if c < 0 {
-            Constant::Int(Sign::Negative, -c as u128)
+            Constant::Int(Sign::Negative, c.unsigned_abs())
         } else {
             Constant::Int(Sign::Positive, c as u128)
         }
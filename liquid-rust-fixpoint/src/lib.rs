@@ -3,66 +3,246 @@
 extern crate rustc_serialize;
 
 mod constraint;
+mod smt;
+mod solution;
 
 use std::{
     fmt::{self, Write as FmtWrite},
-    io::{self, BufWriter, Write as IOWrite},
-    process::{Command, Stdio},
+    fs,
+    io::{self, BufWriter, Read, Write as IOWrite},
+    path::PathBuf,
+    process::{self, Child, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
 };
 
-pub use constraint::{BinOp, Constant, Constraint, Expr, KVid, Name, Pred, Sort, UnOp};
+pub use constraint::{BinOp, Constant, Constraint, Expr, KVid, Name, Pred, Sort, TagIdx, UnOp};
 use itertools::Itertools;
-use liquid_rust_common::format::PadAdapter;
+use liquid_rust_common::{config::CONFIG, format::PadAdapter};
 use serde::Deserialize;
+pub use smt::Model;
+pub use solution::Solution;
 
 pub struct Fixpoint {
     pub kvars: Vec<KVar>,
+    /// Uninterpreted constants, e.g., the abstract refinements of a function.
+    pub constants: Vec<(Name, Sort)>,
     pub constraint: Constraint,
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
-pub struct FixpointResult {
-    pub tag: Safeness,
+#[derive(Deserialize, Debug)]
+#[serde(tag = "tag", content = "contents")]
+pub enum FixpointResult {
+    Safe(Stats),
+    Unsafe(Stats, Vec<Error>),
+    Crash(serde_json::Value),
 }
 
-#[derive(Deserialize, Eq, PartialEq, Debug, Clone, Copy)]
-pub enum Safeness {
-    Safe,
-    Unsafe,
-    Crash,
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Stats {
+    pub num_cstr: usize,
+    pub num_iter: usize,
+    pub num_chck: usize,
+    pub num_vald: usize,
+}
+
+/// Why a query couldn't be answered, either by fixpoint or by z3 for constraints without kvars.
+#[derive(Debug)]
+pub enum FixpointError {
+    /// The solver executable doesn't exist.
+    NotFound(PathBuf),
+    /// The query didn't finish within the configured timeout and was killed.
+    Timeout(Duration),
+    /// The solver exited without a result.
+    Crash { stderr: String },
+    /// The solver exited normally but its output isn't a result.
+    BadOutput(String),
+    /// Writing the query failed.
+    Io(io::Error),
+}
+
+/// A failing constraint. Fixpoint reports it as a pair of its internal id and the tag of the
+/// head, which is empty for untagged heads.
+#[derive(Deserialize, Debug)]
+#[serde(from = "(i64, String)")]
+pub struct Error {
+    pub id: i64,
+    pub tag: Option<TagIdx>,
 }
 
 #[derive(Debug)]
 pub struct KVar(pub KVid, pub Vec<Sort>);
 
 impl Fixpoint {
-    pub fn new(kvars: Vec<KVar>, constraint: Constraint) -> Self {
-        Fixpoint { kvars, constraint }
+    pub fn new(kvars: Vec<KVar>, constants: Vec<(Name, Sort)>, constraint: Constraint) -> Self {
+        Fixpoint {
+            kvars,
+            constants,
+            constraint,
+        }
     }
 
-    pub fn check(&self) -> io::Result<FixpointResult> {
-        let mut child = Command::new("fixpoint")
-            .arg("-q")
-            .arg("--stdin")
-            .arg("--json")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()
-            .unwrap();
-        let mut stdin = None;
-        std::mem::swap(&mut stdin, &mut child.stdin);
-        {
-            let mut w = BufWriter::new(stdin.unwrap());
-            // let mut w = BufWriter::new(std::io::stdout());
+    pub fn check(&self) -> Result<FixpointResult, FixpointError> {
+        let mut cmd = fixpoint_cmd();
+        cmd.arg("--stdin");
+        let out = run(cmd, Some(self.to_string()))?;
+        parse_result(out)
+    }
+
+    /// Like [`Fixpoint::check`] but also returns the solution found for the kvars. Fixpoint only
+    /// saves solutions next to the query file, so the query is written to a fresh temporary
+    /// directory which is removed afterwards.
+    pub fn check_with_solution(&self) -> Result<(FixpointResult, Solution), FixpointError> {
+        static QUERIES: AtomicUsize = AtomicUsize::new(0);
 
+        let dir = std::env::temp_dir().join(format!(
+            "liquid-rust-{}-{}",
+            process::id(),
+            QUERIES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        let query = dir.join("query.fq");
+        {
+            let mut w = BufWriter::new(fs::File::create(&query)?);
             writeln!(w, "{}", self)?;
         }
-        let out = child.wait_with_output()?;
+        let mut cmd = fixpoint_cmd();
+        cmd.arg("--save").arg(&query);
+        let out = run(cmd, None);
+
+        // Fixpoint doesn't save a solution if it crashes.
+        let solution = fs::read_to_string(dir.join(".liquid").join("query.fq.fqout"))
+            .map(|text| solution::parse(&text))
+            .unwrap_or_default();
+        fs::remove_dir_all(&dir)?;
+
+        Ok((parse_result(out?)?, solution))
+    }
+}
+
+/// The output of a solver that exited on its own.
+struct Output {
+    status: ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+fn fixpoint_cmd() -> Command {
+    let mut cmd = Command::new(&CONFIG.fixpoint);
+    cmd.arg("-q")
+        .arg("--json")
+        .args(CONFIG.fixpoint_args.split_whitespace());
+    cmd
+}
+
+/// Runs a solver, feeding it `input` on stdin, and kills it if it doesn't finish within the
+/// configured timeout. The pipes are drained in their own threads so a chatty or hung solver can't
+/// block us.
+fn run(mut cmd: Command, input: Option<String>) -> Result<Output, FixpointError> {
+    let mut child = cmd
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => FixpointError::NotFound(cmd.get_program().into()),
+            _ => FixpointError::Io(err),
+        })?;
+
+    let writer = match (child.stdin.take(), input) {
+        (Some(mut stdin), Some(input)) => {
+            Some(thread::spawn(move || stdin.write_all(input.as_bytes())))
+        }
+        _ => None,
+    };
+    let stdout = drain(child.stdout.take().unwrap());
+    let stderr = drain(child.stderr.take().unwrap());
+
+    let status = match CONFIG.fixpoint_timeout {
+        Some(secs) => wait_timeout(&mut child, Duration::from_secs(secs))?,
+        None => child.wait()?,
+    };
+    // A solver closing its stdin early shows up as a crash when parsing its output, any other
+    // failure to feed it the input is ours.
+    if let Some(Ok(Err(err))) = writer.map(thread::JoinHandle::join) {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            return Err(FixpointError::Io(err));
+        }
+    }
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn drain(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<ExitStatus, FixpointError> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(FixpointError::Timeout(timeout));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Fixpoint reports unsafe constraints with a non-zero exit status, so the output is parsed first
+/// and the status only decides whether unparsable output comes from a crash.
+fn parse_result(out: Output) -> Result<FixpointResult, FixpointError> {
+    match serde_json::from_slice(&out.stdout) {
+        Ok(result) => Ok(result),
+        Err(_) if !out.status.success() => Err(FixpointError::Crash {
+            stderr: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }),
+        Err(err) => Err(FixpointError::BadOutput(err.to_string())),
+    }
+}
 
-        let result = serde_json::from_slice(&out.stdout)?;
+impl From<io::Error> for FixpointError {
+    fn from(err: io::Error) -> Self {
+        FixpointError::Io(err)
+    }
+}
 
-        Ok(result)
+impl fmt::Display for FixpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixpointError::NotFound(path) => {
+                write!(f, "executable `{}` not found", path.display())
+            }
+            FixpointError::Timeout(timeout) => {
+                write!(f, "timed out after {}s", timeout.as_secs())
+            }
+            FixpointError::Crash { .. } => write!(f, "the process crashed"),
+            FixpointError::BadOutput(err) => write!(f, "unexpected output: {}", err),
+            FixpointError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<(i64, String)> for Error {
+    fn from((id, tag): (i64, String)) -> Self {
+        let tag = tag.parse().ok().map(TagIdx::from_usize);
+        Error { id, tag }
     }
 }
 
@@ -84,6 +264,10 @@ impl fmt::Display for Fixpoint {
         writeln!(f, "(qualif Le ((a int) (b int)) (a < b - 1))")?;
         // writeln!(f, "(qualif Foo ((a int) (b int)) (a <= b/2))")?;
 
+        for (name, sort) in &self.constants {
+            writeln!(f, "(constant {:?} {})", name, sort)?;
+        }
+
         for kvar in &self.kvars {
             writeln!(f, "{}", kvar)?;
         }
@@ -0,0 +1,560 @@
+//! Constraints as SMT-LIB queries for z3.
+//!
+//! A constraint without kvars is valid iff, for each head, the hypotheses in scope of it imply it,
+//! so it can be checked by asking z3 for the satisfiability of the hypotheses and the negation of
+//! the head, one query per head, without going through fixpoint.
+//!
+//! Fixpoint only reports which heads fail. To explain a failure we look for a head with the failing
+//! tag, replace the kvars in its hypotheses (and in the head itself) by their solutions, and ask
+//! z3 for a model of the same query. Heads depending on kvars without a solution are skipped.
+use std::{
+    fmt::{self, Write},
+    process::Command,
+};
+
+use liquid_rust_common::config::CONFIG;
+
+use crate::{
+    constraint::Sign, run, BinOp, Constant, Constraint, Error, Expr, Fixpoint, FixpointError,
+    FixpointResult, Name, Output, Pred, Solution, Sort, Stats, TagIdx, UnOp,
+};
+
+/// An assignment of values to the names in a constraint.
+pub type Model = Vec<(Name, Constant)>;
+
+impl Fixpoint {
+    /// Checks a constraint without kvars with z3. Heads that can't be proven, including those z3
+    /// gives up on, are reported as failing with their position in the constraint as id.
+    pub fn check_smt(&self) -> Result<FixpointResult, FixpointError> {
+        let heads = self.heads(&Solution::default());
+
+        let mut script = String::new();
+        write_decls(&mut script, &self.constants).unwrap();
+        for head in &heads {
+            // A head mentioning a kvar can't be translated, there's nothing to ask z3.
+            if let Some(goal) = &head.goal {
+                writeln!(script, "(push 1)").unwrap();
+                self.write_query(&mut script, &head.path, goal).unwrap();
+                writeln!(script, "(pop 1)").unwrap();
+            }
+        }
+
+        let out = run(z3_cmd(), Some(script))?;
+        let errors = failing_heads(&heads, &out)?;
+
+        let stats = Stats {
+            num_cstr: heads.len(),
+            num_iter: 0,
+            num_chck: heads.len(),
+            num_vald: heads.len() - errors.len(),
+        };
+        if errors.is_empty() {
+            Ok(FixpointResult::Safe(stats))
+        } else {
+            Ok(FixpointResult::Unsafe(stats, errors))
+        }
+    }
+
+    /// Returns values for the names in scope of a head tagged with `tag` under which the head
+    /// doesn't hold, using `solution` for the kvars. Returns `None` if every such head depends on
+    /// a kvar without a solution or the solver can't find a model.
+    pub fn counterexample(
+        &self,
+        tag: TagIdx,
+        solution: &Solution,
+    ) -> Result<Option<Model>, FixpointError> {
+        for head in self.heads(solution) {
+            let goal = match head.goal {
+                Some(goal) if head.tag == Some(tag) => goal,
+                _ => continue,
+            };
+            let mut script = String::new();
+            write_decls(&mut script, &self.constants).unwrap();
+            self.write_query(&mut script, &head.path, &goal).unwrap();
+            writeln!(script, "(get-model)").unwrap();
+
+            // z3 gives up by itself on a query taking too long, the counterexample is only a
+            // hint. Without a configured timeout it runs until it finds one.
+            let mut cmd = z3_cmd();
+            if let Some(secs) = CONFIG.fixpoint_timeout {
+                cmd.arg(format!("-T:{}", secs));
+            }
+            let out = run(cmd, Some(script))?;
+            let sexprs = SExpr::parse_all(&String::from_utf8_lossy(&out.stdout));
+            if let [SExpr::Atom(sat), SExpr::List(model), ..] = &sexprs[..] {
+                if sat == "sat" {
+                    return Ok(Some(parse_model(model)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn heads(&self, solution: &Solution) -> Vec<Head> {
+        let mut heads = vec![];
+        let mut path = Path {
+            decls: vec![],
+            hyps: vec![],
+            opaque: 0,
+        };
+        collect_heads(&self.constraint, solution, &mut path, &mut heads);
+        heads
+    }
+
+    /// Writes the binders and hypotheses of `path` and the negation of `goal`, which are
+    /// unsatisfiable iff the head holds. The constants must already be declared.
+    fn write_query(&self, w: &mut impl Write, path: &Path, goal: &str) -> fmt::Result {
+        write_decls(w, &path.decls)?;
+        for hyp in &path.hyps {
+            writeln!(w, "(assert {})", hyp)?;
+        }
+        writeln!(w, "(assert (not {}))", goal)?;
+        writeln!(w, "(check-sat)")
+    }
+}
+
+/// Matches the answers of z3 to the queries of `heads` with a goal, in order, and returns the heads
+/// that can't be proven. Heads without a goal always fail.
+fn failing_heads(heads: &[Head], out: &Output) -> Result<Vec<Error>, FixpointError> {
+    let answers = String::from_utf8_lossy(&out.stdout);
+    let mut answers = answers.split_whitespace();
+    let mut errors = vec![];
+    for (id, head) in heads.iter().enumerate() {
+        let valid = match head.goal {
+            Some(_) => match answers.next() {
+                Some("unsat") => true,
+                Some("sat" | "unknown") => false,
+                Some(answer) => {
+                    return Err(FixpointError::BadOutput(format!("`{}`", answer)));
+                }
+                None if !out.status.success() => {
+                    return Err(FixpointError::Crash {
+                        stderr: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+                    });
+                }
+                None => return Err(FixpointError::BadOutput("missing answer".to_string())),
+            },
+            None => false,
+        };
+        if !valid {
+            errors.push(Error {
+                id: id as i64,
+                tag: head.tag,
+            });
+        }
+    }
+    Ok(errors)
+}
+
+fn write_decls(w: &mut impl Write, decls: &[(Name, Sort)]) -> fmt::Result {
+    for (name, sort) in decls {
+        match sort {
+            Sort::Int => writeln!(w, "(declare-const {:?} Int)", name)?,
+            Sort::Bool => writeln!(w, "(declare-const {:?} Bool)", name)?,
+            Sort::Pred => writeln!(w, "(declare-fun {:?} (Int) Bool)", name)?,
+        }
+    }
+    Ok(())
+}
+
+fn z3_cmd() -> Command {
+    let mut cmd = Command::new(&CONFIG.z3);
+    cmd.arg("-in").arg("-smt2");
+    cmd
+}
+
+/// A head together with the binders and hypotheses in scope of it. The goal is `None` if the head
+/// or one of its hypotheses mentions a kvar without a solution.
+struct Head {
+    path: Path,
+    goal: Option<String>,
+    tag: Option<TagIdx>,
+}
+
+/// The binders and hypotheses in scope of a node, the latter already in SMT-LIB syntax. `opaque`
+/// counts the hypotheses that couldn't be translated.
+#[derive(Clone)]
+struct Path {
+    decls: Vec<(Name, Sort)>,
+    hyps: Vec<String>,
+    opaque: usize,
+}
+
+fn collect_heads(
+    constraint: &Constraint,
+    solution: &Solution,
+    path: &mut Path,
+    heads: &mut Vec<Head>,
+) {
+    match constraint {
+        Constraint::Pred(pred, tag) => {
+            let goal = match path.opaque {
+                0 => pred_to_smt(pred, solution),
+                _ => None,
+            };
+            heads.push(Head {
+                path: path.clone(),
+                goal,
+                tag: *tag,
+            });
+        }
+        Constraint::Conj(constraints) => {
+            for constraint in constraints {
+                collect_heads(constraint, solution, path, heads);
+            }
+        }
+        Constraint::Guard(expr, constraint) => {
+            path.hyps.push(SmtExpr::new(expr).to_string());
+            collect_heads(constraint, solution, path, heads);
+            path.hyps.pop();
+        }
+        Constraint::ForAll(name, sort, pred, constraint) => {
+            path.decls.push((*name, *sort));
+            match pred_to_smt(pred, solution) {
+                Some(hyp) => {
+                    path.hyps.push(hyp);
+                    collect_heads(constraint, solution, path, heads);
+                    path.hyps.pop();
+                }
+                None => {
+                    path.opaque += 1;
+                    collect_heads(constraint, solution, path, heads);
+                    path.opaque -= 1;
+                }
+            }
+            path.decls.pop();
+        }
+    }
+}
+
+/// Translates `pred` to SMT-LIB, or returns `None` if it mentions a kvar without a solution.
+fn pred_to_smt(pred: &Pred, solution: &Solution) -> Option<String> {
+    match pred {
+        Pred::And(preds) => {
+            let preds: Option<Vec<_>> = preds
+                .iter()
+                .map(|pred| pred_to_smt(pred, solution))
+                .collect();
+            Some(format!("(and true {})", preds?.join(" ")))
+        }
+        Pred::KVar(kvid, args) => {
+            let expr = solution.get(kvid)?;
+            Some(
+                SmtExpr {
+                    expr,
+                    args: Some(args),
+                }
+                .to_string(),
+            )
+        }
+        Pred::Expr(expr) => Some(SmtExpr::new(expr).to_string()),
+    }
+}
+
+/// An expression in SMT-LIB syntax. The variables of the solution of a kvar are the positions of
+/// its arguments, which are replaced by `args`.
+struct SmtExpr<'a> {
+    expr: &'a Expr,
+    args: Option<&'a [Name]>,
+}
+
+impl<'a> SmtExpr<'a> {
+    fn new(expr: &'a Expr) -> Self {
+        SmtExpr { expr, args: None }
+    }
+
+    fn with(&self, expr: &'a Expr) -> Self {
+        SmtExpr {
+            expr,
+            args: self.args,
+        }
+    }
+}
+
+impl fmt::Display for SmtExpr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expr {
+            Expr::Var(name) => {
+                let name = match self.args {
+                    Some(args) => args.get(name.index()).unwrap_or(name),
+                    None => name,
+                };
+                write!(f, "{:?}", name)
+            }
+            Expr::Constant(Constant::Int(Sign::Negative, n)) => {
+                write!(f, "(- {})", n)
+            }
+            Expr::Constant(c) => write!(f, "{}", c),
+            Expr::BinaryOp(BinOp::Ne, e1, e2) => {
+                write!(f, "(not (= {} {}))", self.with(e1), self.with(e2))
+            }
+            Expr::BinaryOp(op, e1, e2) => {
+                let op = match op {
+                    BinOp::Iff | BinOp::Eq => "=",
+                    BinOp::Imp => "=>",
+                    BinOp::Or => "or",
+                    BinOp::And => "and",
+                    BinOp::Gt => ">",
+                    BinOp::Ge => ">=",
+                    BinOp::Lt => "<",
+                    BinOp::Le => "<=",
+                    BinOp::Add => "+",
+                    BinOp::Sub => "-",
+                    BinOp::Mul => "*",
+                    BinOp::Div => "div",
+                    BinOp::Ne => unreachable!("handled above"),
+                };
+                write!(f, "({} {} {})", op, self.with(e1), self.with(e2))
+            }
+            Expr::UnaryOp(UnOp::Not, e) => write!(f, "(not {})", self.with(e)),
+            Expr::UnaryOp(UnOp::Neg, e) => write!(f, "(- {})", self.with(e)),
+            Expr::App(func, args) => {
+                write!(f, "({:?}", func)?;
+                for arg in args {
+                    write!(f, " {}", self.with(arg))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Parses the constants of a model, which z3 prints as a list of `(define-fun a3 () Int 3)`.
+/// Definitions of functions and of anything that isn't one of our names are skipped.
+fn parse_model(defs: &[SExpr]) -> Model {
+    defs.iter()
+        .filter_map(|def| match def {
+            SExpr::List(def) => match &def[..] {
+                [SExpr::Atom(define), SExpr::Atom(name), SExpr::List(params), _, value]
+                    if define == "define-fun" && params.is_empty() =>
+                {
+                    let name = Name::from_usize(name.strip_prefix('a')?.parse().ok()?);
+                    Some((name, value.to_constant()?))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum SExpr {
+    Atom(String),
+    List(Vec<SExpr>),
+}
+
+impl SExpr {
+    fn parse_all(text: &str) -> Vec<SExpr> {
+        let mut stack = vec![vec![]];
+        let mut atom = String::new();
+        for c in text.chars() {
+            if c == '(' || c == ')' || c.is_whitespace() {
+                if !atom.is_empty() {
+                    stack
+                        .last_mut()
+                        .unwrap()
+                        .push(SExpr::Atom(std::mem::take(&mut atom)));
+                }
+                if c == '(' {
+                    stack.push(vec![]);
+                } else if c == ')' && stack.len() > 1 {
+                    let list = stack.pop().unwrap();
+                    stack.last_mut().unwrap().push(SExpr::List(list));
+                }
+            } else {
+                atom.push(c);
+            }
+        }
+        if !atom.is_empty() {
+            stack.last_mut().unwrap().push(SExpr::Atom(atom));
+        }
+        stack.swap_remove(0)
+    }
+
+    fn to_constant(&self) -> Option<Constant> {
+        match self {
+            SExpr::Atom(atom) if atom == "true" => Some(Constant::from(true)),
+            SExpr::Atom(atom) if atom == "false" => Some(Constant::from(false)),
+            SExpr::Atom(atom) => Some(Constant::from(atom.parse::<u128>().ok()?)),
+            SExpr::List(list) => match &list[..] {
+                [SExpr::Atom(minus), SExpr::Atom(n)] if minus == "-" => {
+                    Some(Constant::from(-n.parse::<i128>().ok()?))
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KVid;
+    use itertools::Itertools;
+    use std::process::ExitStatus;
+
+    fn atom(atom: &str) -> SExpr {
+        SExpr::Atom(atom.to_string())
+    }
+
+    fn show(model: &Model) -> Vec<String> {
+        model
+            .iter()
+            .map(|(name, value)| format!("{:?} = {}", name, value))
+            .collect()
+    }
+
+    /// The output of z3 for a satisfiable query followed by `(get-model)`.
+    const Z3_OUTPUT: &str = "sat
+(
+  (define-fun a0 () Int
+    (- 3))
+  (define-fun a1 () Bool
+    false)
+  (define-fun a4 () Int
+    12)
+  (define-fun a2 ((x!0 Int)) Bool
+    true)
+  (define-fun k!0 () Int
+    1)
+)
+";
+
+    fn var(name: usize) -> Box<Expr> {
+        Box::new(Expr::Var(Name::from_usize(name)))
+    }
+
+    fn int(n: i128) -> Box<Expr> {
+        Box::new(Expr::Constant(Constant::from(n)))
+    }
+
+    fn output(stdout: &str, code: i32) -> Output {
+        use std::os::unix::process::ExitStatusExt;
+        Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: b"error: out of memory\n".to_vec(),
+        }
+    }
+
+    /// A constraint with a provable head, a head depending on a kvar and an unprovable head, in
+    /// that order and tagged with their position.
+    fn three_heads() -> Vec<Head> {
+        let tag = |i| Some(TagIdx::from_usize(i));
+        let constraint = Constraint::Conj(vec![
+            Constraint::ForAll(
+                Name::from_usize(0),
+                Sort::Int,
+                Pred::Expr(Expr::BinaryOp(BinOp::Gt, var(0), int(0))),
+                Box::new(Constraint::Pred(
+                    Pred::Expr(Expr::BinaryOp(BinOp::Ge, var(0), int(0))),
+                    tag(0),
+                )),
+            ),
+            Constraint::Pred(Pred::KVar(KVid::from_usize(0), vec![]), tag(1)),
+            Constraint::Pred(Pred::Expr(Expr::Constant(Constant::from(false))), tag(2)),
+        ]);
+        Fixpoint::new(vec![], vec![], constraint).heads(&Solution::default())
+    }
+
+    fn failing_tags(out: Output) -> Result<Vec<usize>, FixpointError> {
+        let errors = failing_heads(&three_heads(), &out)?;
+        Ok(errors.iter().map(|err| err.tag.unwrap().index()).collect())
+    }
+
+    #[test]
+    fn smt_expr() {
+        let show = |expr: &Expr| SmtExpr::new(expr).to_string();
+        assert_eq!(show(&int(-3)), "(- 3)");
+        assert_eq!(
+            show(&Expr::BinaryOp(BinOp::Ne, var(0), int(-1))),
+            "(not (= a0 (- 1)))"
+        );
+        assert_eq!(
+            show(&Expr::BinaryOp(
+                BinOp::Imp,
+                var(0),
+                Box::new(Expr::UnaryOp(UnOp::Not, var(1)))
+            )),
+            "(=> a0 (not a1))"
+        );
+        let app = Expr::App(
+            Name::from_usize(2),
+            vec![Expr::BinaryOp(BinOp::Sub, var(0), int(1))],
+        );
+        assert_eq!(show(&app), "(a2 (- a0 1))");
+    }
+
+    #[test]
+    fn smt_expr_kvar_args() {
+        let args = [Name::from_usize(5), Name::from_usize(7)];
+        let expr = Expr::BinaryOp(BinOp::Lt, var(0), var(1));
+        let smt = SmtExpr {
+            expr: &expr,
+            args: Some(&args),
+        };
+        assert_eq!(smt.to_string(), "(< a5 a7)");
+    }
+
+    #[test]
+    fn queries() {
+        let heads = three_heads();
+        let goals = heads.iter().map(|head| head.goal.as_deref()).collect_vec();
+        assert_eq!(goals, [Some("(>= a0 0)"), None, Some("false")]);
+        assert_eq!(heads[0].path.hyps, ["(> a0 0)"]);
+    }
+
+    #[test]
+    fn answers() {
+        assert_eq!(failing_tags(output("unsat\nsat\n", 0)).unwrap(), [1, 2]);
+        assert_eq!(failing_tags(output("unsat\nunsat\n", 0)).unwrap(), [1]);
+        assert_eq!(failing_tags(output("unknown\nunsat\n", 0)).unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn bad_answers() {
+        assert!(matches!(
+            failing_tags(output("unsat\n(error \"line 3\")\n", 0)),
+            Err(FixpointError::BadOutput(answer)) if answer == "`(error`"
+        ));
+        assert!(matches!(
+            failing_tags(output("unsat\n", 0)),
+            Err(FixpointError::BadOutput(answer)) if answer == "missing answer"
+        ));
+        assert!(matches!(
+            failing_tags(output("unsat\n", 1)),
+            Err(FixpointError::Crash { stderr }) if stderr == "error: out of memory"
+        ));
+    }
+
+    #[test]
+    fn parse_all_nested() {
+        let sexprs = SExpr::parse_all("sat\n((define-fun a0 () Int\n    (- 3)))\n");
+        let def = SExpr::List(vec![
+            atom("define-fun"),
+            atom("a0"),
+            SExpr::List(vec![]),
+            atom("Int"),
+            SExpr::List(vec![atom("-"), atom("3")]),
+        ]);
+        assert_eq!(sexprs, vec![atom("sat"), SExpr::List(vec![def])]);
+    }
+
+    #[test]
+    fn parse_all_unbalanced() {
+        assert_eq!(SExpr::parse_all("unsat) x"), vec![atom("unsat"), atom("x")]);
+        assert_eq!(SExpr::parse_all("sat (a"), vec![atom("sat")]);
+        assert_eq!(SExpr::parse_all(""), vec![]);
+    }
+
+    #[test]
+    fn parse_model_constants() {
+        let sexprs = SExpr::parse_all(Z3_OUTPUT);
+        let model = match &sexprs[..] {
+            [SExpr::Atom(sat), SExpr::List(model)] if sat == "sat" => parse_model(model),
+            _ => panic!("unexpected s-expressions: {:?}", sexprs),
+        };
+        assert_eq!(show(&model), ["a0 = -3", "a1 = false", "a4 = 12"]);
+    }
+}
@@ -0,0 +1,329 @@
+//! Parsing of the solutions fixpoint saves for kvars.
+//!
+//! With `--save`, fixpoint writes the solution next to the query in a section of the form
+//!
+//! ```text
+//! Solution:
+//! $k0 := ((lq_karg$k0##0 >= 0) && (lq_karg$k0##0 > lq_karg$k0##1))
+//! ```
+//!
+//! Solutions are expressed over the arguments of the kvar, which fixpoint names with a suffix
+//! `##i` giving their position. We parse them into expressions where the `i`-th argument is the
+//! [`Name`] with index `i`. Solutions mentioning anything else are skipped.
+use std::{collections::HashMap, iter::Peekable, str::Chars};
+
+use crate::{BinOp, Constant, Expr, KVid, Name, UnOp};
+
+/// The predicates assigned to kvars, see the [module level documentation](self).
+pub type Solution = HashMap<KVid, Expr>;
+
+pub(crate) fn parse(text: &str) -> Solution {
+    let section = match text.find("Solution:") {
+        Some(start) => &text[start + "Solution:".len()..],
+        None => return Solution::new(),
+    };
+    let section = section
+        .find("Non-cut")
+        .map_or(section, |end| &section[..end]);
+
+    let mut parser = Parser {
+        tokens: tokenize(section),
+        pos: 0,
+    };
+    let mut solution = Solution::new();
+    while let Some(kvid) = parser.next_binding() {
+        if let Some(expr) = parser.expr() {
+            solution.insert(kvid, expr);
+        }
+    }
+    solution
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Ident(String),
+    Int(u128),
+    Op(&'static str),
+}
+
+const OPS: &[&str] = &[
+    ":=", "<=>", "=>", "==", "!=", "/=", "<=", ">=", "&&", "||", "=", "<", ">", "+", "-", "*", "/",
+    "~", "(", ")", "[", "]", ";",
+];
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let digits = take_while(&mut chars, |c| c.is_ascii_digit());
+            match digits.parse() {
+                Ok(n) => tokens.push(Token::Int(n)),
+                Err(_) => tokens.push(Token::Op("?")),
+            }
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let ident = take_while(&mut chars, |c| {
+                c.is_alphanumeric() || matches!(c, '_' | '$' | '#' | '\'' | '.')
+            });
+            tokens.push(Token::Ident(ident));
+        } else {
+            let rest: String = chars.clone().take(3).collect();
+            match OPS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    for _ in 0..op.len() {
+                        chars.next();
+                    }
+                    tokens.push(Token::Op(op));
+                }
+                None => {
+                    chars.next();
+                    tokens.push(Token::Op("?"));
+                }
+            }
+        }
+    }
+    tokens
+}
+
+fn take_while(chars: &mut Peekable<Chars>, pred: impl Fn(char) -> bool) -> String {
+    let mut s = String::new();
+    while let Some(&c) = chars.peek() {
+        if !pred(c) {
+            break;
+        }
+        s.push(c);
+        chars.next();
+    }
+    s
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    /// Skips to the next `$kN :=` and returns the kvar.
+    fn next_binding(&mut self) -> Option<KVid> {
+        while self.pos + 1 < self.tokens.len() {
+            if let (Token::Ident(ident), Token::Op(":=")) =
+                (&self.tokens[self.pos], &self.tokens[self.pos + 1])
+            {
+                if let Some(kvid) = parse_kvid(ident) {
+                    self.pos += 2;
+                    return Some(kvid);
+                }
+            }
+            self.pos += 1;
+        }
+        None
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, op: &'static str) -> bool {
+        if self.peek() == Some(&Token::Op(op)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_ident(&mut self, ident: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(s)) if s == ident) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> Option<Expr> {
+        self.binary(0)
+    }
+
+    /// Parses a binary expression whose operators bind at least as tight as `level`.
+    fn binary(&mut self, level: usize) -> Option<Expr> {
+        const LEVELS: &[&[(&str, BinOp)]] = &[
+            &[("<=>", BinOp::Iff)],
+            &[("=>", BinOp::Imp)],
+            &[("||", BinOp::Or)],
+            &[("&&", BinOp::And)],
+            &[
+                ("==", BinOp::Eq),
+                ("=", BinOp::Eq),
+                ("!=", BinOp::Ne),
+                ("/=", BinOp::Ne),
+                ("<=", BinOp::Le),
+                (">=", BinOp::Ge),
+                ("<", BinOp::Lt),
+                (">", BinOp::Gt),
+            ],
+            &[("+", BinOp::Add), ("-", BinOp::Sub)],
+            &[("*", BinOp::Mul), ("/", BinOp::Div)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut e1 = self.binary(level + 1)?;
+        loop {
+            let op = LEVELS[level]
+                .iter()
+                .find(|(token, _)| self.peek() == Some(&Token::Op(token)));
+            match op {
+                // `&& [e1; e2]` is a conjunction of a list and not an infix operator.
+                Some(_) if self.tokens.get(self.pos + 1) == Some(&Token::Op("[")) => break,
+                Some((_, op)) => {
+                    self.pos += 1;
+                    let e2 = self.binary(level + 1)?;
+                    e1 = Expr::BinaryOp(*op, Box::new(e1), Box::new(e2));
+                }
+                None => break,
+            }
+        }
+        Some(e1)
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        if self.eat("~") || self.eat_ident("not") {
+            return Some(Expr::UnaryOp(UnOp::Not, Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Some(Expr::UnaryOp(UnOp::Neg, Box::new(self.unary()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Option<Expr> {
+        let token = self.peek()?.clone();
+        self.pos += 1;
+        match token {
+            Token::Int(n) => Some(Expr::Constant(Constant::from(n))),
+            Token::Ident(ident) if ident == "true" => Some(Expr::Constant(Constant::from(true))),
+            Token::Ident(ident) if ident == "false" => Some(Expr::Constant(Constant::from(false))),
+            Token::Ident(ident) => {
+                let (_, index) = ident.rsplit_once("##")?;
+                Some(Expr::Var(Name::from_usize(index.parse().ok()?)))
+            }
+            Token::Op("(") => {
+                let e = self.expr()?;
+                self.eat(")").then(|| e)
+            }
+            Token::Op(op @ ("&&" | "||")) => {
+                let (op, unit) = if op == "&&" {
+                    (BinOp::And, true)
+                } else {
+                    (BinOp::Or, false)
+                };
+                if !self.eat("[") {
+                    return None;
+                }
+                let mut exprs = vec![];
+                while !self.eat("]") {
+                    exprs.push(self.expr()?);
+                    self.eat(";");
+                }
+                let e = exprs
+                    .into_iter()
+                    .reduce(|e1, e2| Expr::BinaryOp(op, Box::new(e1), Box::new(e2)))
+                    .unwrap_or_else(|| Expr::Constant(Constant::from(unit)));
+                Some(e)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn parse_kvid(ident: &str) -> Option<KVid> {
+    let index = ident.strip_prefix('$')?.strip_prefix('k')?;
+    Some(KVid::from_usize(index.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    /// Prints an expression fully parenthesized to check how it was parsed.
+    fn show(expr: &Expr) -> String {
+        match expr {
+            Expr::Var(name) => format!("{:?}", name),
+            Expr::Constant(c) => format!("{}", c),
+            Expr::BinaryOp(op, e1, e2) => format!("({} {} {})", show(e1), op, show(e2)),
+            Expr::UnaryOp(op, e) => format!("({}{})", op, show(e)),
+            Expr::App(func, args) => {
+                format!("({:?} {})", func, args.iter().map(show).join(" "))
+            }
+        }
+    }
+
+    fn parse_one(kvid: usize, text: &str) -> Option<String> {
+        let solution = parse(&format!("Solution:\n$k{} := {}\n", kvid, text));
+        solution.get(&KVid::from_usize(kvid)).map(show)
+    }
+
+    #[test]
+    fn section() {
+        let text = "
+Result: Safe
+
+Solution:
+$k0 := ((lq_karg$k0##0 >= 0) && (lq_karg$k0##0 > lq_karg$k0##1))
+$k1 := && []
+$k2 := lq_karg$k2##0 > f(1)
+
+Non-cut kvars:
+$k3 := true
+";
+        let solution = parse(text);
+        assert_eq!(solution.len(), 2);
+        assert_eq!(
+            show(&solution[&KVid::from_usize(0)]),
+            "((a0 >= 0) && (a0 > a1))"
+        );
+        assert_eq!(show(&solution[&KVid::from_usize(1)]), "true");
+    }
+
+    #[test]
+    fn no_section() {
+        assert!(parse("Result: Safe\n").is_empty());
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse_one(0, "x##0 = 1 + 2 * x##1 - 3").unwrap(),
+            "(a0 = ((1 + (2 * a1)) - 3))"
+        );
+        assert_eq!(
+            parse_one(0, "x##0 < 0 || x##1 > 0 => x##0 /= x##1").unwrap(),
+            "(((a0 < 0) || (a1 > 0)) => (a0 /= a1))"
+        );
+    }
+
+    #[test]
+    fn unary() {
+        assert_eq!(parse_one(0, "~ (x##0 < -1)").unwrap(), "(~(a0 < (-1)))");
+        assert_eq!(parse_one(0, "not x##0").unwrap(), "(~a0)");
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            parse_one(0, "&& [x##0 > 0; x##1 > 0; true]").unwrap(),
+            "(((a0 > 0) && (a1 > 0)) && true)"
+        );
+        assert_eq!(parse_one(0, "|| []").unwrap(), "false");
+    }
+
+    #[test]
+    fn unknown_names() {
+        assert_eq!(parse_one(0, "x > 0"), None);
+        assert_eq!(parse_one(0, "(x##0 > 0"), None);
+    }
+}
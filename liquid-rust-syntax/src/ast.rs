@@ -12,6 +12,13 @@ pub struct FnSig {
     pub span: Span,
 }
 
+/// A signature for a function defined in another crate, e.g., in a `.lrspec` file.
+#[derive(Debug)]
+pub struct ExternFn {
+    pub path: Vec<Path>,
+    pub fn_sig: FnSig,
+}
+
 #[derive(Debug)]
 pub struct Generics {
     pub params: Vec<GenericParam>,
@@ -26,10 +33,35 @@ pub struct Ty {
 
 #[derive(Debug)]
 pub enum TyKind {
-    BaseTy(Path),
-    RefineTy { path: Path, refine: Expr },
-    Exists { bind: Ident, path: Path, pred: Expr },
+    BaseTy(BaseTy),
+    RefineTy {
+        bty: BaseTy,
+        refine: Expr,
+    },
+    Exists {
+        bind: Ident,
+        bty: BaseTy,
+        pred: Expr,
+    },
+    /// A base type whose refinement is left to be inferred, written `B{_}` or `B@_`.
+    Infer(BaseTy),
+    Tuple(Vec<Ty>),
+    /// An array `[T; N]` where `N` is a literal.
+    Array(Box<Ty>, Lit),
     MutRef(Ident),
+    /// A shared reference `&T`.
+    Ref(Box<Ty>),
+    /// The never type `!`, for functions that do not return.
+    Never,
+    /// A function type `fn(T1, ..., Tn) -> R`.
+    Fn(Vec<Ty>, Box<Ty>),
+}
+
+/// A type that can be refined. Slices are refined by their length.
+#[derive(Debug)]
+pub enum BaseTy {
+    Path(Path),
+    Slice(Box<Ty>),
 }
 
 #[derive(Debug)]
@@ -42,10 +74,18 @@ pub struct Path {
 #[derive(Debug)]
 pub struct GenericParam {
     pub name: Ident,
-    pub sort: Ident,
+    pub sort: Sort,
     pub pred: Option<Expr>,
 }
 
+#[derive(Debug)]
+pub enum Sort {
+    /// A basic sort, e.g., `int` or `bool`.
+    Base(Ident),
+    /// A function sort `s1 -> s2`, the sort of abstract refinements.
+    Func { input: Ident, output: Ident },
+}
+
 #[derive(Debug)]
 pub struct Expr {
     pub kind: ExprKind,
@@ -57,6 +97,8 @@ pub enum ExprKind {
     Var(Ident),
     Literal(Lit),
     BinaryOp(BinOp, Box<Expr>, Box<Expr>),
+    /// The application `p(e1, ..., en)` of an abstract refinement.
+    App(Ident, Vec<Expr>),
 }
 
 #[derive(Debug)]
@@ -33,6 +33,10 @@ pub enum Token {
     CloseDelim(DelimToken),
     Invalid,
     Ref,
+    Not,
+    And,
+    PathSep,
+    Underscore,
 }
 
 pub(crate) struct Cursor {
@@ -85,6 +89,8 @@ impl Cursor {
             TokenKind::Colon => Token::Colon,
             TokenKind::Semi => Token::Semi,
             TokenKind::RArrow => Token::RArrow,
+            TokenKind::Not => Token::Not,
+            TokenKind::ModSep => Token::PathSep,
             TokenKind::OpenDelim(delim) => Token::OpenDelim(delim),
             TokenKind::CloseDelim(delim) => Token::CloseDelim(delim),
             TokenKind::Literal(lit) if lit.suffix.is_none() => Token::Literal(lit),
@@ -95,12 +101,14 @@ impl Cursor {
                     suffix: None,
                 })
             }
+            TokenKind::Ident(symb, _) if symb == kw::Underscore => Token::Underscore,
             TokenKind::Ident(symb, _) if symb == self.symbs.ref_ => Token::Ref,
             TokenKind::Ident(symb, _) if symb == self.symbs.fn_ => Token::Fn,
             TokenKind::Ident(symb, _) => Token::Ident(symb),
             TokenKind::BinOp(BinOpToken::Or) => Token::Caret,
             TokenKind::BinOp(BinOpToken::Plus) => Token::Plus,
             TokenKind::BinOp(BinOpToken::Minus) => Token::Minus,
+            TokenKind::BinOp(BinOpToken::And) => Token::And,
             _ => Token::Invalid,
         };
         (
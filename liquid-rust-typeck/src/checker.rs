@@ -10,26 +10,32 @@ extern crate rustc_span;
 use std::collections::hash_map::Entry;
 
 use crate::{
-    constraint_builder::{ConstraintBuilder, Cursor},
+    constraint_builder::{ConstraintBuilder, Cursor, Tag},
+    errors,
     global_env::GlobalEnv,
-    lowering,
-    ty::{self, BaseTy, BinOp, Expr, ExprKind, Loc, Ty, TyKind, Var},
+    lowering::{self, InferenceError},
+    pretty::{NameMap, Pretty, WithCx},
+    ty::{self, BaseTy, BinOp, Expr, ExprKind, Loc, Pred, Sort, Ty, TyKind, UintTy, Var},
     type_env::TypeEnv,
 };
 use itertools::Itertools;
-use liquid_rust_common::errors::ErrorReported;
+use liquid_rust_common::{config::CONFIG, errors::ErrorReported};
 use liquid_rust_core::{
     ir::{
-        self, BasicBlock, Body, Constant, Operand, Place, Rvalue, SourceInfo, Statement,
-        StatementKind, Terminator, TerminatorKind, RETURN_PLACE, START_BLOCK,
+        self, AggregateKind, BasicBlock, Body, Constant, Operand, Place, Rvalue, SourceInfo,
+        Statement, StatementKind, Terminator, TerminatorKind, RETURN_PLACE, START_BLOCK,
     },
     ty as core,
 };
 use rustc_hash::FxHashMap;
 use rustc_hir::def_id::DefId;
 use rustc_index::bit_set::BitSet;
-use rustc_middle::mir;
+use rustc_middle::{
+    mir::{self, Mutability},
+    ty::TyCtxt,
+};
 use rustc_session::Session;
+use rustc_span::{Span, Symbol};
 
 use super::type_env::TypeEnvShape;
 
@@ -44,6 +50,48 @@ pub struct Checker<'a, 'tcx> {
     global_env: &'a GlobalEnv<'tcx>,
     ensures: Vec<(Loc, Ty)>,
     mode: Mode,
+    /// The names of the user variables, used to present counterexamples.
+    local_names: FxHashMap<ir::Local, Symbol>,
+}
+
+/// An error in a statement or terminator, reported at its span.
+#[derive(Debug)]
+pub enum CheckerError {
+    /// A read of a place that was moved out or was never initialized.
+    UseOfUninit,
+    /// A move out of a place behind a reference.
+    MoveBehindRef,
+    /// A write to, or a mutable borrow of, a place behind a shared reference.
+    MutationBehindShrRef,
+    /// Two types we don't know how to relate, e.g., by subtyping or by joining them.
+    IncompatibleTypes(Ty, Ty),
+    /// An operation applied to operands of types it doesn't support.
+    UnsupportedOperands(String, Vec<Ty>),
+    /// The locations required by a call cannot be inferred from its arguments.
+    CannotInferLocations,
+    /// A type parameter refined by an expression instantiated with a type whose values are not
+    /// integers.
+    RefinedParamInstance(core::ParamTy, BaseTy),
+}
+
+impl From<InferenceError> for CheckerError {
+    fn from(_: InferenceError) -> Self {
+        CheckerError::CannotInferLocations
+    }
+}
+
+impl CheckerError {
+    pub fn incompatible_btys(bty1: &BaseTy, bty2: &BaseTy) -> CheckerError {
+        CheckerError::IncompatibleTypes(unrefined(bty1), unrefined(bty2))
+    }
+
+    pub fn unsupported_bty(op: &str, bty: &BaseTy) -> CheckerError {
+        CheckerError::UnsupportedOperands(op.to_string(), vec![unrefined(bty)])
+    }
+}
+
+fn unrefined(bty: &BaseTy) -> Ty {
+    TyKind::Exists(bty.clone(), Pred::Expr(Expr::tt())).intern()
 }
 
 enum Mode {
@@ -56,55 +104,108 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
         global_env: &GlobalEnv<'tcx>,
         body: &Body<'tcx>,
         fn_sig: &core::FnSig,
+        substs: &[core::Ty],
     ) -> Result<FxHashMap<BasicBlock, TypeEnvShape>, ErrorReported> {
-        let (checker, _) = Checker::check_or_infer(global_env, body, fn_sig, Mode::Inference)?;
-        Ok(checker
-            .bb_envs
-            .into_iter()
-            .map(|(bb, env)| (bb, env.into_shape()))
-            .collect())
+        let mut constraint = ConstraintBuilder::new(global_env.tcx);
+        let checker = Checker::check_or_infer(
+            global_env,
+            &mut constraint,
+            body,
+            fn_sig,
+            substs,
+            Mode::Inference,
+        )?;
+        Ok(checker.into_bb_env_shapes())
     }
 
+    /// Checks the body adding the constraints it generates to `constraint`. Returns the
+    /// environments of the join points, whose refinements are kvars to be solved by fixpoint.
     pub fn check(
         global_env: &GlobalEnv<'tcx>,
+        constraint: &mut ConstraintBuilder<'tcx>,
         body: &Body<'tcx>,
         fn_sig: &core::FnSig,
+        substs: &[core::Ty],
         bb_env_shapes: FxHashMap<BasicBlock, TypeEnvShape>,
+    ) -> Result<FxHashMap<BasicBlock, TypeEnv<'tcx>>, ErrorReported> {
+        let checker = Checker::check_or_infer(
+            global_env,
+            constraint,
+            body,
+            fn_sig,
+            substs,
+            Mode::Check(bb_env_shapes),
+        )?;
+        Ok(checker.bb_envs)
+    }
+
+    /// Checks that `fn_sig` refines `super_sig` instantiated with `super_substs`, i.e., that a
+    /// function with signature `fn_sig` can be used wherever one with signature `super_sig` is
+    /// expected.
+    pub fn check_refines(
+        global_env: &GlobalEnv<'tcx>,
+        span: Span,
+        fn_sig: &core::FnSig,
+        super_sig: &core::FnSig,
+        super_substs: &[core::Ty],
     ) -> Result<ConstraintBuilder<'tcx>, ErrorReported> {
-        let (_, constraint) =
-            Checker::check_or_infer(global_env, body, fn_sig, Mode::Check(bb_env_shapes))?;
+        let mut constraint = ConstraintBuilder::new(global_env.tcx);
+        let cursor = &mut constraint.as_cursor();
+
+        let mut env = TypeEnv::new(global_env.tcx);
+        let (mut subst, args) = enter_fn_sig(&mut env, cursor, super_sig, super_substs);
+
+        let tcx = global_env.tcx;
+        let report = |cursor: &Cursor, err| {
+            let names = cursor.name_map(&FxHashMap::default());
+            report_checker_error(tcx, &names, span, err)
+        };
+        let ret = call_fn_sig(&mut env, cursor, None, fn_sig, &[], args);
+        let ret = ret.map_err(|err| match err {
+            CheckerError::CannotInferLocations => {
+                tcx.sess.span_err(
+                    span,
+                    "cannot instantiate the parameters of this spec from the trait's spec",
+                );
+                ErrorReported
+            }
+            err => report(cursor, err),
+        })?;
+
+        let super_ret = subst.lower_ty(cursor, &super_sig.ret);
+        let res = cursor.subtyping(ret, super_ret);
+        res.map_err(|err| report(cursor, err))?;
+
+        for (name, ensured_ty) in &super_sig.ensures {
+            let actual_ty = subst
+                .lower_loc(*name)
+                .ok_or(CheckerError::CannotInferLocations)
+                .and_then(|loc| env.lookup_referee(loc));
+            let actual_ty = actual_ty.map_err(|err| report(cursor, err))?;
+            let ensured_ty = subst.lower_ty(cursor, ensured_ty);
+            let res = cursor.subtyping(actual_ty, ensured_ty);
+            res.map_err(|err| report(cursor, err))?;
+        }
+
         Ok(constraint)
     }
 
     fn check_or_infer(
         global_env: &'a GlobalEnv<'tcx>,
+        constraint: &mut ConstraintBuilder<'tcx>,
         body: &'a Body<'tcx>,
         fn_sig: &core::FnSig,
+        substs: &[core::Ty],
         mode: Mode,
-    ) -> Result<(Checker<'a, 'tcx>, ConstraintBuilder<'tcx>), ErrorReported> {
-        let mut constraint = ConstraintBuilder::new(global_env.tcx);
+    ) -> Result<Checker<'a, 'tcx>, ErrorReported> {
         let cursor = &mut constraint.as_cursor();
 
-        let mut subst = lowering::Subst::with_empty_type_substs();
-
         let mut env = TypeEnv::new(global_env.tcx);
 
-        for param in &fn_sig.params {
-            let fresh = cursor.fresh_name();
-            subst.insert_expr(param.name.name, Var::Free(fresh));
-            cursor.push_forall(fresh, param.sort, subst.lower_expr(&param.pred));
-        }
+        let (mut subst, args) = enter_fn_sig(&mut env, cursor, fn_sig, substs);
 
-        for (loc, ty) in &fn_sig.requires {
-            let ty = subst.lower_ty(cursor, ty);
-            let fresh = Loc::Abstract(cursor.fresh_name());
-            env.insert_loc(fresh, cursor.unpack(ty));
-            subst.insert_loc(*loc, fresh);
-        }
-
-        for (local, ty) in body.args_iter().zip(&fn_sig.args) {
-            let ty = subst.lower_ty(cursor, ty);
-            env.insert_loc(Loc::Local(local), cursor.unpack(ty));
+        for (local, ty) in body.args_iter().zip(args) {
+            env.insert_loc(Loc::Local(local), ty);
         }
 
         for local in body.vars_and_temps_iter() {
@@ -112,23 +213,31 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
         }
 
         env.insert_loc(Loc::Local(RETURN_PLACE), TyKind::Uninit.intern());
+        // Shared references received as arguments point to a fresh weak location.
+        env.unpack_args(cursor);
 
-        let ensures = fn_sig
+        let ensures: Vec<_> = fn_sig
             .ensures
             .iter()
             .map(|(name, ty)| {
                 let ty = subst.lower_ty(cursor, ty);
-                let loc = subst.lower_loc(*name).unwrap();
-                (loc, ty)
+                let loc = subst.lower_loc(*name).ok_or_else(|| {
+                    global_env.tcx.sess.span_err(
+                        body.mir.span,
+                        "cannot check a function ensuring a location it doesn't require",
+                    );
+                    ErrorReported
+                })?;
+                Ok((loc, ty))
             })
-            .collect();
+            .try_collect()?;
 
         let ret_ty = subst.lower_ty(cursor, &fn_sig.ret);
 
         let mut checker = Checker::new(global_env, body, ret_ty, ensures, mode);
         checker.run(&mut env, cursor)?;
 
-        Ok((checker, constraint))
+        Ok(checker)
     }
 
     fn new(
@@ -154,14 +263,29 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
             ret_ty,
             ensures,
             mode,
+            local_names: local_names(body),
         }
     }
 
+    fn into_bb_env_shapes(self) -> FxHashMap<BasicBlock, TypeEnvShape> {
+        self.bb_envs
+            .into_iter()
+            .map(|(bb, env)| (bb, env.into_shape()))
+            .collect()
+    }
+
     fn run(&mut self, env: &mut TypeEnv<'tcx>, cursor: &mut Cursor) -> Result<(), ErrorReported> {
-        self.check_goto(env, cursor, START_BLOCK)?;
+        cursor.declare_adt_fields(&self.body.adt_fields);
+        let source_info = SourceInfo::outermost(self.body.mir.span);
+        self.check_goto(env, cursor, source_info, START_BLOCK)?;
         for bb in self.body.reverse_postorder() {
-            if !self.visited.contains(bb) {
-                let mut env = self.bb_envs[&bb].clone();
+            if self.visited.contains(bb) {
+                continue;
+            }
+            // A block without an environment is only reachable through edges we don't follow,
+            // e.g., the return edge of a call lowered to `Diverge`, so it is dead code.
+            if let Some(env) = self.bb_envs.get(&bb) {
+                let mut env = env.clone();
                 env.unpack(cursor);
                 self.check_basic_block(&mut env, cursor, bb)?;
             }
@@ -182,8 +306,10 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
         }
 
         let data = &self.body.basic_blocks[bb];
+        env.name_vars(cursor, &self.local_names);
         for stmt in &data.statements {
-            self.check_statement(env, cursor, stmt);
+            self.check_statement(env, cursor, stmt)?;
+            env.name_vars(cursor, &self.local_names);
         }
         if let Some(terminator) = &data.terminator {
             self.check_terminator(env, cursor, terminator)?;
@@ -191,16 +317,66 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
         Ok(())
     }
 
-    fn check_statement(&self, env: &mut TypeEnv<'tcx>, cursor: &mut Cursor, stmt: &Statement) {
+    fn check_statement(
+        &self,
+        env: &mut TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        stmt: &Statement,
+    ) -> Result<(), ErrorReported> {
         match &stmt.kind {
+            StatementKind::Assign(p, Rvalue::FnPointer(func, substs, fn_ty)) => {
+                cursor.set_tag(Tag::Assign(stmt.source_info.span));
+                let ty = self.check_fn_pointer(cursor, stmt.source_info, *func, substs, fn_ty);
+                let res = ty.and_then(|ty| env.write_place(cursor, p, ty));
+                self.report_error(cursor, stmt.source_info, res)
+            }
+            StatementKind::Assign(p, Rvalue::ClosureFnPointer(did, op)) => {
+                cursor.set_tag(Tag::Assign(stmt.source_info.span));
+                let (formals, ret) = self.check_closure(env, cursor, stmt.source_info, *did, op)?;
+                let res = env.write_place(cursor, p, TyKind::fn_ty(formals, ret));
+                self.report_error(cursor, stmt.source_info, res)
+            }
             StatementKind::Assign(p, rvalue) => {
-                let ty = self.check_rvalue(env, cursor, rvalue);
-                env.write_place(cursor, p, ty);
+                cursor.set_tag(Tag::Assign(stmt.source_info.span));
+                let ty = self.check_rvalue(env, cursor, stmt.source_info, rvalue);
+                let res = ty.and_then(|ty| env.write_place(cursor, p, ty));
+                self.report_error(cursor, stmt.source_info, res)
             }
-            StatementKind::Nop => {}
+            StatementKind::Nop => Ok(()),
         }
     }
 
+    /// The refinements of a function pointer are inferred, and the signature of the function
+    /// coerced to it must refine them.
+    fn check_fn_pointer(
+        &self,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        func: DefId,
+        substs: &[core::Ty],
+        fn_ty: &core::Ty,
+    ) -> Result<Ty, CheckerError> {
+        let fn_ty = lowering::Subst::with_empty_type_substs().lower_ty(cursor, fn_ty);
+        if let TyKind::Fn(formals, ret) = fn_ty.kind() {
+            let cursor = &mut cursor.snapshot();
+            let mut env = TypeEnv::new(self.global_env.tcx);
+            let actuals = formals
+                .iter()
+                .map(|ty| cursor.unpack(ty.clone()))
+                .collect_vec();
+            let fn_sig = self.global_env.lookup_fn_sig(func);
+            let mut subst = lowering::Subst::with_type_substs(cursor, substs);
+            check_refined_params(fn_sig, &subst)?;
+            subst.infer_from_fn_call(&env, cursor, &actuals, fn_sig)?;
+            let call_site = Some((source_info.span, func));
+            check_preconditions(&mut env, cursor, call_site, fn_sig, &mut subst, actuals)?;
+            let actual_ret = subst.lower_ty(cursor, &fn_sig.ret);
+            let actual_ret = cursor.unpack(actual_ret);
+            cursor.subtyping(actual_ret, ret.clone())?;
+        }
+        Ok(fn_ty)
+    }
+
     fn check_terminator(
         &mut self,
         env: &mut TypeEnv<'tcx>,
@@ -209,25 +385,35 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
     ) -> Result<(), ErrorReported> {
         match &terminator.kind {
             TerminatorKind::Return => {
-                let ret_place_ty = env.lookup_local(RETURN_PLACE);
-                cursor.subtyping(ret_place_ty, self.ret_ty.clone());
+                // Optimized MIR doesn't always initialize the return place when returning `()`.
+                let returns_unit =
+                    matches!(self.ret_ty.kind(), TyKind::Tuple(tys) if tys.is_empty());
+                cursor.set_tag(Tag::Ret(terminator.source_info.span));
+                if !returns_unit {
+                    let ret_place_ty = env.lookup_local(RETURN_PLACE);
+                    let res = cursor.subtyping(ret_place_ty, self.ret_ty.clone());
+                    self.report_error(cursor, terminator.source_info, res)?;
+                }
 
                 for (loc, ensured_ty) in &self.ensures {
-                    let actual_ty = env.lookup_loc(*loc).unwrap();
-                    cursor.subtyping(actual_ty, ensured_ty.clone());
+                    let res = env
+                        .lookup_referee(*loc)
+                        .and_then(|actual_ty| cursor.subtyping(actual_ty, ensured_ty.clone()));
+                    self.report_error(cursor, terminator.source_info, res)?;
                 }
             }
             TerminatorKind::Goto { target } => {
-                self.check_goto(env, cursor, *target)?;
+                self.check_goto(env, cursor, terminator.source_info, *target)?;
             }
             TerminatorKind::SwitchInt { discr, targets } => {
-                self.check_switch_int(env, cursor, discr, targets)?;
+                self.check_switch_int(env, cursor, terminator.source_info, discr, targets)?;
             }
             TerminatorKind::Call {
                 func,
                 substs,
                 args,
                 destination,
+                panics,
             } => {
                 self.check_call(
                     env,
@@ -237,11 +423,73 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
                     substs,
                     args,
                     destination,
+                    *panics,
+                )?;
+            }
+            TerminatorKind::CallClosure {
+                closure,
+                upvars,
+                args,
+                destination,
+            } => {
+                let ret = self.check_call_closure(
+                    env,
+                    cursor,
+                    terminator.source_info,
+                    *closure,
+                    upvars,
+                    args,
                 )?;
+                self.check_call_ret(env, cursor, terminator.source_info, destination, ret)?;
+            }
+            TerminatorKind::CallIndirect {
+                callee,
+                args,
+                destination,
+            } => {
+                let ret =
+                    self.check_call_indirect(env, cursor, terminator.source_info, callee, args)?;
+                self.check_call_ret(env, cursor, terminator.source_info, destination, ret)?;
             }
             TerminatorKind::Drop { place, target } => {
-                let _ = env.move_place(place);
-                self.check_goto(env, cursor, *target)?;
+                cursor.set_tag(Tag::Assign(terminator.source_info.span));
+                // Dropping a place behind a reference doesn't move out of it.
+                let _ = env.move_place(cursor, place);
+                self.check_goto(env, cursor, terminator.source_info, *target)?;
+            }
+            TerminatorKind::DropAndReplace {
+                place,
+                value,
+                target,
+            } => {
+                cursor.set_tag(Tag::Assign(terminator.source_info.span));
+                let ty = self.check_operand(env, cursor, value);
+                let res = ty.and_then(|ty| {
+                    let _ = env.move_place(cursor, place);
+                    env.write_place(cursor, place, ty)
+                });
+                self.report_error(cursor, terminator.source_info, res)?;
+                self.check_goto(env, cursor, terminator.source_info, *target)?;
+            }
+            TerminatorKind::Diverge { func, substs, args } => {
+                self.check_diverge(env, cursor, terminator.source_info, *func, substs, args)?;
+            }
+            TerminatorKind::Unreachable => {
+                cursor.push_tagged_head(Expr::ff(), Tag::Unreachable(terminator.source_info.span));
+            }
+            TerminatorKind::Assert {
+                cond,
+                expected,
+                target,
+            } => {
+                self.check_assert(
+                    env,
+                    cursor,
+                    terminator.source_info,
+                    cond,
+                    *expected,
+                    *target,
+                )?;
             }
         }
         Ok(())
@@ -255,86 +503,256 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
         func: DefId,
         substs: &[core::Ty],
         args: &[Operand],
-        destination: &Option<(Place, BasicBlock)>,
+        destination: &(Place, BasicBlock),
+        panics: bool,
     ) -> Result<(), ErrorReported> {
         let fn_sig = self.global_env.lookup_fn_sig(func);
-        let actuals = args
-            .iter()
-            .map(|arg| self.check_operand(env, arg))
-            .collect_vec();
+        let actuals = self.check_operands(env, cursor, source_info, args)?;
 
-        let mut subst = lowering::Subst::with_type_substs(cursor, substs);
-        if subst.infer_from_fn_call(env, &actuals, fn_sig).is_err() {
-            return self.report_inference_error(source_info);
+        // The preconditions of a callee that panics when they don't hold are the panic condition.
+        let call_site = if panics {
+            cursor.set_tag(Tag::Panic(source_info.span));
+            None
+        } else {
+            Some((source_info.span, func))
         };
+        let ret = call_fn_sig(env, cursor, call_site, fn_sig, substs, actuals);
+        let ret = self.report_error(cursor, source_info, ret)?;
 
-        for param in &fn_sig.params {
-            cursor.push_head(subst.lower_expr(&param.pred));
-        }
+        self.check_call_ret(env, cursor, source_info, destination, ret)
+    }
+
+    /// Writes the value returned by a call into its destination and continues with the block the
+    /// call returns to.
+    fn check_call_ret(
+        &mut self,
+        env: &mut TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        destination: &(Place, BasicBlock),
+        ret: Ty,
+    ) -> Result<(), ErrorReported> {
+        let (p, bb) = destination;
+        cursor.set_tag(Tag::Assign(source_info.span));
+        let res = env.write_place(cursor, p, ret);
+        self.report_error(cursor, source_info, res)?;
 
-        for (actual, formal) in actuals.into_iter().zip(&fn_sig.args) {
-            let formal = subst.lower_ty(cursor, formal);
-            cursor.subtyping(actual, formal);
+        self.check_goto(env, cursor, source_info, *bb)
+    }
+
+    /// A call that never returns must be unreachable. When the callee has a signature, its
+    /// arguments are checked against it first and the call is only required to be unreachable
+    /// when its preconditions hold, so a call violating them is reported as such.
+    fn check_diverge(
+        &mut self,
+        env: &mut TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        func: DefId,
+        substs: &[core::Ty],
+        args: &[Operand],
+    ) -> Result<(), ErrorReported> {
+        let actuals = self.check_operands(env, cursor, source_info, args)?;
+        if let Some(fn_sig) = self.global_env.try_lookup_fn_sig(func) {
+            let mut subst = lowering::Subst::with_type_substs(cursor, substs);
+            let res = check_refined_params(fn_sig, &subst)
+                .and_then(|_| {
+                    subst
+                        .infer_from_fn_call(env, cursor, &actuals, fn_sig)
+                        .map_err(CheckerError::from)
+                })
+                .and_then(|_| {
+                    let call_site = Some((source_info.span, func));
+                    check_preconditions(env, cursor, call_site, fn_sig, &mut subst, actuals.clone())
+                });
+            self.report_error(cursor, source_info, res)?;
+            assume_preconditions(cursor, fn_sig, &mut subst, &actuals);
         }
+        cursor.push_tagged_head(Expr::ff(), Tag::Panic(source_info.span));
+        Ok(())
+    }
+
+    fn check_call_closure(
+        &mut self,
+        env: &mut TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        did: DefId,
+        upvars: &Operand,
+        args: &[Operand],
+    ) -> Result<Ty, ErrorReported> {
+        let (formals, ret) = self.check_closure(env, cursor, source_info, did, upvars)?;
+        let actuals = self.check_operands(env, cursor, source_info, args)?;
+        let ret = check_fn_ty_call(cursor, source_info.span, Some(did), &formals, ret, actuals);
+        self.report_error(cursor, source_info, ret)
+    }
+
+    /// Returns the signature of the closure `did`, whose refinements are inferred, checking the
+    /// body of the closure against it. `upvars` is the closure itself or a reference to it.
+    fn check_closure(
+        &self,
+        env: &mut TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        did: DefId,
+        upvars: &Operand,
+    ) -> Result<(Vec<Ty>, Ty), ErrorReported> {
+        let body: &'a Body<'tcx> = self.body;
+        let closure = &body.closures[&did];
+
+        let upvars = self.read_operand(env, cursor, upvars);
+        let upvars =
+            self.report_error(cursor, source_info, upvars.and_then(|ty| deref(env, ty)))?;
+        // Closures without captured variables are never initialized.
+        let upvars = if upvars.is_uninit() {
+            TyKind::tuple([])
+        } else {
+            upvars
+        };
+
+        let mut subst = lowering::Subst::with_empty_type_substs();
+        let formals = closure
+            .args
+            .iter()
+            .map(|ty| subst.lower_ty(cursor, ty))
+            .collect_vec();
+        let ret = subst.lower_ty(cursor, &closure.ret);
 
-        for (loc, required_ty) in &fn_sig.requires {
-            let actual_ty = env.lookup_loc(subst.lower_loc(*loc).unwrap()).unwrap();
-            let required_ty = subst.lower_ty(cursor, required_ty);
-            cursor.subtyping(actual_ty, required_ty);
+        if let Mode::Check(_) = self.mode {
+            self.check_closure_body(env, cursor, closure, upvars, &formals, &ret)?;
         }
+        Ok((formals, ret))
+    }
 
-        for (loc, updated_ty) in &fn_sig.ensures {
-            let updated_ty = subst.lower_ty(cursor, updated_ty);
-            let updated_ty = cursor.unpack(updated_ty);
-            if let Some(loc) = subst.lower_loc(*loc) {
-                env.update_loc(cursor, loc, updated_ty);
-            } else {
+    /// Checks the body of a closure against a signature. Captured variables are copied into the
+    /// environment of the closure, which is sound because they are either moved into the
+    /// closure or borrowed immutably for as long as the closure is alive.
+    fn check_closure_body(
+        &self,
+        env: &TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        closure: &'a ir::Closure<'tcx>,
+        upvars: Ty,
+        args: &[Ty],
+        ret: &Ty,
+    ) -> Result<(), ErrorReported> {
+        let cursor = &mut cursor.snapshot();
+        let mut closure_env = TypeEnv::new(self.global_env.tcx);
+
+        let upvars = env.copy_into(cursor, upvars, &mut closure_env);
+        let closure_ty = match closure.kind {
+            ir::ClosureKind::FnOnce => upvars,
+            ir::ClosureKind::Fn => {
                 let fresh = Loc::Abstract(cursor.fresh_name());
-                env.insert_loc(fresh, updated_ty);
-                subst.insert_loc(*loc, fresh);
+                closure_env.insert_loc(fresh, upvars);
+                TyKind::StrgRef(fresh).intern()
             }
+        };
+        let mut locals = closure.body.args_iter();
+        closure_env.insert_loc(Loc::Local(locals.next().unwrap()), closure_ty);
+        for (local, ty) in locals.zip(args) {
+            closure_env.insert_loc(Loc::Local(local), cursor.unpack(ty.clone()));
         }
+        for local in closure.body.vars_and_temps_iter() {
+            closure_env.insert_loc(Loc::Local(local), TyKind::Uninit.intern());
+        }
+        closure_env.insert_loc(Loc::Local(RETURN_PLACE), TyKind::Uninit.intern());
 
-        if let Some((p, bb)) = destination {
-            let ret = subst.lower_ty(cursor, &fn_sig.ret);
-            let ret = cursor.unpack(ret);
-            env.write_place(cursor, p, ret);
+        let mut checker = Checker::new(
+            self.global_env,
+            &closure.body,
+            ret.clone(),
+            vec![],
+            Mode::Inference,
+        );
+        checker.run(&mut closure_env.clone(), &mut cursor.fork().as_cursor())?;
+        let shapes = checker.into_bb_env_shapes();
 
-            self.check_goto(env, cursor, *bb)?;
+        let mut checker = Checker::new(
+            self.global_env,
+            &closure.body,
+            ret.clone(),
+            vec![],
+            Mode::Check(shapes),
+        );
+        checker.run(&mut closure_env, cursor)
+    }
+
+    fn check_call_indirect(
+        &mut self,
+        env: &mut TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        callee: &Operand,
+        args: &[Operand],
+    ) -> Result<Ty, ErrorReported> {
+        let callee_ty = self.check_operand(env, cursor, callee);
+        let callee_ty = callee_ty.and_then(|ty| deref(env, ty));
+        let callee_ty = self.report_error(cursor, source_info, callee_ty)?;
+        match callee_ty.kind() {
+            TyKind::Fn(formals, ret) => {
+                let actuals = self.check_operands(env, cursor, source_info, args)?;
+                let ret = check_fn_ty_call(
+                    cursor,
+                    source_info.span,
+                    None,
+                    formals,
+                    ret.clone(),
+                    actuals,
+                );
+                self.report_error(cursor, source_info, ret)
+            }
+            _ => {
+                self.sess.span_err(
+                    source_info.span,
+                    &format!("cannot call a value of type `{:?}`", callee_ty),
+                );
+                Err(ErrorReported)
+            }
         }
-        Ok(())
     }
 
     fn check_switch_int(
         &mut self,
         env: &mut TypeEnv<'tcx>,
         cursor: &mut Cursor,
+        source_info: SourceInfo,
         discr: &Operand,
         targets: &mir::SwitchTargets,
     ) -> Result<(), ErrorReported> {
-        let discr_ty = self.check_operand(env, discr);
+        let discr_ty = self.check_operand(env, cursor, discr);
+        let discr_ty = self.report_error(cursor, source_info, discr_ty)?;
         let mk = |bits| match discr_ty.kind() {
             TyKind::Refine(BaseTy::Bool, e) => {
                 if bits != 0 {
-                    e.clone()
+                    Some(e.clone())
                 } else {
-                    e.not()
+                    Some(e.not())
                 }
             }
-            TyKind::Refine(bty @ (BaseTy::Int(_) | BaseTy::Uint(_)), e) => {
-                ExprKind::BinaryOp(BinOp::Eq, e.clone(), Expr::from_bits(bty, bits)).intern()
+            TyKind::Refine(bty, e) => {
+                let bits = Expr::from_bits(bty, bits)?;
+                Some(ExprKind::BinaryOp(BinOp::Eq, e.clone(), bits).intern())
+            }
+            _ => None,
+        };
+        let guards: Option<Vec<_>> = targets.iter().map(|(bits, _)| mk(bits)).collect();
+        let guards = match guards {
+            Some(guards) => guards,
+            None => {
+                let err = CheckerError::UnsupportedOperands("switch".to_string(), vec![discr_ty]);
+                return self.report_error(cursor, source_info, Err(err));
             }
-            _ => unreachable!("unexpected discr_ty {:?}", discr_ty),
         };
 
-        for (bits, bb) in targets.iter() {
+        for (guard, (_, bb)) in guards.iter().zip(targets.iter()) {
             let cursor = &mut cursor.snapshot();
-            cursor.push_guard(mk(bits));
-            self.check_goto(&mut env.clone(), cursor, bb)?;
+            cursor.push_guard(guard.clone());
+            self.check_goto(&mut env.clone(), cursor, source_info, bb)?;
         }
-        let otherwise = targets
-            .iter()
-            .map(|(bits, _)| mk(bits).not())
+        let otherwise = guards
+            .into_iter()
+            .map(|guard| guard.not())
             .reduce(|e1, e2| ExprKind::BinaryOp(BinOp::And, e1, e2).intern());
 
         let cursor = &mut cursor.snapshot();
@@ -342,18 +760,53 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
             cursor.push_guard(otherwise);
         }
 
-        self.check_goto(env, cursor, targets.otherwise())?;
+        self.check_goto(env, cursor, source_info, targets.otherwise())?;
         Ok(())
     }
 
+    fn check_assert(
+        &mut self,
+        env: &mut TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        cond: &Operand,
+        expected: bool,
+        target: BasicBlock,
+    ) -> Result<(), ErrorReported> {
+        let cond_ty = self.check_operand(env, cursor, cond);
+        let cond_ty = self.report_error(cursor, source_info, cond_ty)?;
+        let pred = match cond_ty.kind() {
+            TyKind::Refine(BaseTy::Bool, e) => {
+                if expected {
+                    e.clone()
+                } else {
+                    e.not()
+                }
+            }
+            _ => {
+                let err = CheckerError::UnsupportedOperands("assert".to_string(), vec![cond_ty]);
+                return self.report_error(cursor, source_info, Err(err));
+            }
+        };
+
+        let cursor = &mut cursor.snapshot();
+        if CONFIG.check_panics {
+            cursor.push_tagged_head(pred.clone(), Tag::Panic(source_info.span));
+        }
+        cursor.push_guard(pred);
+        self.check_goto(env, cursor, source_info, target)
+    }
+
+    /// Continues checking at `target` after a terminator at `source_info`.
     fn check_goto(
         &mut self,
         env: &mut TypeEnv<'tcx>,
         cursor: &mut Cursor,
+        source_info: SourceInfo,
         target: BasicBlock,
     ) -> Result<(), ErrorReported> {
         if self.body.is_join_point(target) {
-            self.check_goto_join_point(env, cursor, target)
+            self.check_goto_join_point(env, cursor, source_info, target)
         } else {
             self.check_basic_block(env, cursor, target)
         }
@@ -363,154 +816,286 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
         &mut self,
         env: &mut TypeEnv<'tcx>,
         cursor: &mut Cursor,
+        source_info: SourceInfo,
         target: BasicBlock,
     ) -> Result<(), ErrorReported> {
-        match &mut self.mode {
-            Mode::Inference => {
-                match self.bb_envs.entry(target) {
-                    Entry::Occupied(mut entry) => {
-                        entry.get_mut().join_with(env, cursor);
-                    }
-                    Entry::Vacant(entry) => {
-                        entry.insert(env.clone());
-                    }
-                };
-            }
+        cursor.set_tag(Tag::Goto(source_info.span));
+        let res = match &mut self.mode {
+            Mode::Inference => match self.bb_envs.entry(target) {
+                Entry::Occupied(mut entry) => entry.get_mut().join_with(env, cursor),
+                Entry::Vacant(entry) => {
+                    entry.insert(env.clone());
+                    Ok(())
+                }
+            },
             Mode::Check(shapes) => {
-                let bb_env = self
-                    .bb_envs
-                    .entry(target)
-                    .or_insert_with(|| env.infer_bb_env(cursor, shapes.remove(&target).unwrap()));
-                env.transform_into(cursor, bb_env);
+                // Inference visits the same blocks, so every join point reached here has a shape.
+                // Without one, the environment itself is an exact (if imprecise) choice.
+                let bb_env = self.bb_envs.entry(target).or_insert_with(|| {
+                    let shape = shapes
+                        .remove(&target)
+                        .unwrap_or_else(|| env.clone().into_shape());
+                    env.infer_bb_env(cursor, shape)
+                });
+                env.transform_into(cursor, bb_env)
             }
         };
-        Ok(())
+        self.report_error(cursor, source_info, res)
     }
 
-    fn check_rvalue(&self, env: &mut TypeEnv<'tcx>, cursor: &mut Cursor, rvalue: &Rvalue) -> Ty {
-        match rvalue {
-            Rvalue::Use(operand) => self.check_operand(env, operand),
+    fn check_rvalue(
+        &self,
+        env: &mut TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        rvalue: &Rvalue,
+    ) -> Result<Ty, CheckerError> {
+        let ty = match rvalue {
+            Rvalue::Use(operand) => self.check_operand(env, cursor, operand)?,
             Rvalue::BinaryOp(bin_op, op1, op2) => {
-                self.check_binary_op(env, cursor, bin_op, op1, op2)
+                self.check_binary_op(env, cursor, source_info, bin_op, op1, op2)?
+            }
+            Rvalue::CheckedBinaryOp(bin_op, op1, op2) => {
+                // Integers are unbounded, an operation overflows if its result is out of the
+                // bounds of its type.
+                let ty = self.check_binary_op(env, cursor, source_info, bin_op, op1, op2)?;
+                let overflow = match ty.kind() {
+                    TyKind::Refine(bty, e) => cursor.in_bounds(bty, e).map(|e| e.not()),
+                    _ => None,
+                };
+                let overflow = overflow.ok_or_else(|| {
+                    CheckerError::UnsupportedOperands(
+                        "checked operation".to_string(),
+                        vec![ty.clone()],
+                    )
+                })?;
+                let overflow = TyKind::Refine(BaseTy::Bool, overflow).intern();
+                TyKind::tuple([ty, overflow])
             }
             Rvalue::MutRef(place) => {
-                // OWNERSHIP SAFETY CHECK
-                TyKind::StrgRef(env.get_loc(place)).intern()
+                TyKind::StrgRef(env.borrow(cursor, Mutability::Mut, place)?).intern()
             }
-            Rvalue::UnaryOp(un_op, op) => self.check_unary_op(env, *un_op, op),
-        }
+            Rvalue::ShrRef(place) => {
+                TyKind::StrgRef(env.borrow(cursor, Mutability::Not, place)?).intern()
+            }
+            Rvalue::Len(place) => {
+                let ty = env.lookup_place(cursor, place)?;
+                match ty.kind() {
+                    TyKind::Refine(BaseTy::Array(_) | BaseTy::Slice(_), len) => {
+                        TyKind::Refine(BaseTy::Uint(UintTy::Usize), len.clone()).intern()
+                    }
+                    _ => {
+                        return Err(CheckerError::UnsupportedOperands(
+                            "len".to_string(),
+                            vec![ty],
+                        ))
+                    }
+                }
+            }
+            Rvalue::Repeat(op, ty, len) => {
+                let op_ty = self.check_operand(env, cursor, op)?;
+                let ty = lowering::Subst::with_empty_type_substs().lower_ty(cursor, ty);
+                cursor.subtyping(op_ty, ty.clone())?;
+                let len = ExprKind::Constant(ty::Constant::from(*len as u128)).intern();
+                TyKind::Refine(BaseTy::Array(ty), len).intern()
+            }
+            Rvalue::UnaryOp(un_op, op) => self.check_unary_op(env, cursor, *un_op, op)?,
+            Rvalue::FnPointer(..) | Rvalue::ClosureFnPointer(..) => {
+                unreachable!("checked in `check_statement`")
+            }
+            Rvalue::Aggregate(AggregateKind::Tuple, args) => {
+                let tys: Vec<_> = args
+                    .iter()
+                    .map(|arg| self.check_operand(env, cursor, arg))
+                    .try_collect()?;
+                TyKind::tuple(tys)
+            }
+            Rvalue::Aggregate(AggregateKind::Adt(def_id, substs), args) => {
+                let fields: Vec<_> = args
+                    .iter()
+                    .map(|arg| self.check_operand(env, cursor, arg))
+                    .try_collect()?;
+                let mut subst = lowering::Subst::with_empty_type_substs();
+                let substs = substs.iter().map(|ty| subst.lower_ty(cursor, ty));
+                TyKind::unfolded(BaseTy::adt(*def_id, substs.collect_vec()), fields)
+            }
+            Rvalue::Aggregate(AggregateKind::Array(ty), args) => {
+                let ty = lowering::Subst::with_empty_type_substs().lower_ty(cursor, ty);
+                for arg in args {
+                    let arg_ty = self.check_operand(env, cursor, arg)?;
+                    cursor.subtyping(arg_ty, ty.clone())?;
+                }
+                let len = ExprKind::Constant(ty::Constant::from(args.len() as u128)).intern();
+                TyKind::Refine(BaseTy::Array(ty), len).intern()
+            }
+        };
+        Ok(ty)
     }
 
     fn check_binary_op(
         &self,
         env: &mut TypeEnv,
         cursor: &mut Cursor,
+        source_info: SourceInfo,
         bin_op: &ir::BinOp,
         op1: &Operand,
         op2: &Operand,
-    ) -> Ty {
-        let ty1 = self.check_operand(env, op1);
-        let ty2 = self.check_operand(env, op2);
+    ) -> Result<Ty, CheckerError> {
+        let ty1 = self.check_operand(env, cursor, op1)?;
+        let ty2 = self.check_operand(env, cursor, op2)?;
 
         match bin_op {
             ir::BinOp::Eq => self.check_eq(BinOp::Eq, ty1, ty2),
             ir::BinOp::Ne => self.check_eq(BinOp::Ne, ty1, ty2),
-            ir::BinOp::Add => self.check_arith_op(cursor, BinOp::Add, ty1, ty2),
-            ir::BinOp::Sub => self.check_arith_op(cursor, BinOp::Sub, ty1, ty2),
-            ir::BinOp::Mul => self.check_arith_op(cursor, BinOp::Mul, ty1, ty2),
-            ir::BinOp::Div => self.check_arith_op(cursor, BinOp::Div, ty1, ty2),
+            ir::BinOp::Add => self.check_arith_op(cursor, source_info, BinOp::Add, ty1, ty2),
+            ir::BinOp::Sub => self.check_arith_op(cursor, source_info, BinOp::Sub, ty1, ty2),
+            ir::BinOp::Mul => self.check_arith_op(cursor, source_info, BinOp::Mul, ty1, ty2),
+            ir::BinOp::Div => self.check_arith_op(cursor, source_info, BinOp::Div, ty1, ty2),
             ir::BinOp::Gt => self.check_cmp_op(BinOp::Gt, ty1, ty2),
             ir::BinOp::Lt => self.check_cmp_op(BinOp::Lt, ty1, ty2),
             ir::BinOp::Le => self.check_cmp_op(BinOp::Le, ty1, ty2),
         }
     }
 
-    fn check_arith_op(&self, cursor: &mut Cursor, op: BinOp, ty1: Ty, ty2: Ty) -> Ty {
+    fn check_arith_op(
+        &self,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        op: BinOp,
+        ty1: Ty,
+        ty2: Ty,
+    ) -> Result<Ty, CheckerError> {
         let (bty, e1, e2) = match (ty1.kind(), ty2.kind()) {
             (
                 TyKind::Refine(BaseTy::Int(int_ty1), e1),
                 TyKind::Refine(BaseTy::Int(int_ty2), e2),
-            ) => {
-                debug_assert_eq!(int_ty1, int_ty2);
-                (BaseTy::Int(*int_ty1), e1.clone(), e2.clone())
-            }
+            ) if int_ty1 == int_ty2 => (BaseTy::Int(*int_ty1), e1.clone(), e2.clone()),
             (
                 TyKind::Refine(BaseTy::Uint(uint_ty1), e1),
                 TyKind::Refine(BaseTy::Uint(uint_ty2), e2),
-            ) => {
-                debug_assert_eq!(uint_ty1, uint_ty2);
-                (BaseTy::Uint(*uint_ty1), e1.clone(), e2.clone())
-            }
-            _ => unreachable!("incompatible types: `{:?}` `{:?}`", ty1, ty2),
+            ) if uint_ty1 == uint_ty2 => (BaseTy::Uint(*uint_ty1), e1.clone(), e2.clone()),
+            _ => return Err(unsupported_bin_op(op, ty1, ty2)),
         };
         if matches!(op, BinOp::Div) {
-            cursor.push_head(ExprKind::BinaryOp(BinOp::Ne, e2.clone(), Expr::zero()).intern());
+            let pred = ExprKind::BinaryOp(BinOp::Ne, e2.clone(), Expr::zero()).intern();
+            cursor.push_tagged_head(pred, Tag::Div(source_info.span));
         }
-        TyKind::Refine(bty, ExprKind::BinaryOp(op, e1, e2).intern()).intern()
+        Ok(TyKind::Refine(bty, ExprKind::BinaryOp(op, e1, e2).intern()).intern())
     }
 
-    fn check_cmp_op(&self, op: BinOp, ty1: Ty, ty2: Ty) -> Ty {
+    fn check_cmp_op(&self, op: BinOp, ty1: Ty, ty2: Ty) -> Result<Ty, CheckerError> {
         let (e1, e2) = match (ty1.kind(), ty2.kind()) {
             (
                 TyKind::Refine(BaseTy::Int(int_ty1), e1),
                 TyKind::Refine(BaseTy::Int(int_ty2), e2),
-            ) => {
-                debug_assert_eq!(int_ty1, int_ty2);
-                (e1.clone(), e2.clone())
-            }
+            ) if int_ty1 == int_ty2 => (e1.clone(), e2.clone()),
             (
                 TyKind::Refine(BaseTy::Uint(uint_ty1), e1),
                 TyKind::Refine(BaseTy::Uint(uint_ty2), e2),
-            ) => {
-                debug_assert_eq!(uint_ty1, uint_ty2);
-                (e1.clone(), e2.clone())
-            }
-            _ => unreachable!("incompatible types: `{:?}` `{:?}`", ty1, ty2),
+            ) if uint_ty1 == uint_ty2 => (e1.clone(), e2.clone()),
+            _ => return Err(unsupported_bin_op(op, ty1, ty2)),
         };
-        TyKind::Refine(BaseTy::Bool, ExprKind::BinaryOp(op, e1, e2).intern()).intern()
+        Ok(TyKind::Refine(BaseTy::Bool, ExprKind::BinaryOp(op, e1, e2).intern()).intern())
     }
 
-    fn check_eq(&self, op: BinOp, ty1: Ty, ty2: Ty) -> Ty {
+    fn check_eq(&self, op: BinOp, ty1: Ty, ty2: Ty) -> Result<Ty, CheckerError> {
         match (ty1.kind(), ty2.kind()) {
-            (TyKind::Refine(bty1, e1), TyKind::Refine(bty2, e2)) => {
-                debug_assert_eq!(bty1, bty2);
-                TyKind::Refine(
+            (TyKind::Refine(bty1, e1), TyKind::Refine(bty2, e2)) if bty1 == bty2 => {
+                Ok(TyKind::Refine(
                     BaseTy::Bool,
                     ExprKind::BinaryOp(op, e1.clone(), e2.clone()).intern(),
                 )
-                .intern()
+                .intern())
             }
-            _ => unreachable!("incompatible types: `{:?}` `{:?}`", ty1, ty2),
+            _ => Err(unsupported_bin_op(op, ty1, ty2)),
         }
     }
 
-    fn check_unary_op(&self, env: &mut TypeEnv, un_op: ir::UnOp, op: &Operand) -> Ty {
-        let ty = self.check_operand(env, op);
-        match un_op {
-            ir::UnOp::Not => match ty.kind() {
-                TyKind::Refine(BaseTy::Bool, e) => TyKind::Refine(BaseTy::Bool, e.not()).intern(),
-                _ => unreachable!("incompatible type: `{:?}`", ty),
-            },
-            ir::UnOp::Neg => match ty.kind() {
-                TyKind::Refine(BaseTy::Int(int_ty), e) => {
-                    TyKind::Refine(BaseTy::Int(*int_ty), e.neg()).intern()
-                }
-                _ => unreachable!("incompatible type: `{:?}`", ty),
-            },
+    fn check_unary_op(
+        &self,
+        env: &mut TypeEnv,
+        cursor: &mut Cursor,
+        un_op: ir::UnOp,
+        op: &Operand,
+    ) -> Result<Ty, CheckerError> {
+        let ty = self.check_operand(env, cursor, op)?;
+        let ty = match (un_op, ty.kind()) {
+            (ir::UnOp::Not, TyKind::Refine(BaseTy::Bool, e)) => {
+                TyKind::Refine(BaseTy::Bool, e.not()).intern()
+            }
+            (ir::UnOp::Neg, TyKind::Refine(BaseTy::Int(int_ty), e)) => {
+                TyKind::Refine(BaseTy::Int(*int_ty), e.neg()).intern()
+            }
+            (ir::UnOp::Not, _) => {
+                return Err(CheckerError::UnsupportedOperands("!".to_string(), vec![ty]))
+            }
+            (ir::UnOp::Neg, _) => {
+                return Err(CheckerError::UnsupportedOperands("-".to_string(), vec![ty]))
+            }
+        };
+        Ok(ty)
+    }
+
+    /// Checks the arguments of a call. A closure passed as an argument has the type of a function
+    /// with the signature of the closure.
+    fn check_operands(
+        &self,
+        env: &mut TypeEnv<'tcx>,
+        cursor: &mut Cursor,
+        source_info: SourceInfo,
+        operands: &[Operand],
+    ) -> Result<Vec<Ty>, ErrorReported> {
+        let mut tys = vec![];
+        for op in operands {
+            let ty = if let Some(did) = self.closure_of(op) {
+                let (formals, ret) = self.check_closure(env, cursor, source_info, did, op)?;
+                TyKind::fn_ty(formals, ret)
+            } else {
+                let ty = self.check_operand(env, cursor, op);
+                self.report_error(cursor, source_info, ty)?
+            };
+            tys.push(ty);
         }
+        Ok(tys)
     }
 
-    fn check_operand(&self, env: &mut TypeEnv, operand: &Operand) -> Ty {
+    /// Returns the closure an operand evaluates to, if its type is the type of a closure.
+    fn closure_of(&self, operand: &Operand) -> Option<DefId> {
         match operand {
-            Operand::Copy(p) => {
-                // OWNERSHIP SAFETY CHECK
-                env.lookup_place(p)
-            }
-            Operand::Move(p) => {
-                // OWNERSHIP SAFETY CHECK
-                env.move_place(p)
+            Operand::Copy(place) | Operand::Move(place) if place.projection.is_empty() => {
+                match self.body.mir.local_decls[place.local].ty.kind() {
+                    rustc_middle::ty::TyKind::Closure(did, _) => Some(*did),
+                    _ => None,
+                }
             }
-            Operand::Constant(c) => self.check_constant(c),
+            _ => None,
+        }
+    }
+
+    fn check_operand(
+        &self,
+        env: &mut TypeEnv,
+        cursor: &mut Cursor,
+        operand: &Operand,
+    ) -> Result<Ty, CheckerError> {
+        let ty = self.read_operand(env, cursor, operand)?;
+        if ty.is_uninit() {
+            return Err(CheckerError::UseOfUninit);
+        }
+        Ok(ty)
+    }
+
+    /// Like `check_operand` but the operand may be uninitialized.
+    fn read_operand(
+        &self,
+        env: &mut TypeEnv,
+        cursor: &mut Cursor,
+        operand: &Operand,
+    ) -> Result<Ty, CheckerError> {
+        match operand {
+            Operand::Copy(p) => env.lookup_place(cursor, p),
+            Operand::Move(p) => env.move_place(cursor, p),
+            Operand::Constant(c) => Ok(self.check_constant(c)),
         }
     }
 
@@ -531,9 +1116,355 @@ impl<'a, 'tcx> Checker<'a, 'tcx> {
         }
     }
 
-    fn report_inference_error(&self, call_source_info: SourceInfo) -> Result<(), ErrorReported> {
-        self.sess
-            .span_err(call_source_info.span, "inference error at function call");
-        Err(ErrorReported)
+    fn report_error<T>(
+        &self,
+        cursor: &Cursor,
+        source_info: SourceInfo,
+        res: Result<T, CheckerError>,
+    ) -> Result<T, ErrorReported> {
+        res.map_err(|err| {
+            let names = cursor.name_map(&self.local_names);
+            report_checker_error(self.global_env.tcx, &names, source_info.span, err)
+        })
+    }
+}
+
+/// The names of the user variables of `body` stored directly in a local.
+pub fn local_names(body: &Body) -> FxHashMap<ir::Local, Symbol> {
+    body.mir
+        .var_debug_info
+        .iter()
+        .filter_map(|info| match info.value {
+            mir::VarDebugInfoContents::Place(place) if place.projection.is_empty() => {
+                Some((place.local, info.name))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Introduces the parameters and required locations of `fn_sig` in the cursor and `env`, returning
+/// the substitution for the signature and its unpacked arguments.
+fn enter_fn_sig(
+    env: &mut TypeEnv,
+    cursor: &mut Cursor,
+    fn_sig: &core::FnSig,
+    substs: &[core::Ty],
+) -> (lowering::Subst, Vec<Ty>) {
+    let mut subst = lowering::Subst::with_type_substs(cursor, substs);
+
+    // Abstract refinements are arbitrary predicates, i.e., uninterpreted functions.
+    for param in fn_sig
+        .params
+        .iter()
+        .filter(|param| param.sort == Sort::Pred)
+    {
+        let fresh = cursor.fresh_name();
+        cursor.declare_const(fresh, Sort::Pred);
+        let app = Expr::app(fresh, [Var::Bound.into()]);
+        subst.insert_pred(param.name.name, app.into());
+    }
+
+    for param in fn_sig
+        .params
+        .iter()
+        .filter(|param| param.sort != Sort::Pred)
+    {
+        let fresh = cursor.fresh_name();
+        cursor.name_var(fresh, param.name.source_info.1);
+        subst.insert_expr(param.name.name, Var::Free(fresh));
+        let pred = subst.lower_pred(cursor, &param.pred, param.sort);
+        cursor.push_forall(
+            fresh,
+            param.sort,
+            pred.subst_bound_vars(Var::Free(fresh).into()),
+        );
+    }
+
+    for (loc, ty) in &fn_sig.requires {
+        let ty = subst.lower_ty(cursor, ty);
+        let fresh = Loc::Abstract(cursor.fresh_name());
+        let ty = cursor.unpack(ty);
+        assume_in_bounds(cursor, &ty);
+        env.insert_loc(fresh, ty);
+        subst.insert_loc(*loc, fresh);
+    }
+
+    let args = fn_sig
+        .args
+        .iter()
+        .map(|ty| {
+            let ty = subst.lower_ty(cursor, ty);
+            let ty = cursor.unpack(ty);
+            assume_in_bounds(cursor, &ty);
+            ty
+        })
+        .collect();
+
+    (subst, args)
+}
+
+/// Values of integer types are within the bounds of their type, but an argument indexed by an
+/// arbitrary expression, e.g., `usize@n`, does not say so.
+fn assume_in_bounds(cursor: &mut Cursor, ty: &Ty) {
+    if let TyKind::Refine(bty, e) = ty.kind() {
+        if let Some(in_bounds) = cursor.in_bounds(bty, e) {
+            cursor.push_guard(in_bounds);
+        }
+    }
+}
+
+/// Checks a call to a function with signature `fn_sig` with arguments `actuals`, updating `env`
+/// with the locations ensured by the callee and returning the unpacked return type.
+/// The obligations of the call are tagged with `call_site`, which is `None` when checking that
+/// a signature refines another one.
+fn call_fn_sig(
+    env: &mut TypeEnv,
+    cursor: &mut Cursor,
+    call_site: Option<(Span, DefId)>,
+    fn_sig: &core::FnSig,
+    substs: &[core::Ty],
+    actuals: Vec<Ty>,
+) -> Result<Ty, CheckerError> {
+    // A strong reference passed where a shared reference is expected is weakened to the type of
+    // the location it points to.
+    let actuals: Vec<_> = actuals
+        .into_iter()
+        .zip(&fn_sig.args)
+        .map(|(actual, formal)| match (actual.kind(), formal) {
+            (TyKind::StrgRef(loc), core::Ty::Ref(_)) => {
+                let ty = env.lookup_referee(*loc)?;
+                Ok(TyKind::Ref(cursor.unpack(ty)).intern())
+            }
+            _ => Ok(actual),
+        })
+        .try_collect::<_, _, CheckerError>()?;
+
+    let mut subst = lowering::Subst::with_type_substs(cursor, substs);
+    check_refined_params(fn_sig, &subst)?;
+    subst.infer_from_fn_call(env, cursor, &actuals, fn_sig)?;
+    check_preconditions(env, cursor, call_site, fn_sig, &mut subst, actuals)?;
+
+    if let Some((span, _)) = call_site {
+        cursor.set_tag(Tag::Assign(span));
+    }
+    for (loc, updated_ty) in &fn_sig.ensures {
+        let updated_ty = subst.lower_ty(cursor, updated_ty);
+        let updated_ty = cursor.unpack(updated_ty);
+        if let Some(loc) = subst.lower_loc(*loc) {
+            env.update_loc(cursor, loc, updated_ty)?;
+        } else {
+            let fresh = Loc::Abstract(cursor.fresh_name());
+            env.insert_loc(fresh, updated_ty);
+            subst.insert_loc(*loc, fresh);
+        }
+    }
+
+    let ret = subst.lower_ty(cursor, &fn_sig.ret);
+    Ok(cursor.unpack(ret))
+}
+
+/// Checks the arguments and the required locations of a call against `fn_sig`. Abstract
+/// refinements are instantiated with predicates to be inferred.
+fn check_preconditions(
+    env: &mut TypeEnv,
+    cursor: &mut Cursor,
+    call_site: Option<(Span, DefId)>,
+    fn_sig: &core::FnSig,
+    subst: &mut lowering::Subst,
+    actuals: Vec<Ty>,
+) -> Result<(), CheckerError> {
+    for param in fn_sig
+        .params
+        .iter()
+        .filter(|param| param.sort == Sort::Pred)
+    {
+        // Abstract refinements are only applied to integers: the type parameters they refine
+        // are instantiated with integers (see `check_refined_params`).
+        subst.insert_pred(param.name.name, cursor.fresh_kvar(Sort::Int));
+    }
+
+    if let Some((span, callee)) = call_site {
+        cursor.set_tag(Tag::Call(span, Some(callee)));
+    }
+    for param in fn_sig
+        .params
+        .iter()
+        .filter(|param| param.sort != Sort::Pred)
+    {
+        let pred = subst.lower_pred(cursor, &param.pred, param.sort);
+        let arg = subst.lower_var(core::Var::Free(param.name.name));
+        cursor.push_head(pred.subst_bound_vars(arg));
+    }
+
+    for (i, (actual, formal)) in actuals.into_iter().zip(&fn_sig.args).enumerate() {
+        if let Some((span, callee)) = call_site {
+            cursor.set_tag(Tag::Arg(span, Some(callee), i));
+        }
+        let formal = subst.lower_ty(cursor, formal);
+        cursor.subtyping(actual, formal)?;
+    }
+
+    if let Some((span, callee)) = call_site {
+        cursor.set_tag(Tag::Call(span, Some(callee)));
+    }
+    for (loc, required_ty) in &fn_sig.requires {
+        let loc = subst
+            .lower_loc(*loc)
+            .ok_or(CheckerError::CannotInferLocations)?;
+        let actual_ty = env.lookup_referee(loc)?;
+        let required_ty = subst.lower_ty(cursor, required_ty);
+        cursor.subtyping(actual_ty, required_ty)?;
+    }
+    Ok(())
+}
+
+/// Type parameters refined by an expression in `fn_sig`, e.g., `T{v: p(v)}`, can only be
+/// instantiated with types whose values are integers, the sort the expression was checked against.
+fn check_refined_params(fn_sig: &core::FnSig, subst: &lowering::Subst) -> Result<(), CheckerError> {
+    let mut params = vec![];
+    let located = fn_sig.requires.iter().chain(&fn_sig.ensures);
+    for ty in fn_sig
+        .args
+        .iter()
+        .chain([&fn_sig.ret])
+        .chain(located.map(|(_, ty)| ty))
+    {
+        collect_refined_params(ty, &mut params);
+    }
+    for param in params {
+        match subst.lookup_type(param).map(|ty| ty.kind()) {
+            Some(TyKind::Refine(bty, _) | TyKind::Exists(bty, _)) if bty.sort() != Sort::Int => {
+                return Err(CheckerError::RefinedParamInstance(param, bty.clone()));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn collect_refined_params(ty: &core::Ty, params: &mut Vec<core::ParamTy>) {
+    match ty {
+        core::Ty::Refine(core::BaseTy::Param(param), _)
+        | core::Ty::Exists(
+            core::BaseTy::Param(param),
+            core::Pred::Expr(_) | core::Pred::KVar(..),
+        ) => params.push(*param),
+        core::Ty::Refine(bty, _) | core::Ty::Exists(bty, _) => match bty {
+            core::BaseTy::Adt(_, substs) => {
+                for ty in substs {
+                    collect_refined_params(ty, params);
+                }
+            }
+            core::BaseTy::Array(ty) | core::BaseTy::Slice(ty) => collect_refined_params(ty, params),
+            _ => {}
+        },
+        core::Ty::Tuple(tys) => {
+            for ty in tys {
+                collect_refined_params(ty, params);
+            }
+        }
+        core::Ty::Ref(ty) => collect_refined_params(ty, params),
+        core::Ty::Fn(args, ret) => {
+            for ty in args.iter().chain([&**ret]) {
+                collect_refined_params(ty, params);
+            }
+        }
+        core::Ty::MutRef(_) | core::Ty::Param(_) | core::Ty::Never => {}
+    }
+}
+
+/// Assumes the refinements of the parameters and of the arguments of a call to `fn_sig` hold.
+fn assume_preconditions(
+    cursor: &mut Cursor,
+    fn_sig: &core::FnSig,
+    subst: &mut lowering::Subst,
+    actuals: &[Ty],
+) {
+    for param in fn_sig
+        .params
+        .iter()
+        .filter(|param| param.sort != Sort::Pred)
+    {
+        let pred = subst.lower_pred(cursor, &param.pred, param.sort);
+        let arg = subst.lower_var(core::Var::Free(param.name.name));
+        if let Pred::Expr(e) = pred.subst_bound_vars(arg) {
+            cursor.push_guard(e);
+        }
+    }
+    for (actual, formal) in actuals.iter().zip(&fn_sig.args) {
+        let formal = subst.lower_ty(cursor, formal);
+        if let (TyKind::Refine(_, e1), TyKind::Refine(_, e2)) =
+            (actual.kind(), cursor.unpack(formal).kind())
+        {
+            cursor.push_guard(ExprKind::BinaryOp(BinOp::Eq, e1.clone(), e2.clone()).intern());
+        }
+    }
+}
+
+fn unsupported_bin_op(op: BinOp, ty1: Ty, ty2: Ty) -> CheckerError {
+    CheckerError::UnsupportedOperands(op.to_string(), vec![ty1, ty2])
+}
+
+fn report_checker_error(
+    tcx: TyCtxt,
+    names: &NameMap,
+    span: Span,
+    err: CheckerError,
+) -> ErrorReported {
+    let sess = tcx.sess;
+    let cx = Ty::default_cx(tcx).names(names);
+    match err {
+        CheckerError::UseOfUninit => sess.emit_err(errors::UseOfUninit { span }),
+        CheckerError::MoveBehindRef => sess.emit_err(errors::MoveBehindRef { span }),
+        CheckerError::MutationBehindShrRef => sess.emit_err(errors::MutationBehindShrRef { span }),
+        CheckerError::IncompatibleTypes(ty1, ty2) => sess.emit_err(errors::IncompatibleTypes {
+            span,
+            ty1: format!("{:?}", WithCx::new(&cx, ty1)),
+            ty2: format!("{:?}", WithCx::new(&cx, ty2)),
+        }),
+        CheckerError::UnsupportedOperands(op, tys) => sess.emit_err(errors::UnsupportedOperands {
+            span,
+            op,
+            tys: tys
+                .into_iter()
+                .map(|ty| format!("`{:?}`", WithCx::new(&cx, ty)))
+                .join(", "),
+        }),
+        CheckerError::CannotInferLocations => sess.emit_err(errors::CannotInferLocations { span }),
+        CheckerError::RefinedParamInstance(param, bty) => {
+            sess.emit_err(errors::RefinedParamInstance {
+                span,
+                param: param.name.to_string(),
+                ty: format!("{:?}", WithCx::new(&cx, bty)),
+            })
+        }
+    }
+    ErrorReported
+}
+
+/// Checks a call at `span` to a function with arguments `formals` and return type `ret`, returning
+/// the unpacked return type.
+fn check_fn_ty_call(
+    cursor: &mut Cursor,
+    span: Span,
+    callee: Option<DefId>,
+    formals: &[Ty],
+    ret: Ty,
+    actuals: Vec<Ty>,
+) -> Result<Ty, CheckerError> {
+    for (i, (actual, formal)) in actuals.into_iter().zip(formals).enumerate() {
+        cursor.set_tag(Tag::Arg(span, callee, i));
+        cursor.subtyping(actual, formal.clone())?;
+    }
+    Ok(cursor.unpack(ret))
+}
+
+/// Follows references until reaching a type that is not a reference.
+fn deref(env: &TypeEnv, ty: Ty) -> Result<Ty, CheckerError> {
+    match ty.kind() {
+        TyKind::StrgRef(loc) => deref(env, env.lookup_referee(*loc)?),
+        TyKind::Ref(ty) => deref(env, ty.clone()),
+        _ => Ok(ty),
     }
 }
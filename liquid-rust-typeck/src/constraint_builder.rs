@@ -3,23 +3,46 @@ use std::{
     ptr::NonNull,
 };
 
-use crate::ty::{self, BaseTy, Expr, ExprKind, Pred, Sort, Ty, TyKind, Var};
-use fixpoint::{BinOp, KVar, KVid, Name};
+use crate::{
+    checker::CheckerError,
+    lowering,
+    pretty::NameMap,
+    ty::{self, BaseTy, Expr, ExprKind, Pred, Sort, Ty, TyKind, Var},
+};
+use fixpoint::{BinOp, KVar, KVid, Name, TagIdx};
 use itertools::{izip, Itertools};
 use liquid_rust_common::{
     format::PadAdapter,
     index::{IndexGen, IndexVec},
 };
+use liquid_rust_core::{ir::Local, ty as core};
 use liquid_rust_fixpoint as fixpoint;
+use rustc_hash::FxHashMap;
+use rustc_hir::def_id::DefId;
 use rustc_middle::ty::TyCtxt;
+use rustc_span::{Span, Symbol};
 
 pub struct ConstraintBuilder<'tcx> {
     tcx: TyCtxt<'tcx>,
     root: Node,
     kvars: IndexVec<KVid, Vec<Sort>>,
+    consts: Vec<(Name, Sort)>,
+    tags: IndexVec<TagIdx, Tag>,
+    default_tag: Option<TagIdx>,
     scopes: Vec<usize>,
     vars: Vec<(Name, Sort)>,
     name_gen: IndexGen<Name>,
+    var_names: FxHashMap<Name, String>,
+    /// The types of the fields of the structs used by the bodies checked so far, see
+    /// [`Cursor::declare_adt_fields`].
+    adt_fields: FxHashMap<DefId, Vec<core::Ty>>,
+}
+
+/// What the tags and names of a constraint stand for in the source.
+pub struct Origins {
+    pub tags: IndexVec<TagIdx, Tag>,
+    /// The source expressions whose values are given by names, see [`Cursor::name_var`].
+    pub var_names: FxHashMap<Name, String>,
 }
 
 pub struct Cursor<'a, 'tcx> {
@@ -27,13 +50,56 @@ pub struct Cursor<'a, 'tcx> {
     node: NonNull<Node>,
     nscopes: usize,
     nvars: usize,
+    tag: Option<TagIdx>,
 }
 
 enum Node {
     Conj(Vec<Node>),
     ForAll(Name, Sort, Pred, Vec<Node>),
     Guard(Expr, Vec<Node>),
-    Head(Pred),
+    Head(Pred, Option<TagIdx>),
+}
+
+/// Why a head has to hold. Fixpoint reports the tags of failing heads so we can point at the
+/// source of each error.
+#[derive(Clone, Copy, Debug)]
+pub enum Tag {
+    /// The code at the span may panic.
+    Panic(Span),
+    /// An obligation in the body of the function at the span. This tells apart functions checked
+    /// in the same query.
+    Body(Span),
+    /// A precondition of the function called at the span other than those on its arguments, e.g.,
+    /// the predicate of a refinement parameter or the type of a required location.
+    Call(Span, Option<DefId>),
+    /// The precondition on the argument at the given position of the call at the span.
+    Arg(Span, Option<DefId>, usize),
+    /// The postcondition of the function returning at the span.
+    Ret(Span),
+    /// The divisor of the division at the span is not zero.
+    Div(Span),
+    /// A value written at the span satisfies the type of the location it is written to.
+    Assign(Span),
+    /// The environment at a jump at the span satisfies the one inferred for its target.
+    Goto(Span),
+    /// The code at the span is unreachable.
+    Unreachable(Span),
+}
+
+impl Tag {
+    pub fn span(&self) -> Span {
+        match self {
+            Tag::Panic(span)
+            | Tag::Body(span)
+            | Tag::Call(span, _)
+            | Tag::Arg(span, ..)
+            | Tag::Ret(span)
+            | Tag::Div(span)
+            | Tag::Assign(span)
+            | Tag::Goto(span)
+            | Tag::Unreachable(span) => *span,
+        }
+    }
 }
 
 impl<'tcx> ConstraintBuilder<'tcx> {
@@ -42,9 +108,14 @@ impl<'tcx> ConstraintBuilder<'tcx> {
             tcx,
             root: Node::Conj(vec![]),
             kvars: IndexVec::new(),
+            consts: vec![],
+            tags: IndexVec::new(),
+            default_tag: None,
             scopes: vec![],
             vars: vec![],
             name_gen: IndexGen::new(),
+            var_names: FxHashMap::default(),
+            adt_fields: FxHashMap::default(),
         }
     }
 
@@ -55,11 +126,17 @@ impl<'tcx> ConstraintBuilder<'tcx> {
                 builder: self,
                 nvars: 0,
                 nscopes: 0,
+                tag: None,
             }
         }
     }
 
-    pub fn into_fixpoint(self) -> fixpoint::Fixpoint {
+    /// Tags the heads pushed from now on that don't have a tag of their own with `tag`.
+    pub fn set_default_tag(&mut self, tag: Tag) {
+        self.default_tag = Some(self.tags.push(tag));
+    }
+
+    pub fn into_fixpoint(self) -> (fixpoint::Fixpoint, Origins) {
         let constraint = self
             .root
             .into_fixpoint(&self.name_gen, &self.kvars)
@@ -69,7 +146,14 @@ impl<'tcx> ConstraintBuilder<'tcx> {
             .into_iter_enumerated()
             .map(|(kvid, sorts)| KVar(kvid, sorts))
             .collect();
-        fixpoint::Fixpoint::new(kvars, constraint)
+        let origins = Origins {
+            tags: self.tags,
+            var_names: self.var_names,
+        };
+        (
+            fixpoint::Fixpoint::new(kvars, self.consts, constraint),
+            origins,
+        )
     }
 }
 
@@ -99,10 +183,25 @@ impl<'tcx> Cursor<'_, 'tcx> {
         self.push_node(Node::Guard(expr, vec![]));
     }
 
+    /// Tags the heads pushed from now on through this cursor, or through cursors created from it,
+    /// with `tag`.
+    pub fn set_tag(&mut self, tag: Tag) {
+        self.tag = Some(self.builder.tags.push(tag));
+    }
+
     pub fn push_head(&mut self, pred: impl Into<Pred>) {
         let pred = pred.into();
         if !pred.is_true() {
-            self.push_node(Node::Head(pred));
+            let tag = self.tag.or(self.builder.default_tag);
+            self.push_node(Node::Head(pred, tag));
+        }
+    }
+
+    pub fn push_tagged_head(&mut self, pred: impl Into<Pred>, tag: Tag) {
+        let pred = pred.into();
+        if !pred.is_true() {
+            let tag = self.builder.tags.push(tag);
+            self.push_node(Node::Head(pred, Some(tag)));
         }
     }
 
@@ -111,7 +210,10 @@ impl<'tcx> Cursor<'_, 'tcx> {
     }
 
     pub fn fresh_kvar_at_last_scope(&mut self, sort: Sort) -> Pred {
-        let scope = self.builder.scopes.last().copied().unwrap_or(0);
+        let scope = self.builder.scopes[..self.nscopes]
+            .last()
+            .copied()
+            .unwrap_or(0);
         self.fresh_kvar_at_scope(sort, scope)
     }
 
@@ -130,89 +232,204 @@ impl<'tcx> Cursor<'_, 'tcx> {
         Pred::kvar(kvid, args)
     }
 
+    /// Records the types of the fields of structs, in terms of the generic parameters of each
+    /// struct, which are needed to unfold and fold them.
+    pub fn declare_adt_fields(&mut self, adt_fields: &FxHashMap<DefId, Vec<core::Ty>>) {
+        for (did, fields) in adt_fields {
+            self.builder
+                .adt_fields
+                .entry(*did)
+                .or_insert_with(|| fields.clone());
+        }
+    }
+
+    /// Returns the declared types of the fields of a struct instantiated with its type arguments.
+    /// Only the structs whose fields were declared with [`Cursor::declare_adt_fields`] are
+    /// supported.
+    pub fn field_tys(&mut self, bty: &BaseTy) -> Result<Vec<Ty>, CheckerError> {
+        let fields = match bty {
+            BaseTy::Adt(did, _) => self.builder.adt_fields.get(did).cloned(),
+            _ => None,
+        };
+        match (bty, fields) {
+            (BaseTy::Adt(_, substs), Some(fields)) => {
+                let mut subst = lowering::Subst::with_lowered_type_substs(substs.iter().cloned());
+                Ok(fields.iter().map(|ty| subst.lower_ty(self, ty)).collect())
+            }
+            _ => Err(CheckerError::unsupported_bty(".", bty)),
+        }
+    }
+
+    /// Unfolds a struct into the types of its fields. The index of the struct is lost, which is
+    /// fine because it doesn't say anything about its fields.
+    pub fn unfold_adt(&mut self, bty: &BaseTy) -> Result<Ty, CheckerError> {
+        let fields = self
+            .field_tys(bty)?
+            .into_iter()
+            .map(|ty| self.unpack(ty))
+            .collect_vec();
+        Ok(TyKind::unfolded(bty.clone(), fields))
+    }
+
+    /// Returns the predicate saying that `e` is within the bounds of the integer type `bty`.
+    pub fn in_bounds(&self, bty: &BaseTy, e: &Expr) -> Option<Expr> {
+        let pointer_width = self.builder.tcx.data_layout.pointer_size.bits();
+        let (min, max) = bty.int_bounds(pointer_width)?;
+        let lower = ExprKind::BinaryOp(BinOp::Le, min, e.clone()).intern();
+        let upper = ExprKind::BinaryOp(BinOp::Le, e.clone(), max).intern();
+        Some(ExprKind::BinaryOp(BinOp::And, lower, upper).intern())
+    }
+
+    /// Declares an uninterpreted constant, e.g., an abstract refinement of the function being
+    /// checked. Unlike the variables in foralls, constants are never arguments of kvars.
+    pub fn declare_const(&mut self, name: Name, sort: Sort) {
+        self.builder.consts.push((name, sort));
+    }
+
+    /// Records that `name` stands for the value of `source`, e.g., a local variable or a refinement
+    /// parameter, to present counterexamples. The first source recorded for a name is kept.
+    pub fn name_var(&mut self, name: Name, source: impl fmt::Display) {
+        self.builder
+            .var_names
+            .entry(name)
+            .or_insert_with(|| source.to_string());
+    }
+
+    /// The source names of the variables named so far and of the user variables in `locals`.
+    pub fn name_map(&self, locals: &FxHashMap<Local, Symbol>) -> NameMap {
+        NameMap::new(&self.builder.var_names, locals)
+    }
+
     pub fn fresh_name(&self) -> Name {
         self.builder.name_gen.fresh()
     }
 
-    pub fn subtyping(&mut self, ty1: Ty, ty2: Ty) {
+    /// Returns an empty builder generating names that are fresh with respect to this one. This is
+    /// used to run inference on a body nested inside the current one, e.g., a closure.
+    pub fn fork(&self) -> ConstraintBuilder<'tcx> {
+        let mut builder = ConstraintBuilder::new(self.builder.tcx);
+        builder.adt_fields = self.builder.adt_fields.clone();
+        builder.name_gen.skip(self.fresh_name().index() + 1);
+        builder
+    }
+
+    pub fn subtyping(&mut self, ty1: Ty, ty2: Ty) -> Result<(), CheckerError> {
         let mut cursor = self.snapshot();
 
         // Optimize trivially satisfiable constraints
         match (ty1.kind(), ty2.kind()) {
             (TyKind::Refine(bty1, e1), TyKind::Refine(bty2, e2)) if e1 == e2 => {
-                cursor.bty_subtyping(bty1, bty2);
-                return;
+                return cursor.bty_subtyping(bty1, bty2);
             }
             (TyKind::Exists(bty1, p1), TyKind::Exists(bty2, p2)) if p1 == p2 => {
-                cursor.bty_subtyping(bty1, bty2);
-                return;
+                return cursor.bty_subtyping(bty1, bty2);
             }
             _ => {}
         }
 
+        // Fold owned structs before comparing them against a folded type, checking their fields
+        // against the declared types of the fields, and unfold a struct compared against an
+        // unfolded one. See an unrefined type parameter as one refined by an unknown index.
+        let ty1 = match (ty1.kind(), ty2.kind()) {
+            (TyKind::Struct(_, fields), TyKind::Refine(bty, _) | TyKind::Exists(bty, _)) => {
+                for (field, bound) in izip!(fields.iter(), cursor.field_tys(bty)?) {
+                    cursor.subtyping(field.clone(), bound)?;
+                }
+                TyKind::Exists(bty.clone(), Pred::Expr(Expr::tt())).intern()
+            }
+            (
+                TyKind::Refine(bty @ BaseTy::Adt(..), _) | TyKind::Exists(bty @ BaseTy::Adt(..), _),
+                TyKind::Struct(..),
+            ) => cursor.unfold_adt(bty)?,
+            (TyKind::Param(param), TyKind::Refine(..) | TyKind::Exists(..)) => {
+                TyKind::Exists(BaseTy::Param(*param), Pred::Expr(Expr::tt())).intern()
+            }
+            _ => ty1,
+        };
+
         let ty1 = cursor.unpack(ty1);
         match (ty1.kind(), ty2.kind()) {
             (TyKind::Refine(bty1, e1), TyKind::Refine(bty2, e2)) => {
-                cursor.bty_subtyping(bty1, bty2);
+                cursor.bty_subtyping(bty1, bty2)?;
                 cursor.push_head(ExprKind::BinaryOp(BinOp::Eq, e1.clone(), e2.clone()).intern());
             }
             (TyKind::Refine(bty1, e), TyKind::Exists(bty2, p)) => {
-                cursor.bty_subtyping(bty1, bty2);
+                cursor.bty_subtyping(bty1, bty2)?;
                 let p = p.subst_bound_vars(e.clone());
                 cursor.push_head(p.subst_bound_vars(e.clone()))
             }
-            (TyKind::StrgRef(loc1), TyKind::StrgRef(loc2)) => {
-                assert_eq!(loc1, loc2);
+            (TyKind::Tuple(tys1), TyKind::Tuple(tys2)) if tys1.len() == tys2.len() => {
+                for (ty1, ty2) in tys1.iter().zip(tys2.iter()) {
+                    cursor.subtyping(ty1.clone(), ty2.clone())?;
+                }
+            }
+            (TyKind::Struct(bty1, fields1), TyKind::Struct(bty2, fields2)) => {
+                cursor.bty_subtyping(bty1, bty2)?;
+                for (ty1, ty2) in fields1.iter().zip(fields2.iter()) {
+                    cursor.subtyping(ty1.clone(), ty2.clone())?;
+                }
+            }
+            (TyKind::StrgRef(loc1), TyKind::StrgRef(loc2)) if loc1 == loc2 => {}
+            (TyKind::Ref(ty1), TyKind::Ref(ty2)) => {
+                cursor.subtyping(ty1.clone(), ty2.clone())?;
             }
             (_, TyKind::Uninit) => {
                 // FIXME: we should rethink in which situation this is sound.
             }
-            (TyKind::Param(param1), TyKind::Param(param2)) => {
-                debug_assert_eq!(param1, param2)
-            }
-            (TyKind::Exists(..), _) => {
-                unreachable!("subtyping with unpacked existential")
+            (TyKind::Param(param1), TyKind::Param(param2))
+            | (TyKind::Refine(BaseTy::Param(param1), _), TyKind::Param(param2))
+                if param1 == param2 => {}
+            (TyKind::Fn(args1, ret1), TyKind::Fn(args2, ret2)) if args1.len() == args2.len() => {
+                for (arg1, arg2) in args1.iter().zip(args2.iter()) {
+                    cursor.subtyping(arg2.clone(), arg1.clone())?;
+                }
+                cursor.subtyping(ret1.clone(), ret2.clone())?;
             }
-            _ => {
-                unreachable!("unexpected types: `{:?}` `{:?}`", ty1, ty2)
+            (_, TyKind::Never) => {
+                // No value inhabits `!`, so this point must be unreachable.
+                cursor.push_head(Expr::ff());
             }
+            _ => return Err(CheckerError::IncompatibleTypes(ty1, ty2)),
         }
+        Ok(())
     }
 
-    fn bty_subtyping(&mut self, bty1: &BaseTy, bty2: &BaseTy) {
+    fn bty_subtyping(&mut self, bty1: &BaseTy, bty2: &BaseTy) -> Result<(), CheckerError> {
         match (bty1, bty2) {
-            (BaseTy::Int(int_ty1), BaseTy::Int(int_ty2)) => {
-                debug_assert_eq!(int_ty1, int_ty2);
-            }
-            (BaseTy::Uint(uint_ty1), BaseTy::Uint(uint_ty2)) => {
-                debug_assert_eq!(uint_ty1, uint_ty2);
-            }
+            (BaseTy::Int(int_ty1), BaseTy::Int(int_ty2)) if int_ty1 == int_ty2 => {}
+            (BaseTy::Uint(uint_ty1), BaseTy::Uint(uint_ty2)) if uint_ty1 == uint_ty2 => {}
             (BaseTy::Bool, BaseTy::Bool) => {}
-            (BaseTy::Adt(did1, substs1), BaseTy::Adt(did2, substs2)) => {
-                debug_assert_eq!(did1, did2);
-                debug_assert_eq!(substs1.len(), substs2.len());
+            (BaseTy::Param(param1), BaseTy::Param(param2)) if param1 == param2 => {}
+            (BaseTy::Adt(did1, substs1), BaseTy::Adt(did2, substs2))
+                if did1 == did2 && substs1.len() == substs2.len() =>
+            {
                 let variances = self.builder.tcx.variances_of(*did1);
                 for (variance, ty1, ty2) in izip!(variances, substs1.iter(), substs2.iter()) {
-                    self.polymorphic_subtyping(*variance, ty1.clone(), ty2.clone());
+                    self.polymorphic_subtyping(*variance, ty1.clone(), ty2.clone())?;
                 }
             }
-            _ => unreachable!("unexpected base types: `{:?}` `{:?}`", bty1, bty2),
+            (BaseTy::Array(ty1), BaseTy::Array(ty2)) | (BaseTy::Slice(ty1), BaseTy::Slice(ty2)) => {
+                self.subtyping(ty1.clone(), ty2.clone())?;
+            }
+            _ => return Err(CheckerError::incompatible_btys(bty1, bty2)),
         }
+        Ok(())
     }
 
-    fn polymorphic_subtyping(&mut self, variance: rustc_middle::ty::Variance, ty1: Ty, ty2: Ty) {
+    fn polymorphic_subtyping(
+        &mut self,
+        variance: rustc_middle::ty::Variance,
+        ty1: Ty,
+        ty2: Ty,
+    ) -> Result<(), CheckerError> {
         match variance {
-            rustc_middle::ty::Variance::Covariant => {
-                self.subtyping(ty1, ty2);
-            }
+            rustc_middle::ty::Variance::Covariant => self.subtyping(ty1, ty2),
             rustc_middle::ty::Variance::Invariant => {
-                self.subtyping(ty1.clone(), ty2.clone());
-                self.subtyping(ty2, ty1);
+                self.subtyping(ty1.clone(), ty2.clone())?;
+                self.subtyping(ty2, ty1)
             }
-            rustc_middle::ty::Variance::Contravariant => {
-                self.subtyping(ty2, ty1);
-            }
-            rustc_middle::ty::Variance::Bivariant => {}
+            rustc_middle::ty::Variance::Contravariant => self.subtyping(ty2, ty1),
+            rustc_middle::ty::Variance::Bivariant => Ok(()),
         }
     }
 
@@ -220,12 +437,16 @@ impl<'tcx> Cursor<'_, 'tcx> {
         match ty.kind() {
             TyKind::Exists(bty, p) => {
                 let fresh = self.fresh_name();
-                self.push_forall(
-                    fresh,
-                    bty.sort(),
-                    p.subst_bound_vars(ExprKind::Var(Var::Free(fresh)).intern()),
-                );
-                TyKind::Refine(bty.clone(), ExprKind::Var(Var::Free(fresh)).intern()).intern()
+                let var = ExprKind::Var(Var::Free(fresh)).intern();
+                self.push_forall(fresh, bty.sort(), p.subst_bound_vars(var.clone()));
+                if let Some(in_bounds) = self.in_bounds(bty, &var) {
+                    self.push_guard(in_bounds);
+                }
+                TyKind::Refine(bty.clone(), var).intern()
+            }
+            TyKind::Tuple(tys) => TyKind::tuple(tys.iter().map(|ty| self.unpack(ty.clone()))),
+            TyKind::Struct(bty, fields) => {
+                TyKind::unfolded(bty.clone(), fields.iter().map(|ty| self.unpack(ty.clone())))
             }
             _ => ty,
         }
@@ -237,7 +458,7 @@ impl<'tcx> Cursor<'_, 'tcx> {
                 Node::Conj(children)
                 | Node::ForAll(_, _, _, children)
                 | Node::Guard(_, children) => children,
-                Node::Head(_) => unreachable!("trying to push into a head node."),
+                Node::Head(..) => unreachable!("trying to push into a head node."),
             };
             children.push(node);
             let node = children.last_mut().unwrap();
@@ -285,9 +506,9 @@ impl Node {
                 expr_to_fixpoint(expr),
                 Box::new(children_to_fixpoint(name_gen, kvars, children)?),
             )),
-            Node::Head(pred) => {
+            Node::Head(pred, tag) => {
                 let (bindings, pred) = pred_to_fixpoint(name_gen, kvars, pred);
-                Some(stitch(bindings, fixpoint::Constraint::Pred(pred)))
+                Some(stitch(bindings, fixpoint::Constraint::Pred(pred, tag)))
             }
         }
     }
@@ -360,6 +581,17 @@ fn pred_to_fixpoint(
             });
             fixpoint::Pred::KVar(kvid, args.collect())
         }
+        Pred::And(preds) => {
+            let preds = preds
+                .iter()
+                .map(|pred| {
+                    let (pred_bindings, pred) = pred_to_fixpoint(name_gen, kvars, pred.clone());
+                    bindings.extend(pred_bindings);
+                    pred
+                })
+                .collect();
+            fixpoint::Pred::And(preds)
+        }
     };
     (bindings, pred)
 }
@@ -376,6 +608,12 @@ fn expr_to_fixpoint(expr: ty::Expr) -> fixpoint::Expr {
         ty::ExprKind::UnaryOp(op, e) => {
             fixpoint::Expr::UnaryOp(*op, Box::new(expr_to_fixpoint(e.clone())))
         }
+        ty::ExprKind::App(func, args) => fixpoint::Expr::App(
+            *func,
+            args.iter()
+                .map(|arg| expr_to_fixpoint(arg.clone()))
+                .collect(),
+        ),
         ty::ExprKind::Var(Var::Bound) => {
             unreachable!("unexpected bound variable")
         }
@@ -402,7 +640,9 @@ mod pretty {
         }
 
         fn default_cx(tcx: TyCtxt) -> PPrintCx {
-            PPrintCx::default(tcx).kvar_args(Visibility::Truncate(1))
+            PPrintCx::default(tcx)
+                .kvar_args(Visibility::Truncate(1))
+                .fully_qualified_paths(true)
             // PPrintCx::default(tcx).kvar_args(Visibility::Show)
         }
     }
@@ -418,7 +658,7 @@ mod pretty {
                     let (bindings, children) = self.forall_chain().unwrap();
 
                     let vars = bindings.iter().format_with(", ", |(var, sort, _), f| {
-                        f(&format_args_cx!("{:?}: {:?}", ^var, ^sort))
+                        f(&format_args_cx!("{:?}: {:?}", Var::Free(*var), ^sort))
                     });
 
                     let preds = bindings
@@ -449,7 +689,7 @@ mod pretty {
                         w!("({:?}) ⇒{:?}", expr, children)
                     }
                 }
-                Node::Head(pred) => {
+                Node::Head(pred, _) => {
                     if pred.is_atom() {
                         w!("{:?}", pred)
                     } else {
@@ -497,5 +737,13 @@ mod pretty {
         }
     }
 
-    impl_debug_with_default_cx!(ConstraintBuilder<'_>, Node);
+    impl fmt::Debug for ConstraintBuilder<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let names = NameMap::new(&self.var_names, &FxHashMap::default());
+            let cx = ConstraintBuilder::default_cx(self.tcx).names(&names);
+            Pretty::fmt(self, &cx, f)
+        }
+    }
+
+    impl_debug_with_default_cx!(Node);
 }
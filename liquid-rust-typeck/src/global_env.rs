@@ -1,4 +1,4 @@
-use liquid_rust_core::ty::FnSig;
+use liquid_rust_core::ty::{FnSig, Ty};
 use rustc_hash::FxHashMap;
 use rustc_hir::def_id::{DefId, LocalDefId};
 use rustc_middle::ty::TyCtxt;
@@ -10,15 +10,80 @@ pub struct FnSpec {
 
 pub struct GlobalEnv<'tcx> {
     pub specs: FxHashMap<LocalDefId, FnSpec>,
+    /// Functions from other crates mapped to the local stub declaring their spec.
+    pub extern_specs: FxHashMap<DefId, LocalDefId>,
+    /// Signatures exported by the dependencies of the crate.
+    pub extern_fn_sigs: FxHashMap<DefId, FnSig>,
+    /// Impl methods implementing a trait method with a spec, mapped to the trait method and the
+    /// type arguments instantiating it for the impl.
+    pub trait_methods: FxHashMap<LocalDefId, (DefId, Vec<Ty>)>,
     pub tcx: TyCtxt<'tcx>,
 }
 
 impl<'tcx> GlobalEnv<'tcx> {
-    pub fn new(tcx: TyCtxt<'tcx>, specs: FxHashMap<LocalDefId, FnSpec>) -> Self {
-        GlobalEnv { tcx, specs }
+    pub fn new(
+        tcx: TyCtxt<'tcx>,
+        specs: FxHashMap<LocalDefId, FnSpec>,
+        extern_specs: FxHashMap<DefId, LocalDefId>,
+        extern_fn_sigs: FxHashMap<DefId, FnSig>,
+        trait_methods: FxHashMap<LocalDefId, (DefId, Vec<Ty>)>,
+    ) -> Self {
+        GlobalEnv {
+            tcx,
+            specs,
+            extern_specs,
+            extern_fn_sigs,
+            trait_methods,
+        }
     }
 
     pub fn lookup_fn_sig(&self, did: DefId) -> &FnSig {
-        &self.specs[&did.as_local().unwrap()].fn_sig
+        if let Some(stub) = self.extern_specs.get(&did) {
+            &self.specs[stub].fn_sig
+        } else if let Some(local_id) = did.as_local() {
+            &self.specs[&local_id].fn_sig
+        } else {
+            &self.extern_fn_sigs[&did]
+        }
+    }
+
+    /// The signatures exported to downstream crates: the ones of local functions and the ones
+    /// given to functions from other crates by extern specs.
+    pub fn exported_fn_sigs(&self) -> impl ExactSizeIterator<Item = (DefId, &FnSig)> {
+        let stubs: FxHashMap<_, _> = self
+            .extern_specs
+            .iter()
+            .map(|(extern_fn, stub)| (*stub, *extern_fn))
+            .collect();
+        self.specs.iter().map(move |(local_id, spec)| {
+            let def_id = stubs
+                .get(local_id)
+                .copied()
+                .unwrap_or_else(|| local_id.to_def_id());
+            (def_id, &spec.fn_sig)
+        })
+    }
+
+    /// The signature of `did`, if it has one. Functions only called in diverging calls, e.g.,
+    /// the ones called by `panic!`, may not have a signature.
+    pub fn try_lookup_fn_sig(&self, did: DefId) -> Option<&FnSig> {
+        if let Some(stub) = self.extern_specs.get(&did) {
+            Some(&self.specs[stub].fn_sig)
+        } else if let Some(local_id) = did.as_local() {
+            self.specs.get(&local_id).map(|spec| &spec.fn_sig)
+        } else {
+            self.extern_fn_sigs.get(&did)
+        }
+    }
+
+    /// Returns the signature a body has to be checked against together with the type arguments
+    /// instantiating it. Impl methods without a spec are checked against the spec of the trait.
+    pub fn lookup_body_fn_sig(&self, did: LocalDefId) -> (&FnSig, &[Ty]) {
+        if let Some(spec) = self.specs.get(&did) {
+            (&spec.fn_sig, &[])
+        } else {
+            let (trait_method, substs) = &self.trait_methods[&did];
+            (self.lookup_fn_sig(*trait_method), substs)
+        }
     }
 }
@@ -0,0 +1,256 @@
+//! Printing of inferred signatures in the syntax of `lr::ty` so users can copy them into code.
+use std::fmt;
+
+use itertools::Itertools;
+use liquid_rust_core::ty as core;
+use liquid_rust_fixpoint::{self as fixpoint, Solution};
+use rustc_hir::def_id::DefId;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Symbol;
+
+/// The signature of a function with the kvars introduced by
+/// [`instantiate_sig_kvars`](crate::lowering::instantiate_sig_kvars) replaced by their solution.
+/// Arguments and return types without refinements are printed as their Rust type.
+pub struct InferredSig<'a, 'tcx> {
+    pub tcx: TyCtxt<'tcx>,
+    pub def_id: DefId,
+    pub fn_sig: &'a core::FnSig,
+    pub solution: &'a Solution,
+}
+
+impl fmt::Display for InferredSig<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rust_sig = self
+            .tcx
+            .erase_late_bound_regions(self.tcx.fn_sig(self.def_id));
+
+        write!(f, "fn")?;
+        if !self.fn_sig.params.is_empty() {
+            let params = self.fn_sig.params.iter().format_with(", ", |param, f| {
+                f(&format_args!(
+                    "{}: {:?}",
+                    param.name.source_info.1, param.sort
+                ))?;
+                match self.solved(&param.pred, param.name.source_info.1) {
+                    Some(pred) => f(&format_args!("{{{}}}", pred)),
+                    None => Ok(()),
+                }
+            });
+            write!(f, "<{}>", params)?;
+        }
+
+        let args = self.fn_sig.args.iter().zip(rust_sig.inputs()).format_with(
+            ", ",
+            |(arg, rust_ty), f| match arg {
+                core::Ty::Refine(
+                    bty,
+                    core::Expr {
+                        kind: core::ExprKind::Var(_, symbol, _),
+                        ..
+                    },
+                ) => f(&format_args!("{}@{}", BaseTy(bty), symbol)),
+                _ => f(rust_ty),
+            },
+        );
+        write!(f, "({}) -> ", args)?;
+
+        match &self.fn_sig.ret {
+            core::Ty::Exists(bty, pred @ core::Pred::KVar(..)) => {
+                let bound = self.fresh_bound_name();
+                match self.solved(pred, bound) {
+                    Some(pred) => write!(f, "{}{{{}: {}}}", BaseTy(bty), bound, pred),
+                    None => write!(f, "{}", BaseTy(bty)),
+                }
+            }
+            _ => write!(f, "{}", rust_sig.output()),
+        }
+    }
+}
+
+impl<'a> InferredSig<'a, '_> {
+    fn solved(&self, pred: &'a core::Pred, bound: Symbol) -> Option<SolvedExpr<'a>> {
+        solved(self.solution, pred, bound)
+    }
+
+    /// A name for the value returned that doesn't clash with a parameter.
+    fn fresh_bound_name(&self) -> Symbol {
+        fresh_bound_name(|symbol| {
+            self.fn_sig
+                .params
+                .iter()
+                .any(|param| param.name.source_info.1 == symbol)
+        })
+    }
+}
+
+/// The solution of the kvar replacing a hole `_` written by the user, printed as a refinement
+/// `{v: p}` that can be written in its place.
+pub struct InferredPred<'a> {
+    pub pred: &'a core::Pred,
+    pub solution: &'a Solution,
+}
+
+impl fmt::Display for InferredPred<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = match self.pred {
+            core::Pred::KVar(_, args) => args,
+            _ => unreachable!("holes are replaced by kvars"),
+        };
+        let bound = fresh_bound_name(|symbol| {
+            args.iter().any(|arg| {
+                matches!(arg.kind, core::ExprKind::Var(core::Var::Free(_), s, _) if s == symbol)
+            })
+        });
+        match solved(self.solution, self.pred, bound) {
+            Some(pred) => write!(f, "{{{}: {}}}", bound, pred),
+            None => write!(f, "{{{}: true}}", bound),
+        }
+    }
+}
+
+/// Returns the solution of `pred` if it is a kvar with a solution other than `true` that only
+/// mentions its arguments.
+fn solved<'a>(
+    solution: &'a Solution,
+    pred: &'a core::Pred,
+    bound: Symbol,
+) -> Option<SolvedExpr<'a>> {
+    let (kvid, args) = match pred {
+        core::Pred::KVar(kvid, args) => (kvid, args),
+        _ => return None,
+    };
+    let expr = solution.get(kvid)?;
+    if matches!(
+        expr,
+        fixpoint::Expr::Constant(fixpoint::Constant::Bool(true))
+    ) || !vars_in_range(expr, args.len())
+    {
+        return None;
+    }
+    let names = args
+        .iter()
+        .map(|arg| match &arg.kind {
+            core::ExprKind::Var(core::Var::Bound, ..) => bound,
+            core::ExprKind::Var(_, symbol, _) => *symbol,
+            _ => unreachable!("unexpected kvar argument"),
+        })
+        .collect();
+    Some(SolvedExpr { expr, names })
+}
+
+fn fresh_bound_name(taken: impl Fn(Symbol) -> bool) -> Symbol {
+    std::iter::once(Symbol::intern("v"))
+        .chain((0..).map(|i| Symbol::intern(&format!("v{}", i))))
+        .find(|symbol| !taken(*symbol))
+        .unwrap()
+}
+
+struct BaseTy<'a>(&'a core::BaseTy);
+
+impl fmt::Display for BaseTy<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            core::BaseTy::Int(int_ty) => write!(f, "{}", int_ty.name_str()),
+            core::BaseTy::Uint(uint_ty) => write!(f, "{}", uint_ty.name_str()),
+            core::BaseTy::Bool => write!(f, "bool"),
+            _ => unreachable!("only scalars are refined in inferred signatures"),
+        }
+    }
+}
+
+/// A solution of a kvar, whose `i`-th argument is printed as `names[i]`.
+struct SolvedExpr<'a> {
+    expr: &'a fixpoint::Expr,
+    names: Vec<Symbol>,
+}
+
+impl SolvedExpr<'_> {
+    fn fmt_expr(&self, expr: &fixpoint::Expr, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match expr {
+            fixpoint::Expr::Var(name) => write!(f, "{}", self.names[name.index()]),
+            fixpoint::Expr::Constant(c @ fixpoint::Constant::Int(..)) => {
+                // There are no negative literals in the syntax of specs.
+                let c = c.to_string();
+                match c.strip_prefix('-') {
+                    Some(n) => write!(f, "(0 - {})", n),
+                    None => write!(f, "{}", c),
+                }
+            }
+            fixpoint::Expr::Constant(c) => write!(f, "{}", c),
+            fixpoint::Expr::BinaryOp(op, e1, e2) => {
+                self.fmt_operand(*op, e1, f)?;
+                write!(f, " {} ", bin_op_str(*op))?;
+                self.fmt_operand(*op, e2, f)
+            }
+            fixpoint::Expr::UnaryOp(fixpoint::UnOp::Not, e) => {
+                write!(f, "!(")?;
+                self.fmt_expr(e, f)?;
+                write!(f, ")")
+            }
+            fixpoint::Expr::UnaryOp(fixpoint::UnOp::Neg, e) => {
+                write!(f, "(0 - ")?;
+                self.fmt_expr(e, f)?;
+                write!(f, ")")
+            }
+            fixpoint::Expr::App(..) => unreachable!("solutions only mention kvar arguments"),
+        }
+    }
+
+    fn fmt_operand(
+        &self,
+        op: fixpoint::BinOp,
+        operand: &fixpoint::Expr,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        let parenthesize = match operand {
+            fixpoint::Expr::BinaryOp(child_op, ..) => {
+                child_op.precedence() < op.precedence()
+                    || (child_op.precedence() == op.precedence()
+                        && !op.precedence().is_associative())
+            }
+            _ => false,
+        };
+        if parenthesize {
+            write!(f, "(")?;
+            self.fmt_expr(operand, f)?;
+            write!(f, ")")
+        } else {
+            self.fmt_expr(operand, f)
+        }
+    }
+}
+
+impl fmt::Display for SolvedExpr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_expr(self.expr, f)
+    }
+}
+
+fn vars_in_range(expr: &fixpoint::Expr, nargs: usize) -> bool {
+    match expr {
+        fixpoint::Expr::Var(name) => name.index() < nargs,
+        fixpoint::Expr::Constant(_) => true,
+        fixpoint::Expr::BinaryOp(_, e1, e2) => vars_in_range(e1, nargs) && vars_in_range(e2, nargs),
+        fixpoint::Expr::UnaryOp(_, e) => vars_in_range(e, nargs),
+        fixpoint::Expr::App(..) => false,
+    }
+}
+
+fn bin_op_str(op: fixpoint::BinOp) -> &'static str {
+    match op {
+        fixpoint::BinOp::Iff => "<=>",
+        fixpoint::BinOp::Imp => "=>",
+        fixpoint::BinOp::Or => "||",
+        fixpoint::BinOp::And => "&&",
+        fixpoint::BinOp::Eq => "==",
+        fixpoint::BinOp::Ne => "!=",
+        fixpoint::BinOp::Gt => ">",
+        fixpoint::BinOp::Ge => ">=",
+        fixpoint::BinOp::Lt => "<",
+        fixpoint::BinOp::Le => "<=",
+        fixpoint::BinOp::Add => "+",
+        fixpoint::BinOp::Sub => "-",
+        fixpoint::BinOp::Mul => "*",
+        fixpoint::BinOp::Div => "/",
+    }
+}
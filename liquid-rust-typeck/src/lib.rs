@@ -14,6 +14,7 @@ extern crate rustc_span;
 mod checker;
 mod constraint_builder;
 pub mod global_env;
+mod inferred_sigs;
 mod intern;
 mod lowering;
 mod pretty;
@@ -23,58 +24,357 @@ mod type_env;
 use std::{fs, io::Write};
 
 use checker::Checker;
-use constraint_builder::ConstraintBuilder;
+use constraint_builder::{ConstraintBuilder, Origins, Tag};
 use global_env::GlobalEnv;
+use inferred_sigs::{InferredPred, InferredSig};
+use itertools::Itertools;
 use liquid_rust_common::{config::CONFIG, errors::ErrorReported};
-use liquid_rust_core::ir::Body;
-use liquid_rust_fixpoint::{Fixpoint, FixpointResult, Safeness};
-use rustc_hir::def_id::DefId;
+use liquid_rust_core::ir::{BasicBlock, Body};
+use liquid_rust_fixpoint::{Fixpoint, FixpointError, FixpointResult, Solution, TagIdx};
+use pretty::{NameMap, PPrintCx, Visibility, WithCx};
+use rustc_hash::FxHashMap;
+use rustc_hir::def_id::{DefId, LocalDefId, LOCAL_CRATE};
 use rustc_middle::ty::TyCtxt;
+use rustc_session::SessionDiagnostic;
+use rustc_span::Span;
+use type_env::TypeEnv;
 
 pub fn check<'tcx>(
     global_env: &GlobalEnv<'tcx>,
     def_id: DefId,
     body: &Body<'tcx>,
 ) -> Result<(), ErrorReported> {
-    let fn_sig = global_env.lookup_fn_sig(def_id);
+    let (fn_sig, substs) = global_env.lookup_body_fn_sig(def_id.expect_local());
 
-    let bb_envs = Checker::infer(global_env, body, fn_sig)?;
-    let constraint = Checker::check(global_env, body, fn_sig, bb_envs)?;
+    let bb_envs = Checker::infer(global_env, body, fn_sig, substs)?;
+    let mut constraint = ConstraintBuilder::new(global_env.tcx);
+    let join_envs = Checker::check(global_env, &mut constraint, body, fn_sig, substs, bb_envs)?;
 
     if CONFIG.dump_constraint {
-        dump_constraint(global_env.tcx, def_id, &constraint).unwrap();
+        dump_constraint(
+            global_env.tcx,
+            &global_env.tcx.def_path_str(def_id),
+            &constraint,
+        );
     }
 
-    let constraint = constraint.into_fixpoint();
+    let (constraint, origins) = constraint.into_fixpoint();
+    let result = run_fixpoint(&constraint);
+    if let (true, Ok((_, solution))) = (CONFIG.show_invariants, &result) {
+        report_invariants(global_env.tcx, body, &join_envs, &origins, solution);
+    }
 
-    match Fixpoint::check(&constraint) {
-        Ok(FixpointResult {
-            tag: Safeness::Safe,
-        }) => Ok(()),
-        Ok(FixpointResult {
-            tag: Safeness::Unsafe,
-        }) => {
-            global_env.tcx.sess.emit_err(errors::RefineError {
-                span: body.mir.span,
-            });
-            Err(ErrorReported)
+    report(
+        global_env,
+        &[body.mir.span],
+        result,
+        &constraint,
+        &origins,
+        |span| errors::RefineError { span },
+    )
+}
+
+/// Checks that the spec of an impl method refines the spec of the trait method it implements:
+/// the requirements of the impl method must be implied by those of the trait method and its
+/// guarantees must imply the ones of the trait method.
+pub fn check_impl_method(global_env: &GlobalEnv, def_id: DefId) -> Result<(), ErrorReported> {
+    let local_id = def_id.expect_local();
+    let fn_sig = &global_env.specs[&local_id].fn_sig;
+    let (trait_method, substs) = &global_env.trait_methods[&local_id];
+    let trait_sig = global_env.lookup_fn_sig(*trait_method);
+    let span = global_env.tcx.def_span(def_id);
+
+    let constraint = Checker::check_refines(global_env, span, fn_sig, trait_sig, substs)?;
+
+    if CONFIG.dump_constraint {
+        let name = format!("{}.refines", global_env.tcx.def_path_str(def_id));
+        dump_constraint(global_env.tcx, &name, &constraint);
+    }
+
+    solve(global_env, span, constraint, |span| {
+        errors::ImplMethodError {
+            span,
+            trait_span: global_env.tcx.def_span(*trait_method),
+        }
+    })
+}
+
+/// Checks `bodies` in a single fixpoint query, inferring the predicates left to be inferred in the
+/// signatures of the functions in `inferred`. `bodies` are the ones of these functions and of their
+/// callers. The solutions of the holes written by the user are reported as notes, as are the
+/// signatures inferred for functions without a spec.
+pub fn check_inferring_sigs<'tcx>(
+    global_env: &mut GlobalEnv<'tcx>,
+    inferred: &[LocalDefId],
+    bodies: &[(LocalDefId, Body<'tcx>)],
+) -> Result<(), ErrorReported> {
+    let tcx = global_env.tcx;
+    let mut constraint = ConstraintBuilder::new(tcx);
+    let holes: Vec<_> = inferred
+        .iter()
+        .map(|def_id| {
+            let fn_sig = &mut global_env.specs.get_mut(def_id).unwrap().fn_sig;
+            lowering::instantiate_sig_kvars(&mut constraint.as_cursor(), fn_sig)
+        })
+        .collect();
+    let global_env = &*global_env;
+
+    // A body with an error is left out of the query, which still checks the other ones.
+    let mut checked = vec![];
+    let mut errors = Ok(());
+    for (def_id, body) in bodies {
+        let (fn_sig, substs) = global_env.lookup_body_fn_sig(*def_id);
+        let join_envs = Checker::infer(global_env, body, fn_sig, substs).and_then(|bb_envs| {
+            constraint.set_default_tag(Tag::Body(body.mir.span));
+            Checker::check(global_env, &mut constraint, body, fn_sig, substs, bb_envs)
+        });
+        match join_envs {
+            Ok(join_envs) => checked.push((body, join_envs)),
+            Err(err) => errors = Err(err),
         }
-        Ok(FixpointResult {
-            tag: Safeness::Crash,
-        }) => panic!("fixpoint crash"),
-        Err(err) => panic!("failed to run fixpoint: {:?}", err),
     }
+
+    if CONFIG.dump_constraint {
+        let name = format!("{}.inferred", tcx.crate_name(LOCAL_CRATE));
+        dump_constraint(tcx, &name, &constraint);
+    }
+
+    let (constraint, origins) = constraint.into_fixpoint();
+    let result = constraint.check_with_solution().map(|(result, solution)| {
+        for (def_id, holes) in inferred.iter().zip(&holes) {
+            for (span, pred) in holes {
+                let pred = InferredPred {
+                    pred,
+                    solution: &solution,
+                };
+                tcx.sess
+                    .span_note_without_error(*span, &format!("inferred `{}`", pred));
+            }
+            if holes.is_empty() {
+                let sig = InferredSig {
+                    tcx,
+                    def_id: def_id.to_def_id(),
+                    fn_sig: &global_env.specs[def_id].fn_sig,
+                    solution: &solution,
+                };
+                tcx.sess.span_note_without_error(
+                    tcx.def_span(*def_id),
+                    &format!("inferred signature `#[lr::ty({})]`", sig),
+                );
+            }
+        }
+        if CONFIG.show_invariants {
+            for (body, join_envs) in &checked {
+                report_invariants(tcx, body, join_envs, &origins, &solution);
+            }
+        }
+        (result, solution)
+    });
+
+    // Failures that cannot be attributed to one of the functions are reported at all of them.
+    let spans = checked.iter().map(|(body, _)| body.mir.span).collect_vec();
+    let result = report(global_env, &spans, result, &constraint, &origins, |span| {
+        errors::RefineError { span }
+    });
+    errors.and(result)
 }
 
-fn dump_constraint(
+fn solve<'tcx, E: for<'a> SessionDiagnostic<'a>>(
+    global_env: &GlobalEnv<'tcx>,
+    span: Span,
+    constraint: ConstraintBuilder<'tcx>,
+    untagged_error: impl Fn(Span) -> E,
+) -> Result<(), ErrorReported> {
+    let (constraint, origins) = constraint.into_fixpoint();
+    let result = run_fixpoint(&constraint);
+    report(
+        global_env,
+        &[span],
+        result,
+        &constraint,
+        &origins,
+        untagged_error,
+    )
+}
+
+fn run_fixpoint(constraint: &Fixpoint) -> Result<(FixpointResult, Solution), FixpointError> {
+    // Without kvars there's no solution to ask fixpoint for, and nothing to infer, so z3 can check
+    // the constraint directly.
+    if constraint.kvars.is_empty() {
+        let result = if CONFIG.direct_smt {
+            constraint.check_smt()
+        } else {
+            constraint.check()
+        };
+        result.map(|result| (result, Solution::default()))
+    } else {
+        constraint.check_with_solution()
+    }
+}
+
+/// Reports the environments inferred for the join points of `body`, e.g., loop headers, as notes
+/// with the kvars in them replaced by their solution.
+fn report_invariants(
     tcx: TyCtxt,
-    def_id: DefId,
-    constraint: &ConstraintBuilder,
-) -> Result<(), std::io::Error> {
+    body: &Body,
+    join_envs: &FxHashMap<BasicBlock, TypeEnv>,
+    origins: &Origins,
+    solution: &Solution,
+) {
+    let names = NameMap::new(&origins.var_names, &checker::local_names(body));
+    let cx = PPrintCx::default(tcx)
+        .kvar_args(Visibility::Hide)
+        .solution(solution)
+        .names(&names);
+    for (bb, env) in join_envs.iter().sorted_by_key(|(bb, _)| **bb) {
+        let data = &body.basic_blocks[*bb];
+        let source_info = match (data.statements.first(), &data.terminator) {
+            (Some(stmt), _) => stmt.source_info,
+            (None, Some(terminator)) => terminator.source_info,
+            (None, None) => continue,
+        };
+        tcx.sess.span_note_without_error(
+            source_info.span,
+            &format!("inferred invariant `{:?}`", WithCx::new(&cx, env)),
+        );
+    }
+}
+
+/// Reports the errors in the result of a fixpoint query. Failing heads without a tag are reported
+/// with `untagged_error` and failures of fixpoint itself at each of the `spans` of the functions
+/// checked in the query.
+fn report<'tcx, E: for<'a> SessionDiagnostic<'a>>(
+    global_env: &GlobalEnv<'tcx>,
+    spans: &[Span],
+    result: Result<(FixpointResult, Solution), FixpointError>,
+    constraint: &Fixpoint,
+    origins: &Origins,
+    untagged_error: impl Fn(Span) -> E,
+) -> Result<(), ErrorReported> {
+    match result {
+        Ok((FixpointResult::Safe(_), _)) => Ok(()),
+        Ok((FixpointResult::Unsafe(_, errors), solution)) => {
+            let sess = global_env.tcx.sess;
+            let mut untagged = errors.is_empty();
+            for tag_idx in errors.iter().filter_map(|err| err.tag).unique() {
+                let tag = origins.tags[tag_idx];
+                let mut diag = match tag {
+                    Tag::Panic(span) => errors::PanicError { span }.into_diagnostic(sess),
+                    Tag::Body(span) => errors::RefineError { span }.into_diagnostic(sess),
+                    tag => errors::ObligationError {
+                        span: tag.span(),
+                        reason: obligation_reason(global_env, tag),
+                    }
+                    .into_diagnostic(sess),
+                };
+                if let Some(note) = counterexample(constraint, &solution, origins, tag_idx) {
+                    diag.note(&note);
+                }
+                diag.emit();
+            }
+            untagged |= errors.iter().any(|err| err.tag.is_none());
+            if untagged {
+                for span in spans {
+                    sess.emit_err(untagged_error(*span));
+                }
+            }
+            Err(ErrorReported)
+        }
+        Ok((FixpointResult::Crash(info), _)) => {
+            let msg = format!("fixpoint crashed: {}", info);
+            for span in spans {
+                global_env.tcx.sess.emit_err(errors::FixpointError {
+                    span: *span,
+                    msg: msg.clone(),
+                });
+            }
+            Err(ErrorReported)
+        }
+        Err(err) => {
+            let sess = global_env.tcx.sess;
+            let msg = err.to_string();
+            for span in spans {
+                let mut diag = errors::FixpointError {
+                    span: *span,
+                    msg: msg.clone(),
+                }
+                .into_diagnostic(sess);
+                if let FixpointError::Crash { stderr } = &err {
+                    if !stderr.is_empty() {
+                        diag.note(&format!("the solver's error output:\n{}", stderr));
+                    }
+                }
+                diag.emit();
+            }
+            Err(ErrorReported)
+        }
+    }
+}
+
+/// Describes values of the user's variables under which the head tagged `tag` fails, e.g.,
+/// "fails when `start = 3`, `len = 3`". Counterexamples are best effort: if the solver isn't
+/// available or the model doesn't mention any named variable there's nothing to report.
+fn counterexample(
+    constraint: &Fixpoint,
+    solution: &Solution,
+    origins: &Origins,
+    tag: TagIdx,
+) -> Option<String> {
+    let model = constraint.counterexample(tag, solution).ok()??;
+    // A variable that is reassigned is refined by a different name after each assignment. We keep
+    // the last one, which is the value at the point of failure.
+    let values = model
+        .iter()
+        .filter_map(|(name, value)| Some((*name, origins.var_names.get(name)?, value)))
+        .sorted_by_key(|(name, ..)| *name)
+        .rev()
+        .unique_by(|(_, source, _)| *source)
+        .sorted_by_key(|(name, ..)| *name)
+        .collect_vec();
+    if values.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "fails when {}",
+        values
+            .iter()
+            .format_with(", ", |(_, source, value), f| f(&format_args!(
+                "`{} = {}`",
+                source, value
+            )))
+    ))
+}
+
+fn obligation_reason(global_env: &GlobalEnv, tag: Tag) -> String {
+    let callee = |callee: Option<DefId>| match callee {
+        Some(def_id) => format!("precondition of `{}`", global_env.tcx.def_path_str(def_id)),
+        None => "precondition".to_string(),
+    };
+    match tag {
+        Tag::Call(_, def_id) => format!("{} might not hold", callee(def_id)),
+        Tag::Arg(_, def_id, i) => format!("{} argument {} might not hold", callee(def_id), i + 1),
+        Tag::Ret(_) => "postcondition might not hold".to_string(),
+        Tag::Div(_) => "possible division by zero".to_string(),
+        Tag::Assign(_) => "assigned value might not have the type of the location".to_string(),
+        Tag::Goto(_) => "environment might not satisfy the invariant of the target".to_string(),
+        Tag::Unreachable(_) => "this code might be reachable".to_string(),
+        Tag::Panic(_) | Tag::Body(_) => unreachable!("reported with its own error"),
+    }
+}
+
+/// Writes `constraint` to the log directory. Failing to do so doesn't prevent checking, so it is
+/// only a warning.
+fn dump_constraint(tcx: TyCtxt, name: &str, constraint: &ConstraintBuilder) {
     let dir = CONFIG.log_dir.join("horn");
-    fs::create_dir_all(&dir)?;
-    let mut file = fs::File::create(dir.join(format!("{}", tcx.def_path_str(def_id))))?;
-    write!(file, "{:?}", constraint)
+    let res = fs::create_dir_all(&dir)
+        .and_then(|_| fs::File::create(dir.join(name)))
+        .and_then(|mut file| write!(file, "{:?}", constraint));
+    if let Err(err) = res {
+        tcx.sess.warn(&format!(
+            "failed to dump the constraint of `{}`: {}",
+            name, err
+        ));
+    }
 }
 
 mod errors {
@@ -88,4 +388,97 @@ mod errors {
         #[label = "this function is unsafe"]
         pub span: Span,
     }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct ImplMethodError {
+        #[message = "method spec does not refine the spec of the trait"]
+        #[label = "this spec is incompatible with the trait"]
+        pub span: Span,
+        #[label = "trait method defined here"]
+        pub trait_span: Span,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct ObligationError {
+        #[message = "refinement type error"]
+        #[label = "{reason}"]
+        pub span: Span,
+        pub reason: String,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct PanicError {
+        #[message = "possible panic"]
+        #[label = "this may panic"]
+        pub span: Span,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct UseOfUninit {
+        #[message = "use of a moved or uninitialized value"]
+        #[label = "value used here"]
+        pub span: Span,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct MoveBehindRef {
+        #[message = "cannot move out of a place behind a reference"]
+        pub span: Span,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct MutationBehindShrRef {
+        #[message = "cannot mutate a place behind a shared reference"]
+        pub span: Span,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct IncompatibleTypes {
+        #[message = "incompatible types `{ty1}` and `{ty2}`"]
+        #[label = "unsupported by the type checker"]
+        pub span: Span,
+        pub ty1: String,
+        pub ty2: String,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct UnsupportedOperands {
+        #[message = "unsupported operand types for `{op}`: {tys}"]
+        pub span: Span,
+        pub op: String,
+        pub tys: String,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct CannotInferLocations {
+        #[message = "cannot infer the locations required by this call"]
+        pub span: Span,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct RefinedParamInstance {
+        #[message = "cannot instantiate the refined type parameter `{param}` with `{ty}`"]
+        #[label = "only type parameters instantiated with integers can be refined"]
+        pub span: Span,
+        pub param: String,
+        pub ty: String,
+    }
+
+    #[derive(SessionDiagnostic)]
+    #[error = "LIQUID"]
+    pub struct FixpointError {
+        #[message = "failed to run the solver: {msg}"]
+        pub span: Span,
+        pub msg: String,
+    }
 }
@@ -1,10 +1,14 @@
 use crate::{constraint_builder::Cursor, ty, type_env::TypeEnv};
+use itertools::Itertools;
 use liquid_rust_core::ty as core;
 use rustc_hash::FxHashMap;
+use rustc_span::{Span, Symbol, DUMMY_SP};
 
 pub struct Subst {
     locations: FxHashMap<core::Name, ty::Loc>,
     exprs: FxHashMap<core::Name, ty::Expr>,
+    /// The predicates abstract refinements are instantiated with.
+    preds: FxHashMap<core::Name, ty::Pred>,
     types: Vec<ty::Ty>,
 }
 
@@ -14,6 +18,7 @@ impl Subst {
     pub fn with_empty_type_substs() -> Self {
         Self {
             exprs: FxHashMap::default(),
+            preds: FxHashMap::default(),
             locations: FxHashMap::default(),
             types: vec![],
         }
@@ -29,79 +34,197 @@ impl Subst {
         subst
     }
 
+    /// A substitution for type parameters whose instances are already lowered, e.g., the type
+    /// arguments of a struct.
+    pub fn with_lowered_type_substs(types: impl IntoIterator<Item = ty::Ty>) -> Self {
+        let mut subst = Subst::with_empty_type_substs();
+        subst.types.extend(types);
+        subst
+    }
+
+    /// The type instantiating the type parameter `param`, if any.
+    pub fn lookup_type(&self, param: core::ParamTy) -> Option<&ty::Ty> {
+        self.types.get(param.index as usize)
+    }
+
     pub fn insert_expr(&mut self, name: core::Name, expr: impl Into<ty::Expr>) {
         self.exprs.insert(name, expr.into());
     }
 
+    pub fn insert_pred(&mut self, name: core::Name, pred: ty::Pred) {
+        self.preds.insert(name, pred);
+    }
+
     pub fn insert_loc(&mut self, name: core::Name, region: impl Into<ty::Loc>) {
         self.locations.insert(name, region.into());
     }
 
+    /// Infers the parameters of `fn_sig` from the types of the arguments of a call and of the
+    /// locations it requires. A parameter is solved from an index of the form `n`, `n + e` or
+    /// `n - e` whose other variables are already known. When several indices determine the same
+    /// parameter the first one is used and the others become equality obligations when checking
+    /// the arguments against the formals. A parameter that cannot be solved is instantiated with a
+    /// fresh name. If the parameter has a predicate, the name is constrained by it and the call
+    /// must be valid for every value satisfying it. Otherwise, the parameter can be any value and
+    /// it is instantiated existentially, with a kvar: the call is checked for the values fixpoint
+    /// picks for it. fixpoint gives a kvar the strongest solution it finds, so one that is only
+    /// assumed would be `false` and make the rest of the body hold vacuously. The kvar is thus
+    /// required to hold for some value, `0` or `false`, which the arguments must then accept.
     pub fn infer_from_fn_call(
         &mut self,
         env: &TypeEnv,
+        cursor: &mut Cursor,
         actuals: &[ty::Ty],
         fn_sig: &core::FnSig,
     ) -> Result<(), InferenceError> {
         assert!(actuals.len() == fn_sig.args.len());
 
+        let mut indices = vec![];
         for (actual, formal) in actuals.iter().zip(fn_sig.args.iter()) {
-            self.infer_from_tys(actual.clone(), formal);
+            self.infer_from_tys(actual, formal, &mut indices)?;
         }
 
         for (loc, required) in &fn_sig.requires {
-            let actual = env.lookup_loc(self.lower_loc(*loc).unwrap()).unwrap();
-            self.infer_from_tys(actual, required);
+            if let Some(loc) = self.lower_loc(*loc) {
+                let actual = env.lookup_loc(loc).ok_or(InferenceError)?;
+                self.infer_from_tys(&actual, required, &mut indices)?;
+            }
         }
 
-        self.check_inference(fn_sig)
-    }
+        while let Some(i) = indices
+            .iter()
+            .position(|(actual, formal)| self.solve(actual, formal).is_some())
+        {
+            let (actual, formal) = indices.swap_remove(i);
+            let (name, e) = self.solve(&actual, formal).unwrap();
+            self.exprs.insert(name, e);
+        }
 
-    fn check_inference(&self, fn_sig: &core::FnSig) -> Result<(), InferenceError> {
-        for param in &fn_sig.params {
+        // Abstract refinements are not inferred from the arguments but instantiated with kvars.
+        for param in fn_sig
+            .params
+            .iter()
+            .filter(|param| param.sort != ty::Sort::Pred)
+        {
             if !self.exprs.contains_key(&param.name.name) {
-                return Err(InferenceError);
+                let fresh = cursor.fresh_name();
+                self.insert_expr(param.name.name, ty::Var::Free(fresh));
+                let mut pred = self.lower_pred(cursor, &param.pred, param.sort);
+                if pred.is_true() {
+                    pred = cursor.fresh_kvar(param.sort);
+                    let witness = match param.sort {
+                        ty::Sort::Bool => ty::Expr::ff(),
+                        _ => ty::Expr::zero(),
+                    };
+                    cursor.snapshot().push_head(pred.subst_bound_vars(witness));
+                }
+                let var = ty::ExprKind::Var(ty::Var::Free(fresh)).intern();
+                cursor.push_forall(fresh, param.sort, pred.subst_bound_vars(var));
             }
         }
 
-        for (region, _) in &fn_sig.requires {
-            if !self.locations.contains_key(region) {
+        for (loc, _) in &fn_sig.requires {
+            if !self.locations.contains_key(loc) {
                 return Err(InferenceError);
             }
         }
         Ok(())
     }
 
-    fn infer_from_tys(&mut self, ty1: ty::Ty, ty2: &core::Ty) {
+    /// Collects the pairs of actual and formal indices in `ty1` and `ty2`, and infers the
+    /// locations of mutable references.
+    fn infer_from_tys<'a>(
+        &mut self,
+        ty1: &ty::Ty,
+        ty2: &'a core::Ty,
+        indices: &mut Vec<(ty::Expr, &'a core::Expr)>,
+    ) -> Result<(), InferenceError> {
         match (ty1.kind(), ty2) {
+            (ty::TyKind::Refine(bty1, e), core::Ty::Refine(bty2, formal)) => {
+                indices.push((e.clone(), formal));
+                self.infer_from_btys(bty1, bty2, indices)?;
+            }
             (
-                ty::TyKind::Refine(_bty1, e),
-                core::Ty::Refine(
-                    _bty2,
-                    core::Expr {
-                        kind: core::ExprKind::Var(core::Var::Free(name), symbol, ..),
-                        ..
-                    },
-                ),
+                ty::TyKind::Refine(bty1, _) | ty::TyKind::Exists(bty1, _),
+                core::Ty::Exists(bty2, _),
             ) => {
-                // debug_assert!(bty1 == bty2);
-                match self.exprs.insert(*name, e.clone()) {
-                    Some(old_e) if &old_e != e => {
-                        todo!("ambiguous instantiation for parameter `{}`", symbol)
-                    }
-                    _ => {}
+                self.infer_from_btys(bty1, bty2, indices)?;
+            }
+            (ty::TyKind::Tuple(tys1), core::Ty::Tuple(tys2)) => {
+                for (ty1, ty2) in tys1.iter().zip(tys2) {
+                    self.infer_from_tys(ty1, ty2, indices)?;
                 }
             }
+            (ty::TyKind::Ref(ty1), core::Ty::Ref(ty2)) => {
+                self.infer_from_tys(ty1, ty2, indices)?;
+            }
             (ty::TyKind::StrgRef(loc1), core::Ty::MutRef(loc2)) => {
                 match self.locations.insert(*loc2, *loc1) {
-                    Some(old_region) if &old_region != loc1 => {
-                        todo!("ambiguous instantiation for region parameter`",);
-                    }
+                    Some(old_loc) if &old_loc != loc1 => return Err(InferenceError),
                     _ => {}
                 }
             }
             _ => {}
         }
+        Ok(())
+    }
+
+    fn infer_from_btys<'a>(
+        &mut self,
+        bty1: &ty::BaseTy,
+        bty2: &'a core::BaseTy,
+        indices: &mut Vec<(ty::Expr, &'a core::Expr)>,
+    ) -> Result<(), InferenceError> {
+        match (bty1, bty2) {
+            (ty::BaseTy::Adt(_, substs1), core::BaseTy::Adt(_, substs2)) => {
+                for (ty1, ty2) in substs1.iter().zip(substs2) {
+                    self.infer_from_tys(ty1, ty2, indices)?;
+                }
+            }
+            (ty::BaseTy::Array(ty1), core::BaseTy::Array(ty2))
+            | (ty::BaseTy::Slice(ty1), core::BaseTy::Slice(ty2)) => {
+                self.infer_from_tys(ty1, ty2, indices)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Solves the single unknown parameter in `formal` such that it is equal to `actual`.
+    fn solve(&self, actual: &ty::Expr, formal: &core::Expr) -> Option<(core::Name, ty::Expr)> {
+        match &formal.kind {
+            core::ExprKind::Var(core::Var::Free(name), ..) if !self.exprs.contains_key(name) => {
+                Some((*name, actual.clone()))
+            }
+            core::ExprKind::BinaryOp(op @ (core::BinOp::Add | core::BinOp::Sub), e1, e2) => {
+                match (self.is_known(e1), self.is_known(e2), op) {
+                    (false, true, core::BinOp::Add) => {
+                        self.solve(&bin_op(ty::BinOp::Sub, actual, &self.lower_expr(e2)), e1)
+                    }
+                    (false, true, core::BinOp::Sub) => {
+                        self.solve(&bin_op(ty::BinOp::Add, actual, &self.lower_expr(e2)), e1)
+                    }
+                    (true, false, core::BinOp::Add) => {
+                        self.solve(&bin_op(ty::BinOp::Sub, actual, &self.lower_expr(e1)), e2)
+                    }
+                    (true, false, core::BinOp::Sub) => {
+                        self.solve(&bin_op(ty::BinOp::Sub, &self.lower_expr(e1), actual), e2)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether all the parameters in `e` are already instantiated.
+    fn is_known(&self, e: &core::Expr) -> bool {
+        match &e.kind {
+            core::ExprKind::Var(core::Var::Free(name), ..) => self.exprs.contains_key(name),
+            core::ExprKind::Var(core::Var::Bound, ..) | core::ExprKind::Literal(_) => true,
+            core::ExprKind::BinaryOp(_, e1, e2) => self.is_known(e1) && self.is_known(e2),
+            core::ExprKind::App(..) => false,
+        }
     }
 
     pub fn lower_loc(&self, name: core::Name) -> Option<ty::Loc> {
@@ -110,22 +233,65 @@ impl Subst {
 
     pub fn lower_ty(&mut self, cursor: &mut Cursor, ty: &core::Ty) -> ty::Ty {
         match ty {
+            // Explicit refinements of type parameters are on integers and only carried over to
+            // instances of sort `int`. Calls instantiating them with other sorts are rejected by
+            // `check_refined_params` in the checker.
+            core::Ty::Refine(core::BaseTy::Param(param), e) => match self.lookup_type(*param) {
+                Some(ty) if instance_sort(ty) == ty::Sort::Int => {
+                    let pred = ty::ExprKind::BinaryOp(
+                        ty::BinOp::Eq,
+                        ty::Var::Bound.into(),
+                        self.lower_expr(e),
+                    )
+                    .intern();
+                    refine_instance(ty, pred.into())
+                }
+                Some(ty) => ty.clone(),
+                None => ty::TyKind::Refine(ty::BaseTy::Param(*param), self.lower_expr(e)).intern(),
+            },
+            core::Ty::Exists(core::BaseTy::Param(param), pred) => {
+                match self.lookup_type(*param).cloned() {
+                    // Predicates to be inferred take the sort of the instance.
+                    Some(ty) if matches!(pred, core::Pred::Infer(_)) => {
+                        let pred = self.lower_pred(cursor, pred, instance_sort(&ty));
+                        refine_instance(&ty, pred)
+                    }
+                    Some(ty) if instance_sort(&ty) == ty::Sort::Int => {
+                        let pred = self.lower_pred(cursor, pred, ty::Sort::Int);
+                        refine_instance(&ty, pred)
+                    }
+                    Some(ty) => ty,
+                    None => {
+                        let pred = self.lower_pred(cursor, pred, ty::Sort::Int);
+                        ty::TyKind::Exists(ty::BaseTy::Param(*param), pred).intern()
+                    }
+                }
+            }
             core::Ty::Refine(bty, e) => {
                 ty::TyKind::Refine(self.lower_base_ty(cursor, bty), self.lower_expr(e)).intern()
             }
             core::Ty::Exists(bty, pred) => {
-                let pred = match pred {
-                    core::Pred::Infer => cursor.fresh_kvar(bty.sort()),
-                    core::Pred::Expr(e) => ty::Pred::Expr(self.lower_expr(e)),
-                };
+                let pred = self.lower_pred(cursor, pred, bty.sort());
                 ty::TyKind::Exists(self.lower_base_ty(cursor, bty), pred).intern()
             }
+            core::Ty::Tuple(tys) => {
+                ty::TyKind::tuple(tys.iter().map(|ty| self.lower_ty(cursor, ty)))
+            }
             core::Ty::MutRef(loc) => ty::TyKind::StrgRef(self.locations[loc]).intern(),
             core::Ty::Param(param) => self
                 .types
                 .get(param.index as usize)
                 .cloned()
                 .unwrap_or_else(|| ty::TyKind::Param(*param).intern()),
+            core::Ty::Never => ty::TyKind::Never.intern(),
+            core::Ty::Ref(ty) => ty::TyKind::Ref(self.lower_ty(cursor, ty)).intern(),
+            core::Ty::Fn(args, ret) => {
+                let args = args
+                    .iter()
+                    .map(|ty| self.lower_ty(cursor, ty))
+                    .collect_vec();
+                ty::TyKind::fn_ty(args, self.lower_ty(cursor, ret))
+            }
         }
     }
 
@@ -138,6 +304,41 @@ impl Subst {
                 let substs = substs.iter().map(|ty| self.lower_ty(cursor, ty)).collect();
                 ty::BaseTy::Adt(*did, substs)
             }
+            core::BaseTy::Array(ty) => ty::BaseTy::Array(self.lower_ty(cursor, ty)),
+            core::BaseTy::Slice(ty) => ty::BaseTy::Slice(self.lower_ty(cursor, ty)),
+            core::BaseTy::Param(param) => ty::BaseTy::Param(*param),
+        }
+    }
+
+    /// Lowers a predicate on a value of sort `sort`.
+    pub fn lower_pred(&self, cursor: &mut Cursor, pred: &core::Pred, sort: ty::Sort) -> ty::Pred {
+        match pred {
+            core::Pred::Infer(_) => cursor.fresh_kvar(sort),
+            core::Pred::KVar(kvid, args) => {
+                ty::Pred::kvar(*kvid, args.iter().map(|arg| self.lower_expr(arg)))
+            }
+            core::Pred::Expr(e) => self.lower_conjuncts(e),
+        }
+    }
+
+    /// Lowers a predicate whose conjuncts at the top level may be applications of abstract
+    /// refinements, which are replaced by the predicates they are instantiated with.
+    fn lower_conjuncts(&self, e: &core::Expr) -> ty::Pred {
+        match &e.kind {
+            core::ExprKind::BinaryOp(core::BinOp::And, e1, e2) => {
+                match (self.lower_conjuncts(e1), self.lower_conjuncts(e2)) {
+                    (ty::Pred::Expr(e1), ty::Pred::Expr(e2)) => {
+                        ty::ExprKind::BinaryOp(ty::BinOp::And, e1, e2)
+                            .intern()
+                            .into()
+                    }
+                    (p1, p2) => ty::Pred::and([p1, p2]),
+                }
+            }
+            core::ExprKind::App(core::Var::Free(func), _, args) => {
+                self.preds[func].subst_bound_vars(self.lower_expr(&args[0]))
+            }
+            _ => ty::Pred::Expr(self.lower_expr(e)),
         }
     }
 
@@ -149,10 +350,13 @@ impl Subst {
                 ty::ExprKind::BinaryOp(lower_bin_op(*op), self.lower_expr(e1), self.lower_expr(e2))
                     .intern()
             }
+            core::ExprKind::App(..) => {
+                unreachable!("abstract refinements are only applied at the top level of predicates")
+            }
         }
     }
 
-    fn lower_var(&self, var: core::Var) -> ty::Expr {
+    pub fn lower_var(&self, var: core::Var) -> ty::Expr {
         match var {
             core::Var::Bound => ty::Var::Bound.into(),
             core::Var::Free(name) => self.exprs[&name].clone(),
@@ -167,6 +371,158 @@ impl Subst {
     }
 }
 
+/// Transfers the refinement `pred` of a type parameter to the type `ty` the parameter is
+/// instantiated with. Types without an index, e.g., tuples, cannot carry the refinement, which is
+/// dropped.
+fn refine_instance(ty: &ty::Ty, pred: ty::Pred) -> ty::Ty {
+    match ty.kind() {
+        ty::TyKind::Refine(bty, e) => {
+            let eq = ty::ExprKind::BinaryOp(ty::BinOp::Eq, ty::Var::Bound.into(), e.clone());
+            ty::TyKind::Exists(bty.clone(), ty::Pred::and([eq.intern().into(), pred])).intern()
+        }
+        ty::TyKind::Exists(bty, p) => {
+            ty::TyKind::Exists(bty.clone(), ty::Pred::and([p.clone(), pred])).intern()
+        }
+        ty::TyKind::Param(param) => ty::TyKind::Exists(ty::BaseTy::Param(*param), pred).intern(),
+        _ => ty.clone(),
+    }
+}
+
+/// The sort of the values of a type instantiating a type parameter. Instances without an index,
+/// e.g., tuples, are not refined, so their sort doesn't matter.
+pub fn instance_sort(ty: &ty::Ty) -> ty::Sort {
+    match ty.kind() {
+        ty::TyKind::Refine(bty, _) | ty::TyKind::Exists(bty, _) => bty.sort(),
+        _ => ty::Sort::Int,
+    }
+}
+
+/// Replaces the predicates to be inferred in `fn_sig` with kvars, so that they are shared by all
+/// the uses of the signature. The kvar of a parameter is applied to the parameters before it and
+/// the ones in types to all the parameters. Returns the kvars of the holes written by the user
+/// together with their span.
+pub fn instantiate_sig_kvars(
+    cursor: &mut Cursor,
+    fn_sig: &mut core::FnSig,
+) -> Vec<(Span, core::Pred)> {
+    let cursor = &mut cursor.snapshot();
+    let mut names = FxHashMap::default();
+    let mut holes = vec![];
+    for param in fn_sig
+        .params
+        .iter_mut()
+        .filter(|param| param.sort != ty::Sort::Pred)
+    {
+        if let core::Pred::Infer(_) = param.pred {
+            let kvar = cursor.fresh_kvar(param.sort);
+            param.pred = kvar_to_core(kvar, param.name.source_info, &names);
+        }
+        let fresh = cursor.fresh_name();
+        names.insert(fresh, param.name);
+        cursor.push_forall(fresh, param.sort, ty::Expr::tt());
+    }
+    let tys = fn_sig
+        .requires
+        .iter_mut()
+        .chain(&mut fn_sig.ensures)
+        .map(|(_, ty)| ty)
+        .chain(&mut fn_sig.args)
+        .chain([&mut fn_sig.ret]);
+    for ty in tys {
+        instantiate_ty_kvars(cursor, ty, &names, &mut holes);
+    }
+    holes
+}
+
+fn instantiate_ty_kvars(
+    cursor: &mut Cursor,
+    ty: &mut core::Ty,
+    names: &FxHashMap<ty::Name, core::Ident>,
+    holes: &mut Vec<(Span, core::Pred)>,
+) {
+    match ty {
+        core::Ty::Refine(bty, _) => instantiate_bty_kvars(cursor, bty, names, holes),
+        core::Ty::Exists(bty, pred) => {
+            instantiate_bty_kvars(cursor, bty, names, holes);
+            if let core::Pred::Infer(span) = *pred {
+                let kvar = cursor.fresh_kvar(bty.sort());
+                let bound = (DUMMY_SP, Symbol::intern("v"));
+                if let Some(span) = span {
+                    holes.push((span, kvar_to_core(kvar.clone(), bound, names)));
+                }
+                *pred = kvar_to_core(kvar, bound, names);
+            }
+        }
+        core::Ty::Tuple(tys) => {
+            for ty in tys {
+                instantiate_ty_kvars(cursor, ty, names, holes);
+            }
+        }
+        core::Ty::Ref(ty) => instantiate_ty_kvars(cursor, ty, names, holes),
+        core::Ty::Fn(args, ret) => {
+            for ty in args.iter_mut().chain([&mut **ret]) {
+                instantiate_ty_kvars(cursor, ty, names, holes);
+            }
+        }
+        core::Ty::MutRef(_) | core::Ty::Param(_) | core::Ty::Never => {}
+    }
+}
+
+fn instantiate_bty_kvars(
+    cursor: &mut Cursor,
+    bty: &mut core::BaseTy,
+    names: &FxHashMap<ty::Name, core::Ident>,
+    holes: &mut Vec<(Span, core::Pred)>,
+) {
+    match bty {
+        core::BaseTy::Adt(_, substs) => {
+            for ty in substs {
+                instantiate_ty_kvars(cursor, ty, names, holes);
+            }
+        }
+        core::BaseTy::Array(ty) | core::BaseTy::Slice(ty) => {
+            instantiate_ty_kvars(cursor, ty, names, holes)
+        }
+        core::BaseTy::Int(_)
+        | core::BaseTy::Uint(_)
+        | core::BaseTy::Bool
+        | core::BaseTy::Param(_) => {}
+    }
+}
+
+fn kvar_to_core(
+    pred: ty::Pred,
+    (bound_span, bound_symbol): (Span, Symbol),
+    names: &FxHashMap<ty::Name, core::Ident>,
+) -> core::Pred {
+    let (kvid, args) = match pred {
+        ty::Pred::KVar(kvid, args) => (kvid, args),
+        ty::Pred::Expr(_) | ty::Pred::And(_) => unreachable!("expected a kvar"),
+    };
+    let args = args
+        .iter()
+        .map(|arg| {
+            let kind = match arg.kind() {
+                ty::ExprKind::Var(ty::Var::Bound) => {
+                    core::ExprKind::Var(core::Var::Bound, bound_symbol, bound_span)
+                }
+                ty::ExprKind::Var(ty::Var::Free(name)) => {
+                    let ident = names[name];
+                    let (span, symbol) = ident.source_info;
+                    core::ExprKind::Var(core::Var::Free(ident.name), symbol, span)
+                }
+                _ => unreachable!("unexpected kvar argument"),
+            };
+            core::Expr { kind, span: None }
+        })
+        .collect();
+    core::Pred::KVar(kvid, args)
+}
+
+fn bin_op(op: ty::BinOp, e1: &ty::Expr, e2: &ty::Expr) -> ty::Expr {
+    ty::ExprKind::BinaryOp(op, e1.clone(), e2.clone()).intern()
+}
+
 fn lower_bin_op(op: core::BinOp) -> ty::BinOp {
     match op {
         core::BinOp::Iff => ty::BinOp::Iff,
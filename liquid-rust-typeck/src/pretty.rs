@@ -1,7 +1,12 @@
-use std::{cell::RefCell, fmt};
+use std::{cell::RefCell, fmt, hash::Hash};
 
+use itertools::Itertools;
+use liquid_rust_core::ir::Local;
+use liquid_rust_fixpoint::{Name, Solution};
+use rustc_hash::FxHashMap;
 use rustc_hir::def_id::DefId;
 use rustc_middle::ty::TyCtxt;
+use rustc_span::Symbol;
 
 use crate::intern::{Internable, Interned};
 
@@ -100,15 +105,26 @@ pub enum Visibility {
     Truncate(usize),
 }
 
-pub struct PPrintCx<'tcx> {
+pub struct PPrintCx<'a, 'tcx> {
     pub tcx: TyCtxt<'tcx>,
     pub kvar_args: Visibility,
     pub fully_qualified_paths: bool,
+    /// Kvars with a solution are printed as their solution.
+    pub solution: Option<&'a Solution>,
+    pub names: Option<&'a NameMap>,
+}
+
+/// Source names to print in place of the names generated by the checker and of MIR locals. A
+/// source name shared by several names or locals, e.g., by the values of a variable before and
+/// after it is reassigned, gets a numeric suffix to tell them apart.
+pub struct NameMap {
+    vars: FxHashMap<Name, String>,
+    locals: FxHashMap<Local, String>,
 }
 
 pub struct WithCx<'a, 'tcx, T> {
     data: T,
-    cx: &'a PPrintCx<'tcx>,
+    cx: &'a PPrintCx<'a, 'tcx>,
 }
 
 pub struct Join<'a, I> {
@@ -133,12 +149,14 @@ impl<'a, I> Join<'a, I> {
     }
 }
 
-impl PPrintCx<'_> {
+impl<'a> PPrintCx<'a, '_> {
     pub fn default(tcx: TyCtxt) -> PPrintCx {
         PPrintCx {
             tcx,
             kvar_args: Visibility::Show,
             fully_qualified_paths: false,
+            solution: None,
+            names: None,
         }
     }
 
@@ -152,10 +170,69 @@ impl PPrintCx<'_> {
             ..self
         }
     }
+
+    pub fn solution(self, solution: &'a Solution) -> Self {
+        Self {
+            solution: Some(solution),
+            ..self
+        }
+    }
+
+    pub fn names(self, names: &'a NameMap) -> Self {
+        Self {
+            names: Some(names),
+            ..self
+        }
+    }
+}
+
+impl NameMap {
+    /// Takes the source expressions whose values are given by names, see
+    /// [`Cursor::name_var`](crate::constraint_builder::Cursor::name_var), and the names of the
+    /// user variables stored in locals.
+    pub fn new(vars: &FxHashMap<Name, String>, locals: &FxHashMap<Local, Symbol>) -> NameMap {
+        NameMap {
+            vars: disambiguate(vars.iter().map(|(name, source)| (*name, source.clone()))),
+            locals: disambiguate(
+                locals
+                    .iter()
+                    .map(|(local, symbol)| (*local, symbol.to_string())),
+            ),
+        }
+    }
+
+    pub fn var(&self, name: Name) -> Option<&str> {
+        self.vars.get(&name).map(String::as_str)
+    }
+
+    pub fn local(&self, local: Local) -> Option<&str> {
+        self.locals.get(&local).map(String::as_str)
+    }
+}
+
+/// Numbers the keys sharing a source name in order, e.g., `i#1` and `i#2`.
+fn disambiguate<K: Copy + Ord + Hash>(
+    names: impl Iterator<Item = (K, String)>,
+) -> FxHashMap<K, String> {
+    let names = names.sorted_by_key(|(key, _)| *key).collect_vec();
+    let counts = names.iter().map(|(_, source)| source).counts();
+    let mut next: FxHashMap<&String, usize> = FxHashMap::default();
+    names
+        .iter()
+        .map(|(key, source)| {
+            if counts[source] == 1 {
+                (*key, source.clone())
+            } else {
+                let i = next.entry(source).or_insert(0);
+                *i += 1;
+                (*key, format!("{}#{}", source, i))
+            }
+        })
+        .collect()
 }
 
 impl<'a, 'tcx, T> WithCx<'a, 'tcx, T> {
-    pub fn new(cx: &'a PPrintCx<'tcx>, data: T) -> Self {
+    pub fn new(cx: &'a PPrintCx<'a, 'tcx>, data: T) -> Self {
         Self { data, cx }
     }
 }
@@ -220,12 +297,43 @@ impl<T: Pretty> fmt::Debug for WithCx<'_, '_, T> {
 
 impl Pretty for DefId {
     fn fmt(&self, cx: &PPrintCx, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let path = cx.tcx.def_path(*self);
         if cx.fully_qualified_paths {
-            let krate = cx.tcx.crate_name(self.krate);
-            write!(f, "{krate}{}", path.to_string_no_crate_verbose())
+            write!(f, "{}", cx.tcx.def_path_str(*self))
         } else {
-            write!(f, "{}", path.data.last().unwrap())
+            write!(f, "{}", cx.tcx.def_path(*self).data.last().unwrap())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disambiguate_shared_names() {
+        let names = disambiguate(
+            [(3, "i"), (0, "x"), (1, "i"), (2, "n"), (5, "i")]
+                .into_iter()
+                .map(|(key, source)| (key, source.to_string())),
+        );
+        let names = names.into_iter().sorted().collect_vec();
+        assert_eq!(
+            names,
+            [(0, "x"), (1, "i#1"), (2, "n"), (3, "i#2"), (5, "i#3")]
+                .map(|(key, source)| (key, source.to_string()))
+        );
+    }
+
+    #[test]
+    fn name_map_vars() {
+        let vars = [(4, "len"), (7, "v[i]"), (9, "len")]
+            .into_iter()
+            .map(|(name, source)| (Name::from_usize(name), source.to_string()))
+            .collect();
+        let names = NameMap::new(&vars, &FxHashMap::default());
+        assert_eq!(names.var(Name::from_usize(4)), Some("len#1"));
+        assert_eq!(names.var(Name::from_usize(7)), Some("v[i]"));
+        assert_eq!(names.var(Name::from_usize(9)), Some("len#2"));
+        assert_eq!(names.var(Name::from_usize(5)), None);
+    }
+}
@@ -1,7 +1,9 @@
 use std::{fmt, lazy::SyncOnceCell};
 
+use itertools::Itertools;
 use liquid_rust_core::ir::Local;
 pub use liquid_rust_core::ty::ParamTy;
+use liquid_rust_fixpoint as fixpoint;
 pub use liquid_rust_fixpoint::{BinOp, Constant, KVid, Name, Sort, UnOp};
 use rustc_hir::def_id::DefId;
 pub use rustc_middle::ty::{IntTy, UintTy};
@@ -19,10 +21,17 @@ pub struct TyS {
 pub enum TyKind {
     Refine(BaseTy, Expr),
     Exists(BaseTy, Pred),
+    Tuple(Tys),
+    /// An owned struct unfolded into the types of its fields. The base type is the (always
+    /// [`BaseTy::Adt`]) type of the struct when folded.
+    Struct(BaseTy, Tys),
     Uninit,
     StrgRef(Loc),
     Ref(Ty),
     Param(ParamTy),
+    Never,
+    /// A function type with the types of its arguments and its return type.
+    Fn(Tys, Ty),
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -37,15 +46,24 @@ pub enum BaseTy {
     Uint(UintTy),
     Bool,
     Adt(DefId, Substs),
+    /// An array refined by its length.
+    Array(Ty),
+    /// A slice refined by its length.
+    Slice(Ty),
+    /// A type parameter refined by an integer index.
+    Param(ParamTy),
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Substs(Interned<Vec<Ty>>);
 
+pub type Tys = Interned<Vec<Ty>>;
+
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Pred {
     KVar(KVid, Interned<Vec<Expr>>),
     Expr(Expr),
+    And(Interned<Vec<Pred>>),
 }
 
 pub type Expr = Interned<ExprS>;
@@ -61,6 +79,8 @@ pub enum ExprKind {
     Constant(Constant),
     BinaryOp(BinOp, Expr, Expr),
     UnaryOp(UnOp, Expr),
+    /// The application of an abstract refinement, declared as an uninterpreted constant.
+    App(Name, Interned<Vec<Expr>>),
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -73,6 +93,19 @@ impl TyKind {
     pub fn intern(self) -> Ty {
         Interned::new(TyS { kind: self })
     }
+
+    pub fn tuple(tys: impl IntoIterator<Item = Ty>) -> Ty {
+        TyKind::Tuple(Interned::new(tys.into_iter().collect())).intern()
+    }
+
+    pub fn unfolded(bty: BaseTy, fields: impl IntoIterator<Item = Ty>) -> Ty {
+        debug_assert!(matches!(bty, BaseTy::Adt(..)));
+        TyKind::Struct(bty, Interned::new(fields.into_iter().collect())).intern()
+    }
+
+    pub fn fn_ty(args: impl IntoIterator<Item = Ty>, ret: Ty) -> Ty {
+        TyKind::Fn(Interned::new(args.into_iter().collect()), ret).intern()
+    }
 }
 
 impl TyS {
@@ -89,6 +122,15 @@ impl TyS {
         match self.kind() {
             TyKind::Ref(ty) => ty.walk(f),
             TyKind::Refine(bty, _) | TyKind::Exists(bty, _) => bty.walk(f),
+            TyKind::Tuple(tys) => tys.iter().for_each(|ty| ty.walk(f)),
+            TyKind::Struct(bty, fields) => {
+                bty.walk(f);
+                fields.iter().for_each(|ty| ty.walk(f));
+            }
+            TyKind::Fn(args, ret) => {
+                args.iter().for_each(|ty| ty.walk(f));
+                ret.walk(f);
+            }
             _ => {}
         }
     }
@@ -100,7 +142,7 @@ impl BaseTy {
             BaseTy::Int(_) => Sort::Int,
             BaseTy::Uint(_) => Sort::Int,
             BaseTy::Bool => Sort::Bool,
-            BaseTy::Adt(_, _) => Sort::Int,
+            BaseTy::Adt(_, _) | BaseTy::Array(_) | BaseTy::Slice(_) | BaseTy::Param(_) => Sort::Int,
         }
     }
 
@@ -108,9 +150,34 @@ impl BaseTy {
         BaseTy::Adt(def_id, Substs::from_iter(substs))
     }
 
+    /// Returns the smallest and the largest value of an integer type, where `isize` and `usize`
+    /// have `pointer_width` bits.
+    pub fn int_bounds(&self, pointer_width: u64) -> Option<(Expr, Expr)> {
+        let (min, max) = match self {
+            BaseTy::Int(int_ty) => {
+                let shift = 128 - int_ty.bit_width().unwrap_or(pointer_width);
+                (
+                    Constant::from(i128::MIN >> shift),
+                    Constant::from(i128::MAX >> shift),
+                )
+            }
+            BaseTy::Uint(uint_ty) => {
+                let shift = 128 - uint_ty.bit_width().unwrap_or(pointer_width);
+                (Constant::ZERO, Constant::from(u128::MAX >> shift))
+            }
+            _ => return None,
+        };
+        Some((
+            ExprKind::Constant(min).intern(),
+            ExprKind::Constant(max).intern(),
+        ))
+    }
+
     fn walk(&self, f: &mut impl FnMut(&TyS)) {
-        if let BaseTy::Adt(_, substs) = self {
-            substs.iter().for_each(|ty| ty.walk(f));
+        match self {
+            BaseTy::Adt(_, substs) => substs.iter().for_each(|ty| ty.walk(f)),
+            BaseTy::Array(ty) | BaseTy::Slice(ty) => ty.walk(f),
+            BaseTy::Int(_) | BaseTy::Uint(_) | BaseTy::Bool | BaseTy::Param(_) => {}
         }
     }
 }
@@ -158,26 +225,35 @@ impl Expr {
             .clone()
     }
 
+    pub fn ff() -> Expr {
+        static FALSE: SyncOnceCell<Expr> = SyncOnceCell::new();
+        FALSE
+            .get_or_init(|| ExprKind::Constant(Constant::Bool(false)).intern())
+            .clone()
+    }
+
     pub fn zero() -> Expr {
         static ZERO: SyncOnceCell<Expr> = SyncOnceCell::new();
         ZERO.get_or_init(|| ExprKind::Constant(Constant::ZERO).intern())
             .clone()
     }
 
-    pub fn from_bits(bty: &BaseTy, bits: u128) -> Expr {
-        // FIXME: We are assuming the higher bits are not set. check this assumption
-        match bty {
-            BaseTy::Int(_) => {
-                let bits = bits as i128;
-                ExprKind::Constant(Constant::from(bits)).intern()
-            }
-            BaseTy::Uint(_) => {
-                let bits = bits as u128;
-                ExprKind::Constant(Constant::from(bits)).intern()
+    /// Returns the constant of type `bty` with the bit representation `bits`, sign-extended to 128
+    /// bits for signed integers, or `None` if values of type `bty` aren't represented by a constant.
+    pub fn from_bits(bty: &BaseTy, bits: u128) -> Option<Expr> {
+        let constant = match bty {
+            BaseTy::Int(_) => Constant::from(bits as i128),
+            BaseTy::Uint(_) => Constant::from(bits),
+            BaseTy::Bool => Constant::Bool(bits != 0),
+            BaseTy::Adt(_, _) | BaseTy::Array(_) | BaseTy::Slice(_) | BaseTy::Param(_) => {
+                return None
             }
-            BaseTy::Bool => ExprKind::Constant(Constant::Bool(bits != 0)).intern(),
-            BaseTy::Adt(_, _) => panic!(),
-        }
+        };
+        Some(ExprKind::Constant(constant).intern())
+    }
+
+    pub fn app(func: Name, args: impl IntoIterator<Item = Expr>) -> Expr {
+        ExprKind::App(func, Interned::new(args.into_iter().collect())).intern()
     }
 
     pub fn not(&self) -> Expr {
@@ -187,6 +263,23 @@ impl Expr {
     pub fn neg(&self) -> Expr {
         ExprKind::UnaryOp(UnOp::Neg, self.clone()).intern()
     }
+
+    /// Instantiates the solution of a kvar with its arguments `args`. Returns `None` if the
+    /// solution mentions a variable other than its arguments.
+    pub fn from_solution(expr: &fixpoint::Expr, args: &[Expr]) -> Option<Expr> {
+        let expr = match expr {
+            fixpoint::Expr::Var(name) => return args.get(name.index()).cloned(),
+            fixpoint::Expr::Constant(c) => ExprKind::Constant(*c),
+            fixpoint::Expr::BinaryOp(op, e1, e2) => ExprKind::BinaryOp(
+                *op,
+                Expr::from_solution(e1, args)?,
+                Expr::from_solution(e2, args)?,
+            ),
+            fixpoint::Expr::UnaryOp(op, e) => ExprKind::UnaryOp(*op, Expr::from_solution(e, args)?),
+            fixpoint::Expr::App(..) => return None,
+        };
+        Some(expr.intern())
+    }
 }
 
 impl ExprS {
@@ -201,7 +294,7 @@ impl ExprS {
     pub fn is_atom(&self) -> bool {
         matches!(
             self.kind,
-            ExprKind::Var(_) | ExprKind::Constant(_) | ExprKind::UnaryOp(..)
+            ExprKind::Var(_) | ExprKind::Constant(_) | ExprKind::UnaryOp(..) | ExprKind::App(..)
         )
     }
 
@@ -219,6 +312,15 @@ impl ExprS {
             )
             .intern(),
             ExprKind::UnaryOp(op, e) => ExprKind::UnaryOp(*op, e.subst_bound_vars(to)).intern(),
+            ExprKind::App(func, args) => ExprKind::App(
+                *func,
+                Interned::new(
+                    args.iter()
+                        .map(|arg| arg.subst_bound_vars(to.clone()))
+                        .collect(),
+                ),
+            )
+            .intern(),
         }
     }
 }
@@ -234,6 +336,16 @@ impl Pred {
         Pred::KVar(kvid, Interned::new(args.into_iter().collect()))
     }
 
+    /// The conjunction of `preds`, dropping the trivial ones.
+    pub fn and(preds: impl IntoIterator<Item = Pred>) -> Self {
+        let mut preds = preds.into_iter().filter(|p| !p.is_true()).collect_vec();
+        match preds.len() {
+            0 => Pred::Expr(Expr::tt()),
+            1 => preds.pop().unwrap(),
+            _ => Pred::And(Interned::new(preds)),
+        }
+    }
+
     pub fn is_atom(&self) -> bool {
         matches!(self, Pred::KVar(_, _)) || matches!(self, Pred::Expr(e) if e.is_atom())
     }
@@ -245,6 +357,12 @@ impl Pred {
                 args.iter().map(|arg| arg.subst_bound_vars(to.clone())),
             ),
             Pred::Expr(e) => Pred::Expr(e.subst_bound_vars(to)),
+            Pred::And(preds) => Pred::And(Interned::new(
+                preds
+                    .iter()
+                    .map(|pred| pred.subst_bound_vars(to.clone()))
+                    .collect(),
+            )),
         }
     }
 
@@ -252,6 +370,7 @@ impl Pred {
         match self {
             Pred::KVar(..) => false,
             Pred::Expr(e) => e.is_true(),
+            Pred::And(preds) => preds.iter().all(Pred::is_true),
         }
     }
 }
@@ -279,7 +398,7 @@ impl Ord for Loc {
     }
 }
 
-impl_internable!(TyS, ExprS, Vec<Expr>, Vec<Ty>);
+impl_internable!(TyS, ExprS, Vec<Expr>, Vec<Ty>, Vec<Pred>);
 
 mod pretty {
     use rustc_middle::ty::TyCtxt;
@@ -299,10 +418,22 @@ mod pretty {
                     }
                 }
                 TyKind::Exists(bty, p) => w!("{:?}{{{:?}}}", bty, p),
+                TyKind::Tuple(tys) => {
+                    if tys.len() == 1 {
+                        w!("({:?},)", &tys[0])
+                    } else {
+                        w!("({:?})", join!(", ", tys.iter()))
+                    }
+                }
+                TyKind::Struct(bty, fields) => {
+                    w!("{:?} {{ {:?} }}", bty, join!(", ", fields.iter()))
+                }
                 TyKind::Uninit => w!("uninit"),
                 TyKind::StrgRef(loc) => w!("ref<{:?}>", loc),
                 TyKind::Ref(region) => w!("&mut {:?}", region),
                 TyKind::Param(ParamTy { name, .. }) => w!("{:?}", ^name),
+                TyKind::Never => w!("!"),
+                TyKind::Fn(args, ret) => w!("fn({:?}) -> {:?}", join!(", ", args.iter()), ret),
             }
         }
 
@@ -325,6 +456,9 @@ mod pretty {
                     }
                     Ok(())
                 }
+                BaseTy::Array(ty) => w!("[{:?}; _]", ty),
+                BaseTy::Slice(ty) => w!("[{:?}]", ty),
+                BaseTy::Param(ParamTy { name, .. }) => w!("{:?}", ^name),
             }
         }
     }
@@ -334,6 +468,13 @@ mod pretty {
             define_scoped!(cx, f);
             match self {
                 Self::KVar(kvid, args) => {
+                    let solved = cx
+                        .solution
+                        .and_then(|solution| solution.get(kvid))
+                        .and_then(|expr| Expr::from_solution(expr, args));
+                    if let Some(expr) = solved {
+                        return w!("{:?}", expr);
+                    }
                     w!("{:?}", ^kvid)?;
                     match cx.kvar_args {
                         Visibility::Show => w!("({:?})", join!(", ", args))?,
@@ -343,6 +484,16 @@ mod pretty {
                     Ok(())
                 }
                 Self::Expr(expr) => w!("{:?}", expr),
+                Self::And(preds) => {
+                    let preds = preds.iter().format_with(" ∧ ", |pred, f| {
+                        if pred.is_atom() {
+                            f(&format_args_cx!("{:?}", pred))
+                        } else {
+                            f(&format_args_cx!("({:?})", pred))
+                        }
+                    });
+                    w!("{}", ^preds)
+                }
             }
         }
 
@@ -365,7 +516,7 @@ mod pretty {
             }
 
             match self.kind() {
-                ExprKind::Var(x) => w!("{:?}", ^x),
+                ExprKind::Var(x) => w!("{:?}", x),
                 ExprKind::BinaryOp(op, e1, e2) => {
                     if should_parenthesize(*op, e1) {
                         w!("({:?})", e1)?;
@@ -405,25 +556,32 @@ mod pretty {
                         w!("{:?}({:?})", op, e)
                     }
                 }
+                ExprKind::App(func, args) => w!("{:?}({:?})", ^func, join!(", ", args.iter())),
             }
         }
     }
 
     impl Pretty for Var {
-        fn fmt(&self, _cx: &PPrintCx, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn fmt(&self, cx: &PPrintCx, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             define_scoped!(cx, f);
             match self {
                 Var::Bound => w!("ν"),
-                Var::Free(var) => w!("{:?}", ^var),
+                Var::Free(var) => match cx.names.and_then(|names| names.var(*var)) {
+                    Some(source) => w!("{}", ^source),
+                    None => w!("{:?}", ^var),
+                },
             }
         }
     }
 
     impl Pretty for Loc {
-        fn fmt(&self, _cx: &PPrintCx, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn fmt(&self, cx: &PPrintCx, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             define_scoped!(cx, f);
             match self {
-                Loc::Local(local) => w!("{:?}", ^local),
+                Loc::Local(local) => match cx.names.and_then(|names| names.local(*local)) {
+                    Some(name) => w!("{}", ^name),
+                    None => w!("{:?}", ^local),
+                },
                 Loc::Abstract(name) => w!("{:?}", ^name),
             }
         }
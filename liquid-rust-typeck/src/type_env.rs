@@ -1,12 +1,18 @@
 use crate::{
+    checker::CheckerError,
     constraint_builder::Cursor,
-    ty::{BaseTy, Ty, TyKind},
+    lowering,
+    ty::{BaseTy, BinOp, Constant, Expr, ExprKind, Ty, TyKind, Var},
 };
 use itertools::{izip, Itertools};
-use liquid_rust_core::ir::{self, Local};
+use liquid_rust_core::{
+    ir::{self, Local},
+    ty as core,
+};
 use liquid_rust_fixpoint::KVid;
-use rustc_hash::FxHashMap;
-use rustc_middle::ty::TyCtxt;
+use rustc_hash::{FxHashMap, FxHashSet};
+use rustc_middle::{mir::Mutability, ty::TyCtxt};
+use rustc_span::Symbol;
 
 use super::ty::{Loc, Pred, TyS};
 
@@ -14,10 +20,21 @@ use super::ty::{Loc, Pred, TyS};
 pub struct TypeEnv<'tcx> {
     tcx: TyCtxt<'tcx>,
     bindings: FxHashMap<Loc, Binding>,
+    /// Locations pointed to by the shared references received as arguments, which can only be
+    /// read.
+    shared: FxHashSet<Loc>,
 }
 
 pub struct TypeEnvShape(Vec<(Loc, Ty)>);
 
+/// A step in the path from a location to a place stored in it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PathElem {
+    Field(ir::Field),
+    /// An element of an array or slice.
+    Index,
+}
+
 #[derive(Clone)]
 pub enum Binding {
     Strong(Ty),
@@ -39,6 +56,14 @@ impl Binding {
         }
     }
 
+    /// A weak binding that only knows its bound, e.g., after joining two paths.
+    fn weakened(bound: Ty) -> Binding {
+        Binding::Weak {
+            ty: bound.clone(),
+            bound,
+        }
+    }
+
     fn ty_mut(&mut self) -> &mut Ty {
         match self {
             Binding::Strong(ty) => ty,
@@ -52,6 +77,7 @@ impl<'tcx> TypeEnv<'tcx> {
         TypeEnv {
             tcx,
             bindings: FxHashMap::default(),
+            shared: FxHashSet::default(),
         }
     }
 
@@ -72,72 +98,366 @@ impl<'tcx> TypeEnv<'tcx> {
         self.bindings.get(&loc).map(|k| k.ty())
     }
 
-    pub fn lookup_place(&self, place: &ir::Place) -> Ty {
-        let (_, ty) = self.walk_place(place);
-        ty
+    /// Returns the type of the location a strong reference points to, which must be in the
+    /// environment for the reference to be dereferenced.
+    pub fn lookup_referee(&self, loc: Loc) -> Result<Ty, CheckerError> {
+        self.lookup_loc(loc).ok_or_else(|| {
+            CheckerError::UnsupportedOperands("*".to_string(), vec![TyKind::StrgRef(loc).intern()])
+        })
+    }
+
+    pub fn lookup_place(
+        &mut self,
+        cursor: &mut Cursor,
+        place: &ir::Place,
+    ) -> Result<Ty, CheckerError> {
+        let (loc, path, _) = self.walk_place(cursor, place)?;
+        Ok(cursor.unpack(project(&self.bindings[&loc].ty(), &path)?))
     }
 
     pub fn insert_loc(&mut self, loc: Loc, ty: Ty) {
         self.bindings.insert(loc, Binding::Strong(ty));
     }
 
-    pub fn update_loc(&mut self, cursor: &mut Cursor, loc: Loc, new_ty: Ty) {
+    pub fn update_loc(
+        &mut self,
+        cursor: &mut Cursor,
+        loc: Loc,
+        new_ty: Ty,
+    ) -> Result<(), CheckerError> {
         let binding = self.bindings.get_mut(&loc).unwrap();
         match binding {
             Binding::Strong(_) => *binding = Binding::Strong(new_ty),
-            Binding::Weak { bound, .. } => {
-                cursor.subtyping(new_ty, bound.clone());
+            Binding::Weak { bound, ty } => {
+                cursor.subtyping(new_ty, bound.clone())?;
+                *ty = cursor.unpack(bound.clone());
             }
         }
+        Ok(())
+    }
+
+    pub fn borrow(
+        &mut self,
+        cursor: &mut Cursor,
+        mutbl: Mutability,
+        place: &ir::Place,
+    ) -> Result<Loc, CheckerError> {
+        let (loc, path, behind_shr_ref) = self.walk_place(cursor, place)?;
+        if behind_shr_ref && mutbl == Mutability::Mut {
+            return Err(CheckerError::MutationBehindShrRef);
+        }
+        if path.is_empty() {
+            return Ok(loc);
+        }
+
+        // A reference to a field or an element points to a fresh location. A shared reference
+        // sees the current value, which cannot change while it is alive. Through a mutable
+        // reference any value of the declared type of the place can be written, so the location
+        // is weak and the place only keeps its declared type.
+        let ty = self.bindings[&loc].ty();
+        let current = project(&ty, &path)?;
+        let fresh = Loc::Abstract(cursor.fresh_name());
+        let binding = match mutbl {
+            Mutability::Not => Binding::Strong(cursor.unpack(current)),
+            Mutability::Mut => {
+                let bound = self.declared_ty(cursor, &ty, &path, place)?;
+                cursor.subtyping(current, bound.clone())?;
+                if !path.contains(&PathElem::Index) {
+                    let ty = update(&ty, &path, cursor.unpack(bound.clone()))?;
+                    self.update_loc(cursor, loc, ty)?;
+                }
+                Binding::Weak {
+                    ty: cursor.unpack(bound.clone()),
+                    bound,
+                }
+            }
+        };
+        self.bindings.insert(fresh, binding);
+        Ok(fresh)
+    }
+
+    /// Returns the declared type of the place at a non empty `path` inside a value of type `ty`,
+    /// i.e., the type of the field or the element type of the array or slice it projects.
+    fn declared_ty(
+        &self,
+        cursor: &mut Cursor,
+        ty: &Ty,
+        path: &[PathElem],
+        place: &ir::Place,
+    ) -> Result<Ty, CheckerError> {
+        let unsupported = || CheckerError::UnsupportedOperands(".".to_string(), vec![ty.clone()]);
+        let (last, prefix) = path.split_last().ok_or_else(unsupported)?;
+        match (last, project(ty, prefix)?.kind()) {
+            (PathElem::Field(field), TyKind::Struct(bty, _)) => cursor
+                .field_tys(bty)?
+                .into_iter()
+                .nth(field.as_usize())
+                .ok_or_else(unsupported),
+            (PathElem::Field(field), TyKind::Tuple(_)) => match place.projection.last() {
+                Some(ir::PlaceElem::Field(_, _, fields)) if field.as_usize() < fields.len() => {
+                    Ok(lowering::Subst::with_empty_type_substs()
+                        .lower_ty(cursor, &fields[field.as_usize()]))
+                }
+                _ => Err(unsupported()),
+            },
+            (PathElem::Index, _) => project(ty, path),
+            (_, _) => Err(unsupported()),
+        }
     }
 
-    pub fn get_loc(&self, place: &ir::Place) -> Loc {
-        let (loc, _) = self.walk_place(place);
-        loc
+    pub fn move_place(
+        &mut self,
+        cursor: &mut Cursor,
+        place: &ir::Place,
+    ) -> Result<Ty, CheckerError> {
+        if place
+            .projection
+            .iter()
+            .any(|elem| matches!(elem, ir::PlaceElem::Deref))
+        {
+            return Err(CheckerError::MoveBehindRef);
+        }
+        let (loc, path, _) = self.walk_place(cursor, place)?;
+        let ty = self.bindings[&loc].ty();
+        let moved = project(&ty, &path)?;
+        if path.contains(&PathElem::Index) {
+            // Only copy types can be moved out of an array
+            return Ok(cursor.unpack(moved));
+        }
+        let ty = update(&ty, &path, TyKind::Uninit.intern())?;
+        self.bindings.insert(loc, Binding::Strong(ty));
+        Ok(moved)
+    }
+
+    pub fn write_place(
+        &mut self,
+        cursor: &mut Cursor,
+        place: &ir::Place,
+        new_ty: Ty,
+    ) -> Result<(), CheckerError> {
+        let (loc, path, behind_shr_ref) = self.walk_place(cursor, place)?;
+        if behind_shr_ref {
+            return Err(CheckerError::MutationBehindShrRef);
+        }
+        let ty = self.bindings[&loc].ty();
+
+        if path.contains(&PathElem::Index) {
+            return cursor.subtyping(new_ty, project(&ty, &path)?);
+        }
+
+        match project(&ty, &path)?.kind() {
+            TyKind::Uninit
+            | TyKind::Refine(..)
+            | TyKind::Param(_)
+            | TyKind::Never
+            | TyKind::Fn(..)
+            | TyKind::StrgRef(_)
+            | TyKind::Ref(_)
+            | TyKind::Tuple(_)
+            | TyKind::Struct(..) => {
+                // TODO: debug check new_ty has the same "shape" as ty
+                self.update_loc(cursor, loc, update(&ty, &path, new_ty)?)?;
+            }
+            // Types in the environment are unpacked.
+            TyKind::Exists(..) => return Err(CheckerError::IncompatibleTypes(new_ty, ty)),
+        }
+        Ok(())
+    }
+
+    /// Returns the location a place points to, the path inside that location and whether the
+    /// place is behind a shared reference. Aggregates traversed by a field projection are unfolded
+    /// along the way and for every index projection we check the index is in bounds. A field of
+    /// an element of an array is reached through a fresh weak location holding the element.
+    fn walk_place(
+        &mut self,
+        cursor: &mut Cursor,
+        place: &ir::Place,
+    ) -> Result<(Loc, Vec<PathElem>, bool), CheckerError> {
+        let mut loc = Loc::Local(place.local);
+        let mut path = vec![];
+        let mut behind_shr_ref = false;
+        for elem in &place.projection {
+            match elem {
+                ir::PlaceElem::Deref => {
+                    let ty = project(&self.bindings[&loc].ty(), &path)?;
+                    match ty.kind() {
+                        TyKind::StrgRef(referee) => {
+                            behind_shr_ref |= self.shared.contains(referee);
+                            loc = *referee;
+                            path.clear();
+                        }
+                        TyKind::Ref(bound) => {
+                            // The referent can only be read, so it is enough to look at it through
+                            // a fresh weak location.
+                            let fresh = Loc::Abstract(cursor.fresh_name());
+                            self.bindings.insert(
+                                fresh,
+                                Binding::Weak {
+                                    bound: bound.clone(),
+                                    ty: cursor.unpack(bound.clone()),
+                                },
+                            );
+                            loc = fresh;
+                            path.clear();
+                            behind_shr_ref = true;
+                        }
+                        _ => {
+                            return Err(CheckerError::UnsupportedOperands(
+                                "*".to_string(),
+                                vec![ty],
+                            ))
+                        }
+                    }
+                }
+                ir::PlaceElem::Field(field, kind, fields) => {
+                    if path.contains(&PathElem::Index) {
+                        // Elements of arrays cannot be unfolded in place because they can only be
+                        // updated weakly, so we look at the element through a fresh weak location.
+                        let elem = project(&self.bindings[&loc].ty(), &path)?;
+                        let fresh = Loc::Abstract(cursor.fresh_name());
+                        self.bindings.insert(
+                            fresh,
+                            Binding::Weak {
+                                ty: cursor.unpack(elem.clone()),
+                                bound: elem,
+                            },
+                        );
+                        loc = fresh;
+                        path.clear();
+                    }
+                    self.unfold(cursor, loc, &path, kind, fields)?;
+                    path.push(PathElem::Field(*field));
+                }
+                ir::PlaceElem::Index(idx) => {
+                    let len = self.lookup_len(loc, &path)?;
+                    let idx_ty = self.lookup_local(*idx);
+                    let idx = match idx_ty.kind() {
+                        TyKind::Refine(BaseTy::Uint(_), e) => e.clone(),
+                        _ => {
+                            let err =
+                                CheckerError::UnsupportedOperands("[]".to_string(), vec![idx_ty]);
+                            return Err(err);
+                        }
+                    };
+                    let lower = ExprKind::BinaryOp(BinOp::Le, Expr::zero(), idx.clone()).intern();
+                    let upper = ExprKind::BinaryOp(BinOp::Lt, idx, len).intern();
+                    cursor.push_head(ExprKind::BinaryOp(BinOp::And, lower, upper).intern());
+                    path.push(PathElem::Index);
+                }
+                ir::PlaceElem::ConstantIndex { offset, from_end } => {
+                    let len = self.lookup_len(loc, &path)?;
+                    let offset = ExprKind::Constant(Constant::from(*offset as u128)).intern();
+                    let op = if *from_end { BinOp::Le } else { BinOp::Lt };
+                    cursor.push_head(ExprKind::BinaryOp(op, offset, len).intern());
+                    path.push(PathElem::Index);
+                }
+            }
+        }
+        Ok((loc, path, behind_shr_ref))
     }
 
-    pub fn move_place(&mut self, place: &ir::Place) -> Ty {
-        assert!(place.projection.is_empty());
-        let loc = Loc::Local(place.local);
-        let ty = self.lookup_loc(loc).unwrap();
-        self.bindings
-            .insert(loc, Binding::Strong(TyKind::Uninit.intern()));
-        ty
+    fn lookup_len(&self, loc: Loc, path: &[PathElem]) -> Result<Expr, CheckerError> {
+        let ty = project(&self.bindings[&loc].ty(), path)?;
+        match ty.kind() {
+            TyKind::Refine(BaseTy::Array(_) | BaseTy::Slice(_), len) => Ok(len.clone()),
+            _ => Err(CheckerError::UnsupportedOperands(
+                "[]".to_string(),
+                vec![ty],
+            )),
+        }
     }
 
-    pub fn write_place(&mut self, cursor: &mut Cursor, place: &ir::Place, new_ty: Ty) {
-        let (loc, ty) = self.walk_place(place);
+    fn unfold(
+        &mut self,
+        cursor: &mut Cursor,
+        loc: Loc,
+        path: &[PathElem],
+        kind: &ir::AggregateKind,
+        fields: &[core::Ty],
+    ) -> Result<(), CheckerError> {
+        let binding = self.bindings.get_mut(&loc).unwrap();
+        let ty = project(&binding.ty(), path)?;
+        let unfolded = match ty.kind() {
+            TyKind::Tuple(_) | TyKind::Struct(..) => return Ok(()),
+            TyKind::Uninit => {
+                let uninit = fields.iter().map(|_| TyKind::Uninit.intern());
+                match kind {
+                    ir::AggregateKind::Tuple => TyKind::tuple(uninit),
+                    ir::AggregateKind::Adt(did, substs) => {
+                        let mut subst = lowering::Subst::with_empty_type_substs();
+                        let substs = substs.iter().map(|ty| subst.lower_ty(cursor, ty));
+                        TyKind::unfolded(BaseTy::adt(*did, substs), uninit)
+                    }
+                    ir::AggregateKind::Array(_) => {
+                        return Err(CheckerError::UnsupportedOperands(".".to_string(), vec![ty]))
+                    }
+                }
+            }
+            TyKind::Refine(bty @ BaseTy::Adt(..), _) | TyKind::Exists(bty @ BaseTy::Adt(..), _) => {
+                cursor.unfold_adt(bty)?
+            }
+            _ => return Err(CheckerError::UnsupportedOperands(".".to_string(), vec![ty])),
+        };
+        *binding.ty_mut() = update(&binding.ty(), path, unfolded)?;
+        Ok(())
+    }
 
+    /// Copies a value of type `ty` into `other`. Locations `ty` points to are copied as well
+    /// with fresh names and their current type. Copying a location is only sound if it cannot
+    /// be modified while the copy is alive, e.g., because it is borrowed immutably.
+    pub fn copy_into(&self, cursor: &mut Cursor, ty: Ty, other: &mut TypeEnv) -> Ty {
         match ty.kind() {
-            TyKind::Uninit | TyKind::Refine(..) | TyKind::Param(_) | TyKind::StrgRef(_) => {
-                // TODO: debug check new_ty has the same "shape" as ty
-                self.update_loc(cursor, loc, new_ty);
+            TyKind::StrgRef(loc) => {
+                let ty = self.copy_into(cursor, self.bindings[loc].ty(), other);
+                let fresh = Loc::Abstract(cursor.fresh_name());
+                other.insert_loc(fresh, ty);
+                TyKind::StrgRef(fresh).intern()
             }
-            TyKind::Ref(_) => {
-                todo!("implement updates of references inside references")
+            TyKind::Tuple(tys) => TyKind::tuple(
+                tys.iter()
+                    .map(|ty| self.copy_into(cursor, ty.clone(), other)),
+            ),
+            TyKind::Struct(bty, fields) => TyKind::unfolded(
+                bty.clone(),
+                fields
+                    .iter()
+                    .map(|ty| self.copy_into(cursor, ty.clone(), other)),
+            ),
+            _ => ty,
+        }
+    }
+
+    /// Records the names of the variables refining the values of the locals in `local_names` and
+    /// of the places they point to, see [`Cursor::name_var`].
+    pub fn name_vars(&self, cursor: &mut Cursor, local_names: &FxHashMap<Local, Symbol>) {
+        for (loc, binding) in &self.bindings {
+            if let Loc::Local(local) = loc {
+                if let Some(name) = local_names.get(local) {
+                    self.name_ty_vars(cursor, &binding.ty(), name.to_string());
+                }
             }
-            TyKind::Exists(..) => unreachable!("unpacked existential: `{:?}`", ty),
         }
     }
 
-    fn walk_place(&self, place: &ir::Place) -> (Loc, Ty) {
-        let mut loc = Loc::Local(place.local);
-        let mut ty = self.lookup_loc(loc).unwrap();
-        for elem in &place.projection {
-            match (elem, ty.kind()) {
-                (ir::PlaceElem::Deref, TyKind::StrgRef(referee)) => {
-                    loc = *referee;
-                    ty = self.lookup_loc(loc).unwrap();
+    fn name_ty_vars(&self, cursor: &mut Cursor, ty: &TyS, source: String) {
+        match ty.kind() {
+            TyKind::Refine(_, e) => {
+                if let ExprKind::Var(Var::Free(name)) = e.kind() {
+                    cursor.name_var(*name, source);
                 }
-                (ir::PlaceElem::Deref, TyKind::Ref(_)) => {
-                    todo!()
+            }
+            TyKind::Tuple(tys) | TyKind::Struct(_, tys) => {
+                for (i, ty) in tys.iter().enumerate() {
+                    self.name_ty_vars(cursor, ty, format!("{}.{}", source, i));
                 }
-                _ => {
-                    unreachable!("unexpected type: {:?}", ty);
+            }
+            TyKind::StrgRef(loc @ Loc::Abstract(_)) => {
+                if let Some(binding) = self.bindings.get(loc) {
+                    self.name_ty_vars(cursor, &binding.ty(), format!("*{}", source));
                 }
             }
+            _ => {}
         }
-        (loc, ty)
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (&Loc, &Binding)> + '_ {
@@ -145,14 +465,27 @@ impl<'tcx> TypeEnv<'tcx> {
     }
 
     pub fn unpack(&mut self, cursor: &mut Cursor) {
+        self.unpack_with(cursor, false);
+    }
+
+    /// Like `unpack` but for the arguments of a function. The locations their shared references
+    /// point to are recorded as shared, so mutating them is an error.
+    pub fn unpack_args(&mut self, cursor: &mut Cursor) {
+        self.unpack_with(cursor, true);
+    }
+
+    fn unpack_with(&mut self, cursor: &mut Cursor, args: bool) {
         for loc in self.bindings.keys().copied().collect_vec() {
             let binding = self.bindings.get_mut(&loc).unwrap();
             match binding.ty().kind() {
-                TyKind::Exists(..) => {
+                TyKind::Exists(..) | TyKind::Tuple(_) | TyKind::Struct(..) => {
                     *binding.ty_mut() = cursor.unpack(binding.ty());
                 }
                 TyKind::Ref(ty) => {
                     let fresh = Loc::Abstract(cursor.fresh_name());
+                    if args {
+                        self.shared.insert(fresh);
+                    }
                     *binding.ty_mut() = TyKind::StrgRef(fresh).intern();
                     self.bindings.insert(
                         fresh,
@@ -167,24 +500,36 @@ impl<'tcx> TypeEnv<'tcx> {
         }
     }
 
-    pub fn transform_into(&mut self, cursor: &mut Cursor, other: &TypeEnv) {
+    pub fn transform_into(
+        &mut self,
+        cursor: &mut Cursor,
+        other: &TypeEnv,
+    ) -> Result<(), CheckerError> {
         self.weakening(other);
 
         let levels = self.levels();
 
         for (loc, _) in levels.into_iter().sorted_by_key(|(_, level)| *level).rev() {
+            if let (Binding::Weak { ty: ty1, .. }, Binding::Weak { ty: ty2, .. }) =
+                (&self.bindings[&loc], &other.bindings[&loc])
+            {
+                cursor.subtyping(ty1.clone(), ty2.clone())?;
+                self.bindings.insert(loc, other.bindings[&loc].clone());
+                continue;
+            }
             let ty1 = self.bindings[&loc].assert_strong();
             let ty2 = other.bindings[&loc].assert_strong();
             match (ty1.kind(), ty2.kind()) {
                 (TyKind::StrgRef(loc), TyKind::Ref(bound)) => {
-                    self.ref_weak(cursor, *loc, bound.clone());
+                    self.ref_weak(cursor, *loc, bound.clone())?;
                 }
                 _ => {
-                    cursor.subtyping(ty1, ty2.clone());
+                    cursor.subtyping(ty1, ty2.clone())?;
                 }
             };
             self.insert_loc(loc, ty2.clone());
         }
+        Ok(())
     }
 
     pub fn infer_bb_env(&self, cursor: &mut Cursor, shape: TypeEnvShape) -> TypeEnv<'tcx> {
@@ -211,17 +556,17 @@ impl<'tcx> TypeEnv<'tcx> {
         }
 
         let mut env = TypeEnv::new(self.tcx);
+        env.shared = self.shared.clone();
         for (loc, ty1) in shape {
             let loc = locs.get(&loc).copied().unwrap_or(loc);
-            let ty2 = self.bindings[&loc].ty();
-
-            let ty = match (ty1.kind(), ty2.kind()) {
-                (TyKind::Refine(_, _), TyKind::Refine(_, _))
-                | (TyKind::StrgRef(_), TyKind::StrgRef(_))
-                | (_, TyKind::Uninit) => ty2,
-                _ => replace_kvars(&ty1, &kvars),
-            };
-            env.insert_loc(loc, ty);
+            match &self.bindings[&loc] {
+                Binding::Strong(ty2) => {
+                    env.insert_loc(loc, infer_ty(&ty1, ty2.clone(), &kvars));
+                }
+                Binding::Weak { bound, .. } => {
+                    env.bindings.insert(loc, Binding::weakened(bound.clone()));
+                }
+            }
         }
         env
     }
@@ -250,8 +595,9 @@ impl<'tcx> TypeEnv<'tcx> {
         levels
     }
 
-    pub fn join_with(&mut self, other: &TypeEnv, cursor: &mut Cursor) {
+    pub fn join_with(&mut self, other: &TypeEnv, cursor: &mut Cursor) -> Result<(), CheckerError> {
         self.weakening(other);
+        self.shared.extend(other.shared.iter().copied());
 
         let mut levels = self.levels();
         let levels_other = other.levels();
@@ -262,121 +608,198 @@ impl<'tcx> TypeEnv<'tcx> {
         for (loc, _) in levels.into_iter().sorted_by_key(|(_, level)| *level) {
             let binding1 = self.bindings[&loc].clone();
             let binding2 = other.bindings[&loc].clone();
-            if let (Binding::Strong(ty1), Binding::Strong(ty2)) = (binding1, binding2) {
-                let ty = self.strg_ty_join(cursor, ty1, ty2);
-                self.bindings.insert(loc, Binding::Strong(ty));
+            match (binding1, binding2) {
+                (Binding::Strong(ty1), Binding::Strong(ty2)) => {
+                    let ty = self.strg_ty_join(cursor, ty1, ty2)?;
+                    self.bindings.insert(loc, Binding::Strong(ty));
+                }
+                (Binding::Weak { bound, .. }, Binding::Weak { .. }) => {
+                    self.bindings.insert(loc, Binding::weakened(bound));
+                }
+                _ => {}
             }
         }
+        Ok(())
     }
 
     fn weakening(&mut self, other: &TypeEnv) {
-        self.bindings.retain(|loc, binding| {
-            if other.bindings.contains_key(loc) {
-                if other.lookup_loc(*loc).unwrap().is_uninit() {
-                    *binding = Binding::Strong(TyKind::Uninit.intern());
+        self.bindings
+            .retain(|loc, binding| match other.lookup_loc(*loc) {
+                Some(ty) => {
+                    if ty.is_uninit() {
+                        *binding = Binding::Strong(TyKind::Uninit.intern());
+                    }
+                    true
                 }
-                true
-            } else {
-                false
-            }
-        });
+                None => false,
+            });
     }
 
-    fn strg_ty_join(&mut self, cursor: &mut Cursor, ty1: Ty, ty2: Ty) -> Ty {
-        match (ty1.kind(), ty2.kind()) {
+    fn strg_ty_join(&mut self, cursor: &mut Cursor, ty1: Ty, ty2: Ty) -> Result<Ty, CheckerError> {
+        let ty = match (ty1.kind(), ty2.kind()) {
             (_, _) if ty1 == ty2 => ty1,
             (TyKind::Uninit, _) | (_, TyKind::Uninit) => TyKind::Uninit.intern(),
             (TyKind::Refine(bty1, e1), TyKind::Refine(bty2, e2)) if e1 == e2 => {
-                TyKind::Refine(self.bty_join(cursor, bty1, bty2), e1.clone()).intern()
+                TyKind::Refine(self.bty_join(cursor, bty1, bty2)?, e1.clone()).intern()
             }
             (
                 TyKind::Refine(bty1, ..) | TyKind::Exists(bty1, ..),
                 TyKind::Refine(bty2, ..) | TyKind::Exists(bty2, ..),
             ) => {
-                let bty = self.bty_join(cursor, bty1, bty2);
+                let bty = self.bty_join(cursor, bty1, bty2)?;
                 let kvar = cursor.fresh_kvar(bty.sort());
                 TyKind::Exists(bty, kvar).intern()
             }
+            (TyKind::Tuple(tys1), TyKind::Tuple(tys2)) if tys1.len() == tys2.len() => {
+                let tys: Vec<_> = izip!(tys1.iter(), tys2.iter())
+                    .map(|(ty1, ty2)| self.strg_ty_join(cursor, ty1.clone(), ty2.clone()))
+                    .try_collect()?;
+                TyKind::tuple(tys)
+            }
+            (TyKind::Struct(bty1, fields1), TyKind::Struct(bty2, fields2)) => {
+                let bty = self.bty_join(cursor, bty1, bty2)?;
+                let fields: Vec<_> = izip!(fields1.iter(), fields2.iter())
+                    .map(|(ty1, ty2)| self.strg_ty_join(cursor, ty1.clone(), ty2.clone()))
+                    .try_collect()?;
+                TyKind::unfolded(bty, fields)
+            }
+            // Unfold a folded struct joined with an unfolded one to keep what we know about the
+            // fields of the latter.
+            (TyKind::Struct(..), TyKind::Refine(bty, _) | TyKind::Exists(bty, _)) => {
+                let ty2 = cursor.unfold_adt(bty)?;
+                self.strg_ty_join(cursor, ty1, ty2)?
+            }
+            (TyKind::Refine(bty, _) | TyKind::Exists(bty, _), TyKind::Struct(..)) => {
+                let ty1 = cursor.unfold_adt(bty)?;
+                self.strg_ty_join(cursor, ty1, ty2)?
+            }
+            (
+                TyKind::Param(param),
+                TyKind::Refine(BaseTy::Param(_), _) | TyKind::Exists(BaseTy::Param(_), _),
+            )
+            | (
+                TyKind::Refine(BaseTy::Param(_), _) | TyKind::Exists(BaseTy::Param(_), _),
+                TyKind::Param(param),
+            ) => TyKind::Param(*param).intern(),
             (TyKind::StrgRef(loc1), TyKind::StrgRef(loc2)) => {
-                let ty = self.ref_weak_join(cursor, *loc1, *loc2);
+                let ty = self.ref_weak_join(cursor, *loc1, *loc2)?;
                 TyKind::Ref(ty).intern()
             }
             (TyKind::Ref(ty), TyKind::StrgRef(loc)) | (TyKind::StrgRef(loc), TyKind::Ref(ty)) => {
-                self.ref_weak(cursor, *loc, ty.clone());
+                self.ref_weak(cursor, *loc, ty.clone())?;
                 ty.clone()
             }
-            _ => todo!("{:?} {:?}", ty1, ty2),
-        }
+            _ => return Err(CheckerError::IncompatibleTypes(ty1, ty2)),
+        };
+        Ok(ty)
     }
 
-    fn ref_weak(&mut self, cursor: &mut Cursor, loc: Loc, bound: Ty) {
-        let ty = self.bindings[&loc].assert_strong();
+    fn ref_weak(&mut self, cursor: &mut Cursor, loc: Loc, bound: Ty) -> Result<(), CheckerError> {
+        let ty = match &self.bindings[&loc] {
+            Binding::Strong(ty) => ty.clone(),
+            // A weak location already satisfies its bound and cannot be given a different one.
+            Binding::Weak { bound: bound2, .. } if bound2 == &bound => return Ok(()),
+            Binding::Weak { ty, .. } => {
+                return Err(CheckerError::IncompatibleTypes(ty.clone(), bound))
+            }
+        };
         match (ty.kind(), bound.kind()) {
             (_, TyKind::Exists(..)) => {
-                cursor.subtyping(ty, bound.clone());
+                cursor.subtyping(ty, bound.clone())?;
                 self.bindings.insert(loc, Binding::Strong(bound));
             }
             (TyKind::StrgRef(loc2), TyKind::Ref(bound2)) => {
-                self.ref_weak(cursor, *loc2, bound2.clone());
+                self.ref_weak(cursor, *loc2, bound2.clone())?;
                 self.bindings.insert(loc, Binding::Strong(bound));
             }
-            (TyKind::Ref(bound2), TyKind::Ref(bound3)) => {
-                assert!(bound2 == bound3);
-            }
-            _ => todo!(),
+            (TyKind::Ref(bound2), TyKind::Ref(bound3)) if bound2 == bound3 => {}
+            _ => return Err(CheckerError::IncompatibleTypes(ty, bound)),
         }
-    }
-
-    fn ref_weak_join(&mut self, cursor: &mut Cursor, loc1: Loc, loc2: Loc) -> Ty {
-        let ty1 = self.bindings[&loc1].assert_strong();
-        let ty2 = self.bindings[&loc2].assert_strong();
+        Ok(())
+    }
+
+    fn ref_weak_join(
+        &mut self,
+        cursor: &mut Cursor,
+        loc1: Loc,
+        loc2: Loc,
+    ) -> Result<Ty, CheckerError> {
+        let (ty1, ty2) = match (&self.bindings[&loc1], &self.bindings[&loc2]) {
+            (Binding::Strong(ty1), Binding::Strong(ty2)) => (ty1.clone(), ty2.clone()),
+            (Binding::Weak { bound: bound1, .. }, Binding::Weak { bound: bound2, .. })
+                if bound1 == bound2 =>
+            {
+                return Ok(bound1.clone());
+            }
+            (binding1, binding2) => {
+                return Err(CheckerError::IncompatibleTypes(
+                    binding1.ty(),
+                    binding2.ty(),
+                ));
+            }
+        };
         match (ty1.kind(), ty2.kind()) {
             (TyKind::Refine(..) | TyKind::Exists(..), TyKind::Refine(..) | TyKind::Exists(..)) => {
-                let ty_join = self.strg_ty_join(cursor, ty1.clone(), ty2.clone());
+                let ty_join = self.strg_ty_join(cursor, ty1.clone(), ty2.clone())?;
                 self.bindings.insert(loc1, Binding::Strong(ty_join.clone()));
                 self.bindings.insert(loc2, Binding::Strong(ty_join.clone()));
-                ty_join
+                Ok(ty_join)
             }
             (TyKind::StrgRef(loc1_), TyKind::StrgRef(loc2_)) => {
-                let ty_join = TyKind::Ref(self.ref_weak_join(cursor, *loc1_, *loc2_)).intern();
+                let ty_join = TyKind::Ref(self.ref_weak_join(cursor, *loc1_, *loc2_)?).intern();
                 self.bindings.insert(loc1, Binding::Strong(ty_join.clone()));
                 self.bindings.insert(loc1, Binding::Strong(ty_join.clone()));
-                ty_join
+                Ok(ty_join)
             }
             (TyKind::StrgRef(loc), TyKind::Ref(ty)) | (TyKind::Ref(ty), TyKind::StrgRef(loc)) => {
-                self.ref_weak(cursor, *loc, ty.clone());
+                self.ref_weak(cursor, *loc, ty.clone())?;
                 self.bindings.insert(loc1, Binding::Strong(ty.clone()));
-                ty.clone()
-            }
-            _ => {
-                todo!()
+                Ok(ty.clone())
             }
+            _ => Err(CheckerError::IncompatibleTypes(ty1, ty2)),
         }
     }
 
-    fn bty_join(&mut self, cursor: &mut Cursor, bty1: &BaseTy, bty2: &BaseTy) -> BaseTy {
-        match (bty1, bty2) {
+    fn bty_join(
+        &mut self,
+        cursor: &mut Cursor,
+        bty1: &BaseTy,
+        bty2: &BaseTy,
+    ) -> Result<BaseTy, CheckerError> {
+        let bty = match (bty1, bty2) {
             (BaseTy::Bool, BaseTy::Bool) => BaseTy::Bool,
-            (BaseTy::Int(int_ty1), BaseTy::Int(int_ty2)) => {
-                debug_assert_eq!(int_ty1, int_ty2);
+            (BaseTy::Param(param1), BaseTy::Param(param2)) if param1 == param2 => {
+                BaseTy::Param(*param1)
+            }
+            (BaseTy::Int(int_ty1), BaseTy::Int(int_ty2)) if int_ty1 == int_ty2 => {
                 BaseTy::Int(*int_ty1)
             }
-            (BaseTy::Uint(uint_ty1), BaseTy::Uint(uint_ty2)) => {
-                debug_assert_eq!(uint_ty1, uint_ty2);
+            (BaseTy::Uint(uint_ty1), BaseTy::Uint(uint_ty2)) if uint_ty1 == uint_ty2 => {
                 BaseTy::Uint(*uint_ty1)
             }
-            (BaseTy::Adt(did1, substs1), BaseTy::Adt(did2, substs2)) => {
-                debug_assert_eq!(did1, did2);
+            (BaseTy::Adt(did1, substs1), BaseTy::Adt(did2, substs2)) if did1 == did2 => {
+                // Only joining the arguments of covariant parameters keeps both sides subtypes of
+                // the join.
                 let variances = self.tcx.variances_of(*did1);
-                let substs =
-                    izip!(variances, substs1.iter(), substs2.iter()).map(|(variance, ty1, ty2)| {
-                        assert!(matches!(variance, rustc_middle::ty::Variance::Covariant));
-                        self.strg_ty_join(cursor, ty1.clone(), ty2.clone())
-                    });
+                let substs: Vec<_> = izip!(variances, substs1.iter(), substs2.iter())
+                    .map(|(variance, ty1, ty2)| match variance {
+                        rustc_middle::ty::Variance::Covariant => {
+                            self.strg_ty_join(cursor, ty1.clone(), ty2.clone())
+                        }
+                        _ => Err(CheckerError::incompatible_btys(bty1, bty2)),
+                    })
+                    .try_collect()?;
                 BaseTy::adt(*did1, substs)
             }
-            _ => todo!("{:?} {:?}", bty1, bty2),
-        }
+            (BaseTy::Array(ty1), BaseTy::Array(ty2)) => {
+                BaseTy::Array(self.strg_ty_join(cursor, ty1.clone(), ty2.clone())?)
+            }
+            (BaseTy::Slice(ty1), BaseTy::Slice(ty2)) => {
+                BaseTy::Slice(self.strg_ty_join(cursor, ty1.clone(), ty2.clone())?)
+            }
+            _ => return Err(CheckerError::incompatible_btys(bty1, bty2)),
+        };
+        Ok(bty)
     }
 }
 
@@ -400,6 +823,85 @@ impl IntoIterator for TypeEnvShape {
     }
 }
 
+/// Returns the type at `path` inside `ty`. All aggregates along the path must be unfolded.
+fn project(ty: &Ty, path: &[PathElem]) -> Result<Ty, CheckerError> {
+    match path {
+        [] => Ok(ty.clone()),
+        [PathElem::Field(field), path @ ..] => match ty.kind() {
+            TyKind::Tuple(tys) | TyKind::Struct(_, tys) if field.as_usize() < tys.len() => {
+                project(&tys[field.as_usize()], path)
+            }
+            _ => Err(CheckerError::UnsupportedOperands(
+                ".".to_string(),
+                vec![ty.clone()],
+            )),
+        },
+        [PathElem::Index, path @ ..] => match ty.kind() {
+            TyKind::Refine(BaseTy::Array(ty) | BaseTy::Slice(ty), _)
+            | TyKind::Exists(BaseTy::Array(ty) | BaseTy::Slice(ty), _) => project(ty, path),
+            _ => Err(CheckerError::UnsupportedOperands(
+                "[]".to_string(),
+                vec![ty.clone()],
+            )),
+        },
+    }
+}
+
+/// Returns a copy of `ty` where the type at `path` is replaced by `new_ty`. The path cannot go
+/// through an index projection because elements of an array cannot be strongly updated.
+fn update(ty: &Ty, path: &[PathElem], new_ty: Ty) -> Result<Ty, CheckerError> {
+    match path {
+        [] => Ok(new_ty),
+        [PathElem::Field(field), path @ ..] => {
+            let update_fields = |tys: &[Ty]| {
+                tys.iter()
+                    .enumerate()
+                    .map(|(i, ty)| {
+                        if i == field.as_usize() {
+                            update(ty, path, new_ty.clone())
+                        } else {
+                            Ok(ty.clone())
+                        }
+                    })
+                    .try_collect::<_, Vec<_>, _>()
+            };
+            match ty.kind() {
+                TyKind::Tuple(tys) => Ok(TyKind::tuple(update_fields(tys)?)),
+                TyKind::Struct(bty, fields) => {
+                    Ok(TyKind::unfolded(bty.clone(), update_fields(fields)?))
+                }
+                _ => Err(CheckerError::UnsupportedOperands(
+                    ".".to_string(),
+                    vec![ty.clone()],
+                )),
+            }
+        }
+        [PathElem::Index, ..] => Err(CheckerError::UnsupportedOperands(
+            "[]".to_string(),
+            vec![ty.clone()],
+        )),
+    }
+}
+
+/// Infers the type of a location at the entry of a basic block given the shape of the location
+/// and its type in the environment of the first predecessor we see.
+fn infer_ty(shape: &TyS, ty: Ty, kvars: &FxHashMap<KVid, Pred>) -> Ty {
+    match (shape.kind(), ty.kind()) {
+        (TyKind::Refine(_, _), TyKind::Refine(_, _))
+        | (TyKind::StrgRef(_), TyKind::StrgRef(_))
+        | (_, TyKind::Uninit) => ty,
+        (TyKind::Tuple(tys1), TyKind::Tuple(tys2)) => TyKind::tuple(
+            izip!(tys1.iter(), tys2.iter()).map(|(ty1, ty2)| infer_ty(ty1, ty2.clone(), kvars)),
+        ),
+        (TyKind::Struct(bty, fields1), TyKind::Struct(_, fields2)) => TyKind::unfolded(
+            replace_kvars_bty(bty, kvars),
+            izip!(fields1.iter(), fields2.iter())
+                .map(|(ty1, ty2)| infer_ty(ty1, ty2.clone(), kvars)),
+        ),
+        _ => replace_kvars(shape, kvars),
+    }
+}
+
 fn replace_kvars(ty: &TyS, kvars: &FxHashMap<KVid, Pred>) -> Ty {
     match ty.kind() {
         TyKind::Refine(bty, e) => TyKind::Refine(bty.clone(), e.clone()).intern(),
@@ -407,10 +909,20 @@ fn replace_kvars(ty: &TyS, kvars: &FxHashMap<KVid, Pred>) -> Ty {
             TyKind::Exists(replace_kvars_bty(bty, kvars), kvars[kvid].clone()).intern()
         }
         TyKind::Exists(bty, p) => TyKind::Exists(bty.clone(), p.clone()).intern(),
+        TyKind::Tuple(tys) => TyKind::tuple(tys.iter().map(|ty| replace_kvars(ty, kvars))),
+        TyKind::Struct(bty, fields) => TyKind::unfolded(
+            replace_kvars_bty(bty, kvars),
+            fields.iter().map(|ty| replace_kvars(ty, kvars)),
+        ),
         TyKind::Uninit => TyKind::Uninit.intern(),
         TyKind::StrgRef(loc) => TyKind::StrgRef(*loc).intern(),
         TyKind::Ref(ty) => TyKind::Ref(replace_kvars(ty, kvars)).intern(),
         TyKind::Param(param_ty) => TyKind::Param(*param_ty).intern(),
+        TyKind::Never => TyKind::Never.intern(),
+        TyKind::Fn(args, ret) => TyKind::fn_ty(
+            args.iter().map(|ty| replace_kvars(ty, kvars)),
+            replace_kvars(ret, kvars),
+        ),
     }
 }
 
@@ -420,7 +932,9 @@ fn replace_kvars_bty(bty: &BaseTy, kvars: &FxHashMap<KVid, Pred>) -> BaseTy {
             let substs = substs.iter().map(|ty| replace_kvars(ty, kvars));
             BaseTy::adt(*did, substs)
         }
-        BaseTy::Int(_) | BaseTy::Uint(_) | BaseTy::Bool => bty.clone(),
+        BaseTy::Array(ty) => BaseTy::Array(replace_kvars(ty, kvars)),
+        BaseTy::Slice(ty) => BaseTy::Slice(replace_kvars(ty, kvars)),
+        BaseTy::Int(_) | BaseTy::Uint(_) | BaseTy::Bool | BaseTy::Param(_) => bty.clone(),
     }
 }
 
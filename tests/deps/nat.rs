@@ -0,0 +1,18 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn<n: int>(i32@n) -> i32{v: v > n})]
+pub fn inc(x: i32) -> i32 {
+    x + 1
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32{v: v >= 0})]
+pub fn dec(x: i32) -> i32 {
+    x - 1
+}
+
+#[lr::ty(fn<n: int>(x: i32@n; ref<x>) -> i32; x: i32{v: v > n})]
+pub fn incr(x: &mut i32) -> i32 {
+    *x += 1;
+    0
+}
@@ -0,0 +1,25 @@
+pub fn inc(x: i32) -> i32 {
+    x + 1
+}
+
+pub fn read(x: &i32) -> i32 {
+    *x
+}
+
+pub struct Counter;
+
+impl Counter {
+    pub fn step(n: i32) -> i32 {
+        n + 1
+    }
+}
+
+pub mod math {
+    pub fn abs(x: i32) -> i32 {
+        if x < 0 {
+            -x
+        } else {
+            x
+        }
+    }
+}
@@ -0,0 +1,37 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+mod rvec;
+use rvec::RVec;
+
+#[lr::ty(fn<p: int -> bool>(T{v: p(v)}) -> T{v: p(v)})]
+pub fn id<T>(x: T) -> T {
+    x
+}
+
+#[lr::ty(fn<p: int -> bool>(i32{v: p(v)}) -> i32{v: p(v)})]
+pub fn inc(x: i32) -> i32 {
+    x + 1
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn test_id() -> i32 {
+    id(0)
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn<n: int{n > 0}>(RVec<i32{v: v > 0}>@n) -> i32{v: v > 0})]
+pub fn last(mut vec: RVec<i32>) -> i32 {
+    vec.pop()
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn test_last() -> i32 {
+    let mut vec = RVec::new();
+    vec.push(0);
+    last(vec) //~ ERROR precondition of `last` argument 1 might not hold
+}
+
+#[lr::ty(fn() -> bool)]
+pub fn test_id_bool() -> bool {
+    id(true) //~ ERROR cannot instantiate the refined type parameter `T` with `bool`
+}
@@ -0,0 +1,7 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn<p: int -> bool>(i32{v: v == p}) -> i32)] //~ ERROR abstract refinement `p` must be applied to an argument
+pub fn unapplied(x: i32) -> i32 {
+    x
+}
@@ -0,0 +1,33 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn([i32; 4], usize{v: v <= 4}) -> i32)]
+pub fn get(a: [i32; 4], i: usize) -> i32 {
+    a[i] //~ ERROR assigned value might not have the type of the location
+}
+
+#[lr::ty(fn<n: int>(s: [i32{v: v >= 0}]@n; ref<s>) -> i32; s: [i32{v: v >= 0}]@n)]
+pub fn dec(s: &mut [i32]) -> i32 {
+    let mut i = 0;
+    while i < s.len() {
+        s[i] -= 1; //~ ERROR assigned value might not have the type of the location
+        i += 1;
+    }
+    0
+}
+
+#[lr::ty(fn<n: int>(s: [i32]@n; ref<s>) -> i32; s: [i32]@n)]
+pub fn off_by_one(s: &mut [i32]) -> i32 {
+    let mut i = 0;
+    while i <= s.len() {
+        s[i] = 0; //~ ERROR assigned value might not have the type of the location
+        i += 1;
+    }
+    0
+}
+
+#[lr::ty(fn([(i32{v: v > 0}, i32); 2]) -> i32)]
+pub fn clear_first_fst(mut a: [(i32, i32); 2]) -> i32 {
+    a[0].0 = 0; //~ ERROR assigned value might not have the type of the location
+    a[1].1
+}
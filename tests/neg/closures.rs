@@ -0,0 +1,39 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(i32) -> i32{v: v > 1})]
+pub fn captured(k: i32) -> i32 {
+    let add = |x: i32| x + k;
+    add(1)
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn() -> i32{v: v > 1})]
+pub fn nested_calls() -> i32 {
+    let inc = |x: i32| x + 1;
+    inc(inc(0))
+}
+
+#[lr::ty(fn(fn(i32{v: v > 0}) -> i32{v: v > 0}, i32) -> i32{v: v > 0})]
+pub fn apply_ptr(f: fn(i32) -> i32, x: i32) -> i32 {
+    f(f(x)) //~ ERROR precondition argument 1 might not hold
+}
+
+#[lr::ty(fn(fn(i32{v: v > 0}) -> i32, i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn apply<F: Fn(i32) -> i32>(f: F, x: i32) -> i32 {
+    f(x)
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn(fn() -> i32) -> i32{v: v > 0})]
+pub fn call_once<F: FnOnce() -> i32>(f: F) -> i32 {
+    f()
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn(fn(i32{v: v > 0}) -> i32{v: v > 0}, i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn apply_pos<F: Fn(i32) -> i32>(f: F, x: i32) -> i32 {
+    f(x)
+}
+
+#[lr::ty(fn() -> i32)]
+pub fn apply_closure() -> i32 {
+    apply_pos(|x| x - 1, 3) //~ ERROR precondition of `apply_pos` argument 1 might not hold
+}
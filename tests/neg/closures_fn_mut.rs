@@ -0,0 +1,11 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(i32) -> i32)]
+pub fn count(n: i32) -> i32 {
+    let mut k = 0;
+    let mut inc = || k += 1; //~ ERROR closures mutating their captured variables are not supported
+    inc();
+    inc();
+    n + k
+}
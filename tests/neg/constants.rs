@@ -0,0 +1,7 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn neg_one() -> i32 {
+    -1
+} //~ ERROR postcondition might not hold
@@ -0,0 +1,12 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn() -> i32{v: v > 1})]
+pub fn one() -> i32 {
+    nat::inc(0)
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+pub fn dec_zero(x: i32) -> i32 {
+    nat::dec(x) //~ ERROR precondition of `nat::dec` argument 1 might not hold
+}
@@ -0,0 +1,26 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+fn double(x: i32) -> i32 {
+    x + x
+}
+
+fn reset(x: &mut i32) {
+    *x = 0;
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn quadruple(x: i32) -> i32 {
+    double(double(x))
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn() -> i32{v: v >= 0})]
+pub fn after_reset() -> i32 {
+    let mut x = 1;
+    reset(&mut x);
+    x
+} //~ ERROR postcondition might not hold
+
+pub fn first(xs: &[i32]) -> i32 {
+    xs[0] //~ ERROR assigned value might not have the type of the location
+}
@@ -0,0 +1,36 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+#![allow(dead_code)]
+
+#[lr::extern_spec(i32::abs)]
+#[lr::ty(fn<n: int>(i32@n) -> i32{v: v >= n && v >= 0 - n})]
+fn abs(x: i32) -> i32 {
+    i32::abs(x)
+}
+
+#[lr::ty(fn(i32) -> i32{v: v > 0})]
+pub fn abs_pos(x: i32) -> i32 {
+    x.abs()
+} //~ ERROR postcondition might not hold
+
+#[lr::extern_spec(std::cmp::max::<T>)]
+#[lr::ty(fn<a: int, b: int>(i32@a, i32@b) -> i32{v: v >= a && v >= b})]
+fn max(a: i32, b: i32) -> i32 {
+    std::cmp::max(a, b)
+}
+
+#[lr::ty(fn(i32) -> i32{v: v > 0})]
+pub fn max_pos(x: i32) -> i32 {
+    std::cmp::max(x, 0)
+} //~ ERROR postcondition might not hold
+
+#[lr::extern_spec(std::vec::Vec::<T>::len)]
+#[lr::ty(fn(&Vec<T>) -> usize{v: v <= 9223372036854775807})]
+fn len<T>(v: &Vec<T>) -> usize {
+    v.len()
+}
+
+#[lr::ty(fn(&Vec<i32>) -> usize{v: v < 9223372036854775807})]
+pub fn vec_len(v: &Vec<i32>) -> usize {
+    v.len()
+} //~ ERROR postcondition might not hold
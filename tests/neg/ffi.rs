@@ -0,0 +1,17 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+extern "C" {
+    #[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+    fn abs_succ(x: i32) -> i32;
+}
+
+#[lr::ty(fn(i32) -> i32{v: v >= 0})]
+pub fn call_abs_succ(x: i32) -> i32 {
+    unsafe { abs_succ(x) } //~ ERROR precondition of `abs_succ` argument 1 might not hold
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v > 0})]
+pub fn weaker_ret(x: i32) -> i32 {
+    unsafe { abs_succ(x) }
+} //~ ERROR postcondition might not hold
@@ -0,0 +1,11 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+pub fn count(n: i32) -> i32 { //~ ERROR failed to run the solver: executable `../tests/no-such-fixpoint` not found
+    let mut i = 0;
+    while i < n {
+        i += 1;
+    }
+    i
+}
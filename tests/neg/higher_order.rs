@@ -0,0 +1,44 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(fn(i32{v: v >= 0}) -> i32{v: v > 0}, i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn apply(f: fn(i32) -> i32, x: i32) -> i32 {
+    f(x)
+}
+
+#[lr::ty(fn(fn(i32{v: v > 0}) -> i32{v: v > 1}, i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn apply_stronger_arg(f: fn(i32) -> i32, x: i32) -> i32 {
+    apply(f, x) //~ ERROR precondition of `apply` argument 1 might not hold
+}
+
+#[lr::ty(fn(fn(i32) -> i32{v: v >= 0}, i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn apply_weaker_ret(f: fn(i32) -> i32, x: i32) -> i32 {
+    apply(f, x) //~ ERROR precondition of `apply` argument 1 might not hold
+}
+
+#[lr::ty(fn(fn(i64{v: v > 0}) -> bool@true) -> bool@true)]
+pub fn call_zero(f: fn(i64) -> bool) -> bool {
+    f(0) //~ ERROR precondition argument 1 might not hold
+}
+
+#[lr::ty(fn(fn(i32{v: v > 0}) -> i32{v: v > 0}, i32) -> i32{v: v > 0})]
+pub fn pair(f: fn(i32) -> i32, x: i32) -> i32 {
+    let p = (f, x);
+    (p.0)(p.1) //~ ERROR precondition argument 1 might not hold
+}
+
+#[lr::ty(fn(i32) -> i32)]
+pub fn dec(x: i32) -> i32 {
+    x - 1
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn apply_dec() -> i32 {
+    apply(dec, 3) //~ ERROR precondition of `apply` argument 1 might not hold
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn apply_closure_ptr() -> i32 {
+    let f: fn(i32) -> i32 = |x| x - 1;
+    apply(f, 3) //~ ERROR precondition of `apply` argument 1 might not hold
+}
@@ -0,0 +1,36 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+mod rvec;
+use rvec::RVec;
+
+#[lr::ty(fn<n: int>(i32@n) -> i32{_})]
+pub fn dec(n: i32) -> i32 {
+    n - 1
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn zero() -> i32 {
+    dec(1)
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn(i32{_}) -> i32{v: v > 0})]
+fn positive(x: i32) -> i32 {
+    x
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn call_positive() -> i32 {
+    positive(0)
+}
+
+#[lr::ty(fn() -> RVec<i32>@_)]
+fn empty() -> RVec<i32> {
+    RVec::new()
+}
+
+#[lr::ty(fn() -> i32)]
+pub fn first() -> i32 {
+    let mut vec = empty();
+    vec.pop() //~ ERROR precondition of `rvec::RVec::<T>::pop` might not hold
+}
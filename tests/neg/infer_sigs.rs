@@ -0,0 +1,24 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+fn inc(x: i32) -> i32 {
+    x + 1
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn positive(x: i32) -> i32 {
+    x
+}
+
+fn forward(x: i32) -> i32 {
+    positive(x) //~ ERROR precondition of `positive` argument 1 might not hold
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v > 1})]
+pub fn succ(n: i32) -> i32 {
+    inc(n)
+} //~ ERROR postcondition might not hold
+
+pub fn zero() -> i32 {
+    forward(0)
+}
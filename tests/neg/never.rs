@@ -0,0 +1,54 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::assume]
+#[lr::ty(fn() -> !)]
+pub fn fail() -> ! {
+    panic!()
+}
+
+#[lr::assume]
+#[lr::ty(fn(i32{v: v < 0}) -> !)]
+pub fn fail_neg(_x: i32) -> ! {
+    panic!()
+}
+
+#[lr::ty(fn(i32) -> i32)]
+pub fn check_panic(x: i32) -> i32 {
+    if x < 0 {
+        panic!(); //~ ERROR this may panic
+    }
+    x
+}
+
+#[lr::ty(fn(bool) -> i32)]
+pub fn check_unreachable(b: bool) -> i32 {
+    if !b {
+        unreachable!(); //~ ERROR this may panic
+    }
+    0
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32)]
+pub fn check_unreachable_unchecked(x: i32) -> i32 {
+    if x <= 0 {
+        unsafe { std::hint::unreachable_unchecked() } //~ ERROR this may panic
+    }
+    x
+}
+
+#[lr::ty(fn(i32) -> i32)]
+pub fn check_diverging_call(x: i32) -> i32 {
+    if x < 10 {
+        fail(); //~ ERROR this may panic
+    }
+    x
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32)]
+pub fn check_diverging_call_args(x: i32) -> i32 {
+    if x < 10 {
+        fail_neg(x); //~ ERROR precondition of `fail_neg` argument 1 might not hold
+    }
+    x
+}
@@ -0,0 +1,29 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(i32{v: v > 0}, i32{v: v > 0}) -> (i32{v: v > 0}, i32{v: v > 0}))]
+pub fn swap(x: i32, y: i32) -> (i32, i32) {
+    (y, x)
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32)]
+pub fn swap_fst(a: i32) -> i32 {
+    let p = swap(a - 1, a); //~ ERROR ^^^^^^^^^^^^^^ precondition of `swap` argument 1 might not hold
+    p.0
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32)]
+pub fn swap_snd(a: i32) -> i32 {
+    let p = swap(a, a - 1); //~ ERROR ^^^^^^^^^^^^^^ precondition of `swap` argument 2 might not hold
+    p.0
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn dec(x: i32) -> i32 {
+    x - 1
+} //~ ERROR ^ postcondition might not hold
+
+#[lr::ty(fn(i32{v: v >= 0 && v < 5}) -> i32{v: v > 0})]
+pub fn positive(x: i32) -> i32 {
+    x
+} //~ ERROR fails when `x = 0`
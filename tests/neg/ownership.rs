@@ -0,0 +1,22 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+pub struct S {
+    x: i32,
+}
+
+pub fn unbox(b: Box<S>) -> S {
+    *b //~ ERROR cannot move out of a place behind a reference
+}
+
+#[lr::ty(fn(&i32) -> i32)]
+pub fn write_shr(x: &mut i32) -> i32 {
+    *x = 1; //~ ERROR cannot mutate a place behind a shared reference
+    0
+}
+
+#[lr::ty(fn(&i32) -> i32)]
+pub fn borrow_shr(x: &mut i32) -> i32 {
+    let y = &mut *x; //~ ERROR cannot mutate a place behind a shared reference
+    *y
+}
@@ -0,0 +1,54 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn<n: int>(i32@{n + 1}) -> i32@n)]
+pub fn prev(x: i32) -> i32 {
+    x - 1
+}
+
+#[lr::ty(fn() -> i32@5)]
+pub fn test_prev() -> i32 {
+    prev(5)
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn<n: int>(i32@n, i32@n) -> i32@n)]
+pub fn same(x: i32, _y: i32) -> i32 {
+    x
+}
+
+#[lr::ty(fn() -> i32)]
+pub fn test_same() -> i32 {
+    same(3, 4) //~ ERROR precondition of `same` argument 2 might not hold
+}
+
+#[lr::assume]
+#[lr::ty(fn<n: int>() -> i32{v: v >= n})]
+pub fn unbounded() -> i32 {
+    i32::MAX
+}
+
+#[lr::ty(fn() -> i32{v: v >= 1})]
+pub fn test_unbounded() -> i32 {
+    unbounded()
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn<n: int>(i32{v: v >= n}) -> i32{v: v >= n})]
+pub fn at_least(x: i32) -> i32 {
+    x
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 1})]
+pub fn test_at_least(x: i32) -> i32 {
+    at_least(x)
+} //~ ERROR postcondition might not hold
+
+#[lr::assume]
+#[lr::ty(fn<n: int{n > 0}>() -> i32@n)]
+pub fn positive() -> i32 {
+    1
+}
+
+#[lr::ty(fn() -> i32{v: v > 1})]
+pub fn test_positive() -> i32 {
+    positive()
+} //~ ERROR postcondition might not hold
@@ -0,0 +1,22 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn() -> i32{v: v > 2})]
+pub fn two() -> i32 {
+    plain::inc(plain::inc(0))
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn<n: int>(i32@n) -> i32{v: v > n})]
+pub fn read_local(x: i32) -> i32 {
+    plain::read(&x)
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn() -> i32@3)]
+pub fn steps() -> i32 {
+    plain::Counter::step(plain::Counter::step(0))
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn(i32) -> i32{v: v > 0})]
+pub fn abs(x: i32) -> i32 {
+    plain::math::abs(x)
+} //~ ERROR postcondition might not hold
@@ -0,0 +1,29 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+pub struct Pair<T> {
+    pub fst: T,
+    pub snd: T,
+}
+
+#[lr::ty(fn(Pair<i32{v: v > 0}>) -> Pair<i32{v: v > 0}>)]
+pub fn clobber(mut p: Pair<i32>) -> Pair<i32> {
+    p.fst = 0;
+    p
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn(Pair<i32{v: v > 0}>) -> Pair<i32{v: v > 0}>)]
+pub fn clobber_through_ref(mut p: Pair<i32>) -> Pair<i32> {
+    let r = &mut p.snd;
+    *r = 0; //~ ERROR assigned value might not have the type of the location
+    p
+}
+
+#[lr::ty(fn<n: int>(s: [i32{v: v >= 0}]@n; ref<s>) -> i32; s: [i32{v: v >= 0}]@n)]
+pub fn dec_first(s: &mut [i32]) -> i32 {
+    if s.len() > 0 {
+        let r = &mut s[0];
+        *r -= 1; //~ ERROR assigned value might not have the type of the location
+    }
+    0
+}
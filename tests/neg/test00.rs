@@ -4,5 +4,5 @@
 #[lr::ty(fn<n: int>(i32@n) -> i32{v: v < n})]
 pub fn inc(x: i32) -> i32 {
     x + 1
-}
+} //~ ERROR postcondition might not hold
 
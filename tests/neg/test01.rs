@@ -13,4 +13,4 @@ pub fn ref_join(b: bool, n: i32, m: i32) -> i32 {
     }
     *r += 1;
     *r - n
-}
+} //~ ERROR postcondition might not hold
@@ -9,6 +9,6 @@ pub fn vec_push() -> usize {
     let mut v = RVec::new();
     v.push(1);
     v.push(2);
-    let r = v.get_mut(2);
+    let r = v.get_mut(2); //~ ERROR precondition of `rvec::RVec::<T>::get_mut` argument 2 might not hold
     *r
 }
@@ -5,4 +5,4 @@
 pub fn inc(x: &mut i32) -> i32 {
     *x += 1;
     0
-}
+} //~ ERROR postcondition might not hold
@@ -0,0 +1,38 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+pub trait Step {
+    #[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v > 0})]
+    fn step(x: i32) -> i32;
+}
+
+pub struct Id;
+
+impl Step for Id {
+    fn step(x: i32) -> i32 {
+        x
+    } //~ ERROR postcondition might not hold
+}
+
+pub struct StrongerArg;
+
+impl Step for StrongerArg {
+    #[lr::ty(fn(i32{v: v > 0}) -> i32{v: v > 0})]
+    fn step(x: i32) -> i32 { //~ ERROR this spec is incompatible with the trait
+        x
+    }
+}
+
+pub struct WeakerRet;
+
+impl Step for WeakerRet {
+    #[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+    fn step(x: i32) -> i32 { //~ ERROR this spec is incompatible with the trait
+        x
+    }
+}
+
+#[lr::ty(fn(i32) -> i32{v: v > 0})]
+pub fn run<S: Step>(x: i32) -> i32 {
+    S::step(x) //~ ERROR precondition of `Step::step` argument 1 might not hold
+}
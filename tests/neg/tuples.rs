@@ -0,0 +1,25 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+pub struct Pair {
+    fst: i32,
+    snd: i32,
+}
+
+#[lr::ty(fn<n: int>(i32@n) -> (i32@n, i32{v: v > n + 1}))]
+pub fn dup_inc(x: i32) -> (i32, i32) {
+    (x, x + 1)
+} //~ ERROR postcondition might not hold
+
+#[lr::ty(fn<n: int>(i32@n) -> i32{v: v > n})]
+pub fn snd(x: i32) -> i32 {
+    let p = dup_inc(x);
+    p.1
+}
+
+#[lr::ty(fn<n: int>(i32@n) -> i32{v: v > n + 1})]
+pub fn pair(x: i32) -> i32 {
+    let mut p = Pair { fst: x, snd: x };
+    p.snd += 1;
+    p.snd - p.fst + x
+} //~ ERROR postcondition might not hold
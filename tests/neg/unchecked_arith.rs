@@ -0,0 +1,8 @@
+// compile-flags: -C overflow-checks=off
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn<n: int>(s: [i32]@n; ref<s>, usize{v: v < n}) -> i32; s: [i32]@n)]
+pub fn prev(s: &mut [i32], i: usize) -> i32 {
+    s[i - 1] //~ ERROR assigned value might not have the type of the location
+}
@@ -0,0 +1,12 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(bool, bool) -> bool)]
+pub fn lt(a: bool, b: bool) -> bool {
+    a < b //~ ERROR unsupported operand types for `<`: `bool@a`, `bool@b`
+}
+
+#[lr::ty(fn(u32) -> u32)]
+pub fn flip(x: u32) -> u32 {
+    !x //~ ERROR unsupported operand types for `!`: `u32@x`
+}
@@ -0,0 +1,12 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(i32) -> i32)]
+pub fn parity(x: i32) -> i32 { //~ ERROR unsupported binary operation
+    x % 2
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn dec(x: i32) -> i32 {
+    x - 1
+} //~ ERROR postcondition might not hold
@@ -0,0 +1,18 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(Box<i32>) -> i32)]
+pub fn unbox(b: Box<i32>) -> i32 {
+    *b //~ ERROR unsupported operand types for `*`: `Box<
+}
+
+#[lr::ty(fn(i32, &i32, &i32) -> i32)]
+pub fn join_refs(n: i32, x: &i32, y: &i32) -> i32 {
+    let mut t = (x, 0);
+    let mut i = 0;
+    while i < n { //~ ERROR incompatible types
+        t.0 = y;
+        i += 1;
+    }
+    *t.0
+}
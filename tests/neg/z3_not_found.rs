@@ -0,0 +1,7 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v > 0})]
+pub fn inc(x: i32) -> i32 { //~ ERROR failed to run the solver: executable `../tests/no-such-z3` not found
+    x + 1
+}
@@ -0,0 +1,38 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+mod rvec;
+use rvec::RVec;
+
+#[lr::ty(fn<p: int -> bool>(T{v: p(v)}) -> T{v: p(v)})]
+pub fn id<T>(x: T) -> T {
+    x
+}
+
+#[lr::ty(fn<p: int -> bool>(i32{v: p(v)}, i32{v: p(v)}) -> i32{v: p(v)})]
+pub fn max(x: i32, y: i32) -> i32 {
+    if x > y { x } else { y }
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn test_id() -> i32 {
+    id(5)
+}
+
+#[lr::ty(fn() -> i32{v: v >= 10})]
+pub fn test_max() -> i32 {
+    max(10, 20)
+}
+
+#[lr::ty(fn<n: int{n > 0}>(RVec<i32{v: v > 0}>@n) -> i32{v: v > 0})]
+pub fn last(mut vec: RVec<i32>) -> i32 {
+    vec.pop()
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn test_last() -> i32 {
+    let mut vec = RVec::new();
+    vec.push(1);
+    vec.push(2);
+    last(vec)
+}
@@ -0,0 +1,60 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn([i32; 4], usize{v: v < 4}) -> i32)]
+pub fn get(a: [i32; 4], i: usize) -> i32 {
+    a[i]
+}
+
+#[lr::ty(fn() -> [i32{v: v >= 0}; 3])]
+pub fn zeros() -> [i32; 3] {
+    [0; 3]
+}
+
+#[lr::ty(fn<n: int>(i32{v: v > n}, i32{v: v > n}) -> [i32{v: v > n}; 2])]
+pub fn pair(x: i32, y: i32) -> [i32; 2] {
+    [x, y]
+}
+
+#[lr::ty(fn<n: int>(s: [i32]@n; ref<s>) -> usize@n; s: [i32]@n)]
+pub fn len(s: &mut [i32]) -> usize {
+    s.len()
+}
+
+#[lr::ty(fn<n: int>(s: [i32{v: v >= 0}]@n; ref<s>) -> i32{v: v >= 0}; s: [i32{v: v >= 0}]@n)]
+pub fn sum(s: &mut [i32]) -> i32 {
+    let mut i = 0;
+    let mut sum = 0;
+    while i < s.len() {
+        sum += s[i];
+        s[i] = 0;
+        i += 1;
+    }
+    sum
+}
+
+#[lr::ty(fn([i32{v: v > 0}; 4]) -> i32{v: v > 0})]
+pub fn first(a: [i32; 4]) -> i32 {
+    let [x, ..] = a;
+    x
+}
+
+#[lr::ty(fn<n: int>(s: [i32{v: v >= 0}]@n; ref<s>) -> i32; s: [i32{v: v >= 0}]@n)]
+pub fn reset_first(s: &mut [i32]) -> i32 {
+    if s.len() > 0 {
+        let r = &mut s[0];
+        *r = 0;
+    }
+    0
+}
+
+#[lr::ty(fn([(i32{v: v > 0}, i32); 2]) -> i32{v: v > 0})]
+pub fn first_fst(a: [(i32, i32); 2]) -> i32 {
+    a[0].0
+}
+
+#[lr::ty(fn([(i32{v: v > 0}, i32); 2]) -> i32{v: v > 0})]
+pub fn set_first_fst(mut a: [(i32, i32); 2]) -> i32 {
+    a[0].0 = 1;
+    a[1].0
+}
@@ -0,0 +1,20 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn() -> i32{v: v < 0})]
+pub fn neg_one() -> i32 {
+    -1
+}
+
+#[lr::ty(fn(i32) -> i32{v: v > 0})]
+pub fn switch_neg(x: i32) -> i32 {
+    match x {
+        -1 => -x,
+        _ => 1,
+    }
+}
+
+#[lr::ty(fn() -> i64{v: v + 4294967296 < 0})]
+pub fn min_i64() -> i64 {
+    i64::MIN
+}
@@ -0,0 +1,19 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn() -> i32{v: v > 1})]
+pub fn two() -> i32 {
+    nat::inc(nat::inc(0))
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+pub fn round_trip(x: i32) -> i32 {
+    nat::dec(nat::inc(x))
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn incr_local() -> i32 {
+    let mut x = 0;
+    nat::incr(&mut x);
+    x
+}
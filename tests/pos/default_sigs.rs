@@ -0,0 +1,52 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+fn double(x: i32) -> i32 {
+    x + x
+}
+
+fn reset(x: &mut i32) {
+    *x = 0;
+}
+
+#[lr::ty(fn(i32) -> i32)]
+pub fn quadruple(x: i32) -> i32 {
+    double(double(x))
+}
+
+#[lr::ty(fn() -> i32{v: v >= 0})]
+pub fn after_reset() -> i32 {
+    let mut x = 1;
+    reset(&mut x);
+    if x < 0 {
+        0
+    } else {
+        x
+    }
+}
+
+#[lr::ty(fn(i32, i32) -> i32)]
+pub fn larger(a: i32, b: i32) -> i32 {
+    std::cmp::max(a, b)
+}
+
+pub fn first(xs: &[i32]) -> i32 {
+    if xs.len() > 0 {
+        xs[0]
+    } else {
+        0
+    }
+}
+
+fn parity(x: i32) -> i32 {
+    x % 2
+}
+
+fn name_len(name: &str) -> usize {
+    name.len()
+}
+
+#[lr::ty(fn(i32) -> i32)]
+pub fn parity_plus_one(x: i32) -> i32 {
+    parity(x) + 1
+}
@@ -0,0 +1,47 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+#![allow(dead_code)]
+
+#[lr::extern_spec(i32::abs)]
+#[lr::ty(fn<n: int>(i32@n) -> i32{v: v >= n && v >= 0 - n})]
+fn abs(x: i32) -> i32 {
+    i32::abs(x)
+}
+
+#[lr::extern_spec(i64::signum)]
+#[lr::ty(fn(i64) -> i64{v: v >= 0 - 1 && v <= 1})]
+fn signum(x: i64) -> i64 {
+    x.signum()
+}
+
+#[lr::ty(fn(i32) -> i32{v: v >= 0})]
+pub fn abs_nonneg(x: i32) -> i32 {
+    x.abs()
+}
+
+#[lr::ty(fn(i64) -> i64{v: v < 2})]
+pub fn signum_small(x: i64) -> i64 {
+    i64::signum(x)
+}
+
+#[lr::extern_spec(std::cmp::max::<T>)]
+#[lr::ty(fn<a: int, b: int>(i32@a, i32@b) -> i32{v: v >= a && v >= b})]
+fn max(a: i32, b: i32) -> i32 {
+    std::cmp::max(a, b)
+}
+
+#[lr::ty(fn(i32) -> i32{v: v >= 0})]
+pub fn max_nonneg(x: i32) -> i32 {
+    std::cmp::max(x, 0)
+}
+
+#[lr::extern_spec(std::vec::Vec::<T>::len)]
+#[lr::ty(fn(&Vec<T>) -> usize{v: v <= 9223372036854775807})]
+fn len<T>(v: &Vec<T>) -> usize {
+    v.len()
+}
+
+#[lr::ty(fn(&Vec<i32>) -> usize{v: v < 9223372036854775808})]
+pub fn vec_len(v: &Vec<i32>) -> usize {
+    v.len()
+}
@@ -0,0 +1,20 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+extern "C" {
+    #[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+    fn abs_succ(x: i32) -> i32;
+
+    #[lr::ty(fn<n: int>(i64@n) -> i64{v: v > n})]
+    fn next(x: i64) -> i64;
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+pub fn call_abs_succ(x: i32) -> i32 {
+    unsafe { abs_succ(x) }
+}
+
+#[lr::ty(fn() -> i64{v: v > 1})]
+pub fn call_next() -> i64 {
+    unsafe { next(next(0)) }
+}
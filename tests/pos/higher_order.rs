@@ -0,0 +1,39 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(fn(i32{v: v >= 0}) -> i32{v: v > 0}, i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn apply(f: fn(i32) -> i32, x: i32) -> i32 {
+    f(x)
+}
+
+#[lr::ty(fn(fn(i32) -> i32{v: v > 1}, i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn apply_weaker(f: fn(i32) -> i32, x: i32) -> i32 {
+    apply(f, x)
+}
+
+#[lr::ty(fn(fn(i64{v: v > 0}) -> bool@true) -> bool@true)]
+pub fn call_one(f: fn(i64) -> bool) -> bool {
+    f(1)
+}
+
+#[lr::ty(fn(fn(i32{v: v > 0}) -> i32{v: v > 0}, i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn pair(f: fn(i32) -> i32, x: i32) -> i32 {
+    let p = (f, x);
+    (p.0)(p.1)
+}
+
+#[lr::ty(fn<n: int>(i32@n) -> i32{v: v > n})]
+pub fn inc(x: i32) -> i32 {
+    x + 1
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn apply_inc() -> i32 {
+    apply(inc, 3)
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn apply_closure_ptr() -> i32 {
+    let f: fn(i32) -> i32 = |x| x + 1;
+    apply(f, 3)
+}
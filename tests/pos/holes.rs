@@ -0,0 +1,38 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+mod rvec;
+use rvec::RVec;
+
+#[lr::ty(fn<n: int>(i32@n) -> i32{_})]
+pub fn inc(n: i32) -> i32 {
+    n + 1
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn one() -> i32 {
+    inc(0)
+}
+
+#[lr::ty(fn(i32{_}) -> i32{v: v > 0})]
+fn positive(x: i32) -> i32 {
+    x
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn five() -> i32 {
+    positive(5)
+}
+
+#[lr::ty(fn() -> RVec<i32>@_)]
+fn singleton() -> RVec<i32> {
+    let mut vec = RVec::new();
+    vec.push(0);
+    vec
+}
+
+#[lr::ty(fn() -> i32)]
+pub fn first() -> i32 {
+    let mut vec = singleton();
+    vec.pop()
+}
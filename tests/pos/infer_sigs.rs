@@ -0,0 +1,43 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+fn inc(x: i32) -> i32 {
+    x + 1
+}
+
+fn abs(x: i32) -> i32 {
+    if x < 0 {
+        0 - x
+    } else {
+        x
+    }
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32{v: v > 0})]
+pub fn positive(x: i32) -> i32 {
+    x
+}
+
+fn forward(x: i32) -> i32 {
+    positive(x)
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v > 0})]
+pub fn succ(n: i32) -> i32 {
+    inc(n)
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32{v: v > 1})]
+pub fn succ_succ(n: i32) -> i32 {
+    inc(inc(n))
+}
+
+#[lr::ty(fn(i32) -> i32{v: v >= 0})]
+pub fn magnitude(n: i32) -> i32 {
+    abs(n)
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn five() -> i32 {
+    forward(5)
+}
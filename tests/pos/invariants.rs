@@ -0,0 +1,13 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+pub fn sum(n: i32) -> i32 {
+    let mut i = 0;
+    let mut s = 0;
+    while i < n { //~ NOTE inferred invariant
+        i += 1;
+        s += i;
+    }
+    s
+}
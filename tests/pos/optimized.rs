@@ -0,0 +1,27 @@
+// compile-flags: -O -Cpanic=abort
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+pub fn sum(n: i32) -> i32 {
+    let mut i = 0;
+    let mut r = 0;
+    while i < n {
+        i += 1;
+        r += i;
+    }
+    r
+}
+
+#[lr::ty(fn<n: int>(s: [i32]@n; ref<s>, usize{v: v < n}) -> i32; s: [i32]@n)]
+pub fn zero(s: &mut [i32], i: usize) -> i32 {
+    let old = s[i];
+    s[i] = 0;
+    old
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32{v: v >= 0})]
+pub fn pred(x: i32) -> i32 {
+    assert!(x > 0);
+    x - 1
+}
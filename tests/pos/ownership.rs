@@ -0,0 +1,29 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::assume]
+#[lr::ty(fn<n: int>(&i32@n) -> &i32@n)]
+pub fn id(x: &i32) -> &i32 {
+    x
+}
+
+#[lr::ty(fn(&i32{v: v > 0}) -> i32{v: v > 1})]
+pub fn read_through_ref(x: &i32) -> i32 {
+    let r = id(x);
+    *r + 1
+}
+
+#[lr::ty(fn<n: int>(x: i32@n; ref<x>) -> i32; x: i32@{n + 1})]
+pub fn incr(x: &mut i32) -> i32 {
+    *x += 1;
+    0
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn move_then_reassign() -> i32 {
+    let mut x = 1;
+    let y = x;
+    x = y + 1;
+    incr(&mut x);
+    x
+}
@@ -0,0 +1,50 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn(u32, u32{v: v > 0}) -> u32)]
+pub fn div(x: u32, y: u32) -> u32 {
+    x / y
+}
+
+#[lr::ty(fn(i32{v: v > 0}) -> i32)]
+pub fn check(x: i32) -> i32 {
+    assert!(x > 0);
+    x
+}
+
+#[lr::ty(fn<n: int>(s: [i32]@n; ref<s>, bool@false) -> i32; s: [i32]@n)]
+pub fn dead_slice(s: &mut [i32], b: bool) -> i32 {
+    if b {
+        s[1..][0] = 0;
+    }
+    0
+}
+
+#[lr::ty(fn(i32{v: v >= 0 && v < 100}) -> i32{v: v > 0})]
+pub fn inc(x: i32) -> i32 {
+    x + 1
+}
+
+#[lr::ty(fn(u8{v: v > 0}) -> u8)]
+pub fn dec(x: u8) -> u8 {
+    x - 1
+}
+
+pub struct Four {
+    items: [i32; 4],
+}
+
+impl std::ops::Index<usize> for Four {
+    type Output = i32;
+
+    #[lr::assume]
+    #[lr::ty(fn(&Four, usize{v: v < 4}) -> &i32)]
+    fn index(&self, i: usize) -> &i32 {
+        &self.items[i]
+    }
+}
+
+#[lr::ty(fn(&Four, usize{v: v < 3}) -> i32)]
+pub fn get(four: &Four, i: usize) -> i32 {
+    four[i + 1]
+}
@@ -0,0 +1,69 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+mod rvec;
+use rvec::RVec;
+
+#[lr::ty(fn<n: int>(i32@{n + 1}) -> i32@n)]
+pub fn prev(x: i32) -> i32 {
+    x - 1
+}
+
+#[lr::ty(fn() -> i32@4)]
+pub fn test_prev() -> i32 {
+    prev(5)
+}
+
+#[lr::ty(fn<n: int>(i32@n, i32@n) -> i32@n)]
+pub fn same(x: i32, _y: i32) -> i32 {
+    x
+}
+
+#[lr::ty(fn<a: int>(i32@a) -> i32@a)]
+pub fn test_same(a: i32) -> i32 {
+    same(a, a)
+}
+
+#[lr::ty(fn<n: int>(RVec<i32>@{n - 1}) -> usize@n)]
+pub fn len_plus_one(mut vec: RVec<i32>) -> usize {
+    vec.len() + 1
+}
+
+#[lr::ty(fn() -> usize@2)]
+pub fn test_len_plus_one() -> usize {
+    let mut vec = RVec::new();
+    vec.push(0);
+    len_plus_one(vec)
+}
+
+#[lr::assume]
+#[lr::ty(fn<n: int>() -> i32{v: v >= n})]
+pub fn unbounded() -> i32 {
+    i32::MAX
+}
+
+#[lr::ty(fn() -> i32{v: v >= 0})]
+pub fn test_unbounded() -> i32 {
+    unbounded()
+}
+
+#[lr::ty(fn<n: int>(i32{v: v >= n}) -> i32{v: v >= n})]
+pub fn at_least(x: i32) -> i32 {
+    x
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v >= 0})]
+pub fn test_at_least(x: i32) -> i32 {
+    at_least(x)
+}
+
+#[lr::assume]
+#[lr::ty(fn<n: int{n > 0}>() -> i32@n)]
+pub fn positive() -> i32 {
+    1
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn test_positive() -> i32 {
+    positive()
+}
@@ -0,0 +1,22 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+#[lr::ty(fn() -> i32{v: v > 1})]
+pub fn two() -> i32 {
+    plain::inc(plain::inc(0))
+}
+
+#[lr::ty(fn<n: int>(i32@n) -> i32@n)]
+pub fn read_local(x: i32) -> i32 {
+    plain::read(&x)
+}
+
+#[lr::ty(fn() -> i32@2)]
+pub fn steps() -> i32 {
+    plain::Counter::step(plain::Counter::step(0))
+}
+
+#[lr::ty(fn(i32) -> i32{v: v >= 0})]
+pub fn abs(x: i32) -> i32 {
+    plain::math::abs(x)
+}
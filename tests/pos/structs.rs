@@ -0,0 +1,46 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+pub struct Pair<T> {
+    pub fst: T,
+    pub snd: T,
+}
+
+#[lr::ty(fn(Pair<i32{v: v > 0}>) -> i32{v: v > 0})]
+pub fn fst(p: Pair<i32>) -> i32 {
+    p.fst
+}
+
+#[lr::ty(fn() -> Pair<i32{v: v > 0}>)]
+pub fn make() -> Pair<i32> {
+    Pair { fst: 1, snd: 2 }
+}
+
+#[lr::ty(fn(bool, Pair<i32{v: v > 0}>) -> i32{v: v > 0})]
+pub fn join(b: bool, mut p: Pair<i32>) -> i32 {
+    if b {
+        p.fst = 3;
+    }
+    p.fst
+}
+
+#[lr::ty(fn(Pair<i32{v: v > 0}>) -> i32{v: v > 1})]
+pub fn shared_field_ref(p: Pair<i32>) -> i32 {
+    let r = &p.fst;
+    *r + 1
+}
+
+#[lr::ty(fn(Pair<i32{v: v > 0}>) -> Pair<i32{v: v > 0}>)]
+pub fn mut_field_ref(mut p: Pair<i32>) -> Pair<i32> {
+    let r = &mut p.fst;
+    *r += 1;
+    p
+}
+
+#[lr::ty(fn(i32) -> i32{v: v > 0})]
+pub fn tuple_field_ref(x: i32) -> i32 {
+    let mut t = (x, 1);
+    let r = &mut t.0;
+    *r = 0;
+    t.1
+}
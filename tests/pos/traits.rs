@@ -0,0 +1,46 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+pub trait Step {
+    #[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v > 0})]
+    fn step(x: i32) -> i32;
+}
+
+pub struct Inc;
+
+impl Step for Inc {
+    fn step(x: i32) -> i32 {
+        x + 1
+    }
+}
+
+pub struct IncTwice;
+
+impl Step for IncTwice {
+    #[lr::ty(fn(i32{v: v + 2 > 0}) -> i32{v: v > 1})]
+    fn step(x: i32) -> i32 {
+        x + 3
+    }
+}
+
+pub trait Pick {
+    #[lr::ty(fn(Self, Self) -> Self)]
+    fn pick(x: Self, y: Self) -> Self;
+}
+
+impl Pick for i32 {
+    #[lr::ty(fn(i32, i32) -> i32)]
+    fn pick(x: i32, _y: i32) -> i32 {
+        x
+    }
+}
+
+#[lr::ty(fn(i32{v: v >= 0}) -> i32{v: v > 0})]
+pub fn run<S: Step>(x: i32) -> i32 {
+    S::step(x)
+}
+
+#[lr::ty(fn() -> i32{v: v > 0})]
+pub fn run_inc() -> i32 {
+    Inc::step(0) + IncTwice::step(1)
+}
@@ -0,0 +1,32 @@
+#![feature(register_tool)]
+#![register_tool(lr)]
+
+pub struct Pair {
+    fst: i32,
+    snd: i32,
+}
+
+#[lr::ty(fn<n: int>(i32@n) -> (i32@n, i32{v: v > n}))]
+pub fn dup_inc(x: i32) -> (i32, i32) {
+    (x, x + 1)
+}
+
+#[lr::ty(fn<n: int>(i32@n) -> i32{v: v > n})]
+pub fn snd(x: i32) -> i32 {
+    let p = dup_inc(x);
+    p.1
+}
+
+#[lr::ty(fn<n: int>(i32@n) -> i32{v: v > n})]
+pub fn pair(x: i32) -> i32 {
+    let mut p = Pair { fst: x, snd: x };
+    p.snd += 1;
+    p.snd - p.fst + x
+}
+
+#[lr::ty(fn<n: int>(&i32{v: v > 0}, &i32@n) -> i32{v: v == n})]
+pub fn replace_ref(x: &i32, y: &i32) -> i32 {
+    let mut p = (x, 0);
+    p.0 = y;
+    *p.0
+}